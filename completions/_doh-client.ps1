@@ -21,30 +21,49 @@ Register-ArgumentCompleter -Native -CommandName 'doh-client' -ScriptBlock {
 
     $completions = @(switch ($command) {
         'doh-client' {
-            [CompletionResult]::new('-l', '-l', [CompletionResultType]::ParameterName, 'Listen address [default: 127.0.0.1:53]')
-            [CompletionResult]::new('--listen-addr', '--listen-addr', [CompletionResultType]::ParameterName, 'Listen address [default: 127.0.0.1:53]')
-            [CompletionResult]::new('-r', '-r', [CompletionResultType]::ParameterName, 'Remote address/domain to the DOH server (see below)')
-            [CompletionResult]::new('--remote-host', '--remote-host', [CompletionResultType]::ParameterName, 'Remote address/domain to the DOH server (see below)')
+            [CompletionResult]::new('-l', '-l', [CompletionResultType]::ParameterName, 'Listen address, can be given multiple times to listen on several addresses [default: 127.0.0.1:53]')
+            [CompletionResult]::new('--listen-addr', '--listen-addr', [CompletionResultType]::ParameterName, 'Listen address, can be given multiple times to listen on several addresses [default: 127.0.0.1:53]')
+            [CompletionResult]::new('-r', '-r', [CompletionResultType]::ParameterName, 'Remote address/domain to the DOH server (see below) or a DNS stamp (sdns://), which also sets --domain, --path and --transport and pins the certificate hashes of the stamp')
+            [CompletionResult]::new('--remote-host', '--remote-host', [CompletionResultType]::ParameterName, 'Remote address/domain to the DOH server (see below) or a DNS stamp (sdns://), which also sets --domain, --path and --transport and pins the certificate hashes of the stamp')
             [CompletionResult]::new('-d', '-d', [CompletionResultType]::ParameterName, 'The domain name of the remote server')
             [CompletionResult]::new('--domain', '--domain', [CompletionResultType]::ParameterName, 'The domain name of the remote server')
+            [CompletionResult]::new('-u', '-u', [CompletionResultType]::ParameterName, 'The URI template of the DoH server (RFC 8484), which replaces --remote-host, --domain and --path, e.g. https://dns.example:8443/dns-query{?dns} or https://[2606:4700:4700::1111]/dns-query, without {?dns} the queries are always sent with POST')
+            [CompletionResult]::new('--upstream', '--upstream', [CompletionResultType]::ParameterName, 'The URI template of the DoH server (RFC 8484), which replaces --remote-host, --domain and --path, e.g. https://dns.example:8443/dns-query{?dns} or https://[2606:4700:4700::1111]/dns-query, without {?dns} the queries are always sent with POST')
+            [CompletionResult]::new('--bootstrap-dns', '--bootstrap-dns', [CompletionResultType]::ParameterName, 'DNS server, which resolves the domain names of the remote hosts and the proxies over UDP or TCP instead of the system resolver, can be given multiple times [default port: 53]')
             [CompletionResult]::new('--retries', '--retries', [CompletionResultType]::ParameterName, 'The number of retries to connect to the remote server')
-            [CompletionResult]::new('-t', '-t', [CompletionResultType]::ParameterName, 'The time in seconds after that the connection would be closed if no response is received from the server')
-            [CompletionResult]::new('--timeout', '--timeout', [CompletionResultType]::ParameterName, 'The time in seconds after that the connection would be closed if no response is received from the server')
+            [CompletionResult]::new('--failback', '--failback', [CompletionResultType]::ParameterName, 'The time in seconds after that a failed remote host is preferred again, e.g. the first remote host after a failover')
+            [CompletionResult]::new('--strategy', '--strategy', [CompletionResultType]::ParameterName, 'The strategy to select the remote host for a query')
+            [CompletionResult]::new('--weight', '--weight', [CompletionResultType]::ParameterName, 'The weight of the remote host for the weighted strategy')
+            [CompletionResult]::new('--max-connections', '--max-connections', [CompletionResultType]::ParameterName, 'The maximum number of HTTP/1.1 or HTTP/2 connections to the remote host, another connection is opened if all HTTP/1.1 connections are busy or all HTTP/2 connections reached the maximum number of concurrent streams of the server and idle connections are closed again')
+            [CompletionResult]::new('--keepalive', '--keepalive', [CompletionResultType]::ParameterName, 'The interval in seconds of the PINGs on idle HTTP/2 connections, if the server does not answer a PING, then the remote host is connected again (0 disables the PINGs)')
+            [CompletionResult]::new('--idle-timeout', '--idle-timeout', [CompletionResultType]::ParameterName, 'The time in seconds after that idle HTTP/1.1 and HTTP/2 connections are closed (0 keeps them open)')
+            [CompletionResult]::new('-t', '-t', [CompletionResultType]::ParameterName, 'The time in seconds after that a query fails, if no response is received. The remote hosts, which are tried in turn, share this time: each one gets an equal part of the remaining time, so a query takes at most this long in total')
+            [CompletionResult]::new('--timeout', '--timeout', [CompletionResultType]::ParameterName, 'The time in seconds after that a query fails, if no response is received. The remote hosts, which are tried in turn, share this time: each one gets an equal part of the remaining time, so a query takes at most this long in total')
+            [CompletionResult]::new('--hedge-delay', '--hedge-delay', [CompletionResultType]::ParameterName, 'Send the query to the next remote host too, if the remote host has not answered within this delay, e.g. 50 or p90')
+            [CompletionResult]::new('--transport', '--transport', [CompletionResultType]::ParameterName, 'The protocol to connect to the remote host: h2 falls back to HTTP/1.1 if the server does not support it, h3 needs the http3 feature, dot is DNS over TLS (RFC 7858) and doq is DNS over QUIC (RFC 9250, needs the doq feature), both without HTTP, so --path and --get are ignored')
             [CompletionResult]::new('-p', '-p', [CompletionResultType]::ParameterName, 'The path of the URI')
             [CompletionResult]::new('--path', '--path', [CompletionResultType]::ParameterName, 'The path of the URI')
+            [CompletionResult]::new('--tls-early-data', '--tls-early-data', [CompletionResultType]::ParameterName, 'Send the first request as TLS 1.3 early data (0-RTT), if the TLS session to the remote host is resumed. Over HTTPS only, if the resumed session used HTTP/2. Use --tls-early-data=false to disable it for a remote host')
             [CompletionResult]::new('-c', '-c', [CompletionResultType]::ParameterName, 'The size of the private HTTP cache If the size is 0 then the private HTTP cache is not used (ignores cache-control)')
             [CompletionResult]::new('--cache-size', '--cache-size', [CompletionResultType]::ParameterName, 'The size of the private HTTP cache If the size is 0 then the private HTTP cache is not used (ignores cache-control)')
+            [CompletionResult]::new('--pin-sha256', '--pin-sha256', [CompletionResultType]::ParameterName, 'The base64 encoded SHA256 hashes of the public keys (SPKI), which are allowed for the certificate of the remote host, separated by commas')
+            [CompletionResult]::new('--ech-config', '--ech-config', [CompletionResultType]::ParameterName, 'Encrypt the TLS client hello to the remote host with ECH, so the domain is not sent in cleartext. The value is the base64 encoded ECHConfigList of the server or dns to fetch it from the HTTPS record of the domain with the --bootstrap-dns servers. Needs TLS 1.3 and is not supported over QUIC')
             [CompletionResult]::new('--client-auth-certs', '--client-auth-certs', [CompletionResultType]::ParameterName, 'The path to the pem file, which contains the certificates for the client authentication')
             [CompletionResult]::new('--client-auth-key', '--client-auth-key', [CompletionResultType]::ParameterName, 'The path to the pem file, which contains the key for the client authentication')
+            [CompletionResult]::new('--ca', '--ca', [CompletionResultType]::ParameterName, 'The path to a pem file or a directory of pem files, which contains CA certificates, which are trusted in addition to the platform''s native certificate store or CAFILE, for the remote hosts and the https proxies. Can be given multiple times')
             [CompletionResult]::new('--proxy-host', '--proxy-host', [CompletionResultType]::ParameterName, 'Socks5 or HTTP CONNECT proxy host (see below)')
-            [CompletionResult]::new('--proxy-scheme', '--proxy-scheme', [CompletionResultType]::ParameterName, 'The protocol of the proxy')
+            [CompletionResult]::new('--proxy-scheme', '--proxy-scheme', [CompletionResultType]::ParameterName, 'The protocol of the proxy, none connects the remote host directly')
             [CompletionResult]::new('--proxy-credentials', '--proxy-credentials', [CompletionResultType]::ParameterName, 'The credentials for the proxy')
-            [CompletionResult]::new('--proxy-https-cafile', '--proxy-https-cafile', [CompletionResultType]::ParameterName, 'The path to the pem file, which contains the trusted CA certificates for the https proxy If no path is given then the platform''s native certificate store will be used')
+            [CompletionResult]::new('--proxy-https-cafile', '--proxy-https-cafile', [CompletionResultType]::ParameterName, 'The path to the pem file or a directory of pem files, which contains the trusted CA certificates for the https proxy If no path is given then the platform''s native certificate store will be used')
             [CompletionResult]::new('--proxy-https-domain', '--proxy-https-domain', [CompletionResultType]::ParameterName, 'The domain name of the https proxy')
-            [CompletionResult]::new('--listen-activation', '--listen-activation', [CompletionResultType]::ParameterName, 'Use file descriptor 3 under Unix as UDP socket or launch_activate_socket() under Mac OS')
+            [CompletionResult]::new('--listen-activation', '--listen-activation', [CompletionResultType]::ParameterName, 'Use the UDP and TCP sockets passed by systemd (LISTEN_FDS) under Unix or launch_activate_socket() under Mac OS')
+            [CompletionResult]::new('--listen-tcp', '--listen-tcp', [CompletionResultType]::ParameterName, 'Listen for DNS queries over TCP on the listen address too or use the passed TCP sockets')
             [CompletionResult]::new('-g', '-g', [CompletionResultType]::ParameterName, 'Use the GET method for the HTTP/2.0 request')
             [CompletionResult]::new('--get', '--get', [CompletionResultType]::ParameterName, 'Use the GET method for the HTTP/2.0 request')
             [CompletionResult]::new('--cache-fallback', '--cache-fallback', [CompletionResultType]::ParameterName, 'Use expired cache entries if no response is received from the server')
+            [CompletionResult]::new('--pin-only', '--pin-only', [CompletionResultType]::ParameterName, 'Only check the public key pins of the remote hosts with --pin-sha256, but not the CA certificates and the domain, e.g. for self-signed certificates')
+            [CompletionResult]::new('--ech-fallback', '--ech-fallback', [CompletionResultType]::ParameterName, 'Send the domain in cleartext, if no ECH config of --ech-config is available or the server rejects ECH without retry configs. Otherwise the connection fails')
+            [CompletionResult]::new('--no-native-certs', '--no-native-certs', [CompletionResultType]::ParameterName, 'Do not trust the platform''s native certificate store, only the CA certificates of --ca')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
@@ -23,7 +23,7 @@ _doh-client() {
 
     case "${cmd}" in
         doh__client)
-            opts="-l -r -d -t -p -g -c -h -V --listen-addr --listen-activation --remote-host --domain --retries --timeout --path --get --cache-size --cache-fallback --client-auth-certs --client-auth-key --proxy-host --proxy-scheme --proxy-credentials --proxy-https-cafile --proxy-https-domain --help --version [CAFILE]"
+            opts="-l -r -d -u -t -p -g -c -h -V --listen-addr --listen-activation --listen-tcp --remote-host --domain --upstream --bootstrap-dns --retries --failback --strategy --weight --max-connections --keepalive --idle-timeout --timeout --hedge-delay --transport --path --tls-early-data --get --cache-size --cache-fallback --pin-sha256 --pin-only --ech-config --ech-fallback --client-auth-certs --client-auth-key --ca --no-native-certs --proxy-host --proxy-scheme --proxy-credentials --proxy-https-cafile --proxy-https-domain --help --version [CAFILE]"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -53,10 +53,46 @@ _doh-client() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --upstream)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                -u)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --bootstrap-dns)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
                 --retries)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --failback)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --strategy)
+                    COMPREPLY=($(compgen -W "failover round-robin random weighted fastest" -- "${cur}"))
+                    return 0
+                    ;;
+                --weight)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --max-connections)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --keepalive)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --idle-timeout)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
                 --timeout)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -65,6 +101,14 @@ _doh-client() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --hedge-delay)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --transport)
+                    COMPREPLY=($(compgen -W "h2 dot" -- "${cur}"))
+                    return 0
+                    ;;
                 --path)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -73,6 +117,10 @@ _doh-client() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --tls-early-data)
+                    COMPREPLY=($(compgen -W "true false" -- "${cur}"))
+                    return 0
+                    ;;
                 --cache-size)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -81,6 +129,14 @@ _doh-client() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --pin-sha256)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --ech-config)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
                 --client-auth-certs)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -89,12 +145,16 @@ _doh-client() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --ca)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
                 --proxy-host)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
                 --proxy-scheme)
-                    COMPREPLY=($(compgen -W "socks5 socks5h http https" -- "${cur}"))
+                    COMPREPLY=($(compgen -W "socks5 socks5h http https none" -- "${cur}"))
                     return 0
                     ;;
                 --proxy-credentials)
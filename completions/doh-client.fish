@@ -1,22 +1,47 @@
-complete -c doh-client -s l -l listen-addr -d 'Listen address [default: 127.0.0.1:53]' -r
-complete -c doh-client -s r -l remote-host -d 'Remote address/domain to the DOH server (see below)' -r
+complete -c doh-client -s l -l listen-addr -d 'Listen address, can be given multiple times to listen on several addresses [default: 127.0.0.1:53]' -r
+complete -c doh-client -s r -l remote-host -d 'Remote address/domain to the DOH server (see below) or a DNS stamp (sdns://), which also sets --domain, --path and --transport and pins the certificate hashes of the stamp' -r
 complete -c doh-client -s d -l domain -d 'The domain name of the remote server' -r
+complete -c doh-client -s u -l upstream -d 'The URI template of the DoH server (RFC 8484), which replaces --remote-host, --domain and --path, e.g. https://dns.example:8443/dns-query{?dns} or https://[2606:4700:4700::1111]/dns-query, without {?dns} the queries are always sent with POST' -r
+complete -c doh-client -l bootstrap-dns -d 'DNS server, which resolves the domain names of the remote hosts and the proxies over UDP or TCP instead of the system resolver, can be given multiple times [default port: 53]' -r
 complete -c doh-client -l retries -d 'The number of retries to connect to the remote server' -r
-complete -c doh-client -s t -l timeout -d 'The time in seconds after that the connection would be closed if no response is received from the server' -r
+complete -c doh-client -l failback -d 'The time in seconds after that a failed remote host is preferred again, e.g. the first remote host after a failover' -r
+complete -c doh-client -l strategy -d 'The strategy to select the remote host for a query' -r -f -a "failover\t''
+round-robin\t''
+random\t''
+weighted\t''
+fastest\t''"
+complete -c doh-client -l weight -d 'The weight of the remote host for the weighted strategy' -r
+complete -c doh-client -l max-connections -d 'The maximum number of HTTP/1.1 or HTTP/2 connections to the remote host, another connection is opened if all HTTP/1.1 connections are busy or all HTTP/2 connections reached the maximum number of concurrent streams of the server and idle connections are closed again' -r
+complete -c doh-client -l keepalive -d 'The interval in seconds of the PINGs on idle HTTP/2 connections, if the server does not answer a PING, then the remote host is connected again (0 disables the PINGs)' -r
+complete -c doh-client -l idle-timeout -d 'The time in seconds after that idle HTTP/1.1 and HTTP/2 connections are closed (0 keeps them open)' -r
+complete -c doh-client -s t -l timeout -d 'The time in seconds after that a query fails, if no response is received. The remote hosts, which are tried in turn, share this time: each one gets an equal part of the remaining time, so a query takes at most this long in total' -r
+complete -c doh-client -l hedge-delay -d 'Send the query to the next remote host too, if the remote host has not answered within this delay, e.g. 50 or p90' -r
+complete -c doh-client -l transport -d 'The protocol to connect to the remote host: h2 falls back to HTTP/1.1 if the server does not support it, h3 needs the http3 feature, dot is DNS over TLS (RFC 7858) and doq is DNS over QUIC (RFC 9250, needs the doq feature), both without HTTP, so --path and --get are ignored' -r -f -a "h2\t''
+dot\t''"
 complete -c doh-client -s p -l path -d 'The path of the URI' -r
+complete -c doh-client -l tls-early-data -d 'Send the first request as TLS 1.3 early data (0-RTT), if the TLS session to the remote host is resumed. Over HTTPS only, if the resumed session used HTTP/2. Use --tls-early-data=false to disable it for a remote host' -r -f -a "true\t''
+false\t''"
 complete -c doh-client -s c -l cache-size -d 'The size of the private HTTP cache If the size is 0 then the private HTTP cache is not used (ignores cache-control)' -r
+complete -c doh-client -l pin-sha256 -d 'The base64 encoded SHA256 hashes of the public keys (SPKI), which are allowed for the certificate of the remote host, separated by commas' -r
+complete -c doh-client -l ech-config -d 'Encrypt the TLS client hello to the remote host with ECH, so the domain is not sent in cleartext. The value is the base64 encoded ECHConfigList of the server or dns to fetch it from the HTTPS record of the domain with the --bootstrap-dns servers. Needs TLS 1.3 and is not supported over QUIC' -r
 complete -c doh-client -l client-auth-certs -d 'The path to the pem file, which contains the certificates for the client authentication' -r
 complete -c doh-client -l client-auth-key -d 'The path to the pem file, which contains the key for the client authentication' -r
+complete -c doh-client -l ca -d 'The path to a pem file or a directory of pem files, which contains CA certificates, which are trusted in addition to the platform\'s native certificate store or CAFILE, for the remote hosts and the https proxies. Can be given multiple times' -r
 complete -c doh-client -l proxy-host -d 'Socks5 or HTTP CONNECT proxy host (see below)' -r
-complete -c doh-client -l proxy-scheme -d 'The protocol of the proxy' -r -f -a "socks5\t''
+complete -c doh-client -l proxy-scheme -d 'The protocol of the proxy, none connects the remote host directly' -r -f -a "socks5\t''
 socks5h\t''
 http\t''
-https\t''"
+https\t''
+none\t''"
 complete -c doh-client -l proxy-credentials -d 'The credentials for the proxy' -r
-complete -c doh-client -l proxy-https-cafile -d 'The path to the pem file, which contains the trusted CA certificates for the https proxy If no path is given then the platform\'s native certificate store will be used' -r
+complete -c doh-client -l proxy-https-cafile -d 'The path to the pem file or a directory of pem files, which contains the trusted CA certificates for the https proxy If no path is given then the platform\'s native certificate store will be used' -r
 complete -c doh-client -l proxy-https-domain -d 'The domain name of the https proxy' -r
-complete -c doh-client -l listen-activation -d 'Use file descriptor 3 under Unix as UDP socket or launch_activate_socket() under Mac OS'
+complete -c doh-client -l listen-activation -d 'Use the UDP and TCP sockets passed by systemd (LISTEN_FDS) under Unix or launch_activate_socket() under Mac OS'
+complete -c doh-client -l listen-tcp -d 'Listen for DNS queries over TCP on the listen address too or use the passed TCP sockets'
 complete -c doh-client -s g -l get -d 'Use the GET method for the HTTP/2.0 request'
 complete -c doh-client -l cache-fallback -d 'Use expired cache entries if no response is received from the server'
+complete -c doh-client -l pin-only -d 'Only check the public key pins of the remote hosts with --pin-sha256, but not the CA certificates and the domain, e.g. for self-signed certificates'
+complete -c doh-client -l ech-fallback -d 'Send the domain in cleartext, if no ECH config of --ech-config is available or the server rejects ECH without retry configs. Otherwise the connection fails'
+complete -c doh-client -l no-native-certs -d 'Do not trust the platform\'s native certificate store, only the CA certificates of --ca'
 complete -c doh-client -s h -l help -d 'Print help'
 complete -c doh-client -s V -l version -d 'Print version'
@@ -1,10 +1,15 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::time::Duration;
 
+use crate::resolver::BOOTSTRAP_DNS_PORT;
+use crate::HedgeDelay;
+use clap::builder::PossibleValuesParser;
 use clap::value_parser;
 use clap::{crate_authors, crate_description, crate_version, Arg, ArgAction, Command};
 
 const ABOUT: &str =
-    "Open a local UDP (DNS) port and forward DNS queries to a remote HTTP/2.0 server.\n\
+    "Open a local UDP (DNS) port, and optionally a TCP port, and forward DNS queries to a remote HTTP/2.0 server.\n\
     By default, the client will connect to the Cloudflare DNS service.\n\
     This binary uses the env_logger as logger implementations. \n\
     See https://github.com/sebasmagri/env_logger/";
@@ -13,7 +18,63 @@ const AFTER_HELP: &str =
     "CAUTION: If a domain name is used for a <Addr/Domain:Port> value instead of an IP address \
     the system resolver will be used to resolve the IP address of the domain name. If the \
     `doh-client` is configured as system resolver, then it will NOT WORK. It is recommended to \
-    always use an IP address for <Addr/Domain:Port> values.\n";
+    always use an IP address for <Addr/Domain:Port> values or --bootstrap-dns.\n\n\
+    MULTIPLE REMOTE HOSTS: If --remote-host is given multiple times then the remote hosts are used \
+    by the --strategy: with failover the first remote host is preferred and the next one is used \
+    if the connection fails or a query times out, round-robin uses them one after another, random \
+    and weighted pick a random remote host (weighted by --weight) and fastest prefers the remote \
+    host with the lowest average latency. A failed remote host is tried last until the --failback \
+    time is over. With --hedge-delay a query is sent to the next remote host too if the first one \
+    has not answered within the delay, the first answer is used and the other request is \
+    cancelled. The delay is either fixed in milliseconds (e.g. 50) or a percentile of the recent \
+    latencies of the first remote host (e.g. p90). The per remote host arguments (--domain, \
+    --path, --weight, --max-connections, --keepalive, --idle-timeout, --transport, --odoh-target, \
+    --odoh-config, --pin-sha256, --ech-config, --tls-early-data, --client-auth-*, --proxy-*) can \
+    be given once for all remote hosts or once per remote host, then the n-th value belongs to the \
+    n-th remote host. The same applies to --upstream instead of --remote-host. With \
+    --proxy-scheme none a remote host is connected without a proxy.\n";
+
+/// The values of the `transport` argument, which are supported by the enabled features.
+fn transports() -> Vec<&'static str> {
+    let mut transports = vec!["h2"];
+    if cfg!(feature = "http3") {
+        transports.push("h3");
+    }
+    transports.push("dot");
+    if cfg!(feature = "doq") {
+        transports.push("doq");
+    }
+    transports
+}
+
+fn parse_bootstrap_dns(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        Ok(addr)
+    } else if let Ok(addr) = value.parse::<IpAddr>() {
+        Ok(SocketAddr::new(addr, BOOTSTRAP_DNS_PORT))
+    } else {
+        Err(format!(
+            "{} is not an IP address with an optional port",
+            value
+        ))
+    }
+}
+
+fn parse_hedge_delay(value: &str) -> Result<HedgeDelay, String> {
+    if let Some(percentile) = value.strip_prefix('p') {
+        match percentile.parse::<u8>() {
+            Ok(percentile) if (1..=100).contains(&percentile) => {
+                Ok(HedgeDelay::Percentile(percentile))
+            }
+            _ => Err(format!("{} is not a percentile between p1 and p100", value)),
+        }
+    } else {
+        match value.parse::<u64>() {
+            Ok(delay) => Ok(HedgeDelay::Fixed(Duration::from_millis(delay))),
+            Err(e) => Err(format!("{} is not a delay in milliseconds: {}", value, e)),
+        }
+    }
+}
 
 #[cfg(any(feature = "socks5", feature = "http-proxy"))]
 fn proxy_args(app: Command) -> Command {
@@ -21,33 +82,33 @@ fn proxy_args(app: Command) -> Command {
 
     let mut proxy_host = Arg::new("proxy-host")
         .long("proxy-host")
-        .action(ArgAction::Set)
+        .action(ArgAction::Append)
         .value_name("Addr/Domain:Port")
         .required(false)
         .requires("proxy-scheme");
 
     let mut proxy_scheme = Arg::new("proxy-scheme")
         .long("proxy-scheme")
-        .action(ArgAction::Set)
-        .help("The protocol of the proxy")
+        .action(ArgAction::Append)
+        .help("The protocol of the proxy, none connects the remote host directly")
         .required(false)
         .requires("proxy-host");
 
     if cfg!(all(feature = "socks5", feature = "http-proxy")) {
         proxy_host = proxy_host.help("Socks5 or HTTP CONNECT proxy host (see below)");
-        proxy_scheme = proxy_scheme.value_parser(["socks5", "socks5h", "http", "https"]);
+        proxy_scheme = proxy_scheme.value_parser(["socks5", "socks5h", "http", "https", "none"]);
     } else if cfg!(all(feature = "socks5", not(feature = "http-proxy"))) {
         proxy_host = proxy_host.help("Socks5 proxy host (see below)");
-        proxy_scheme = proxy_scheme.value_parser(["socks5", "socks5h"]);
+        proxy_scheme = proxy_scheme.value_parser(["socks5", "socks5h", "none"]);
     } else {
         proxy_host = proxy_host.help("HTTP CONNECT proxy host (see below)");
-        proxy_scheme = proxy_scheme.value_parser(["http", "https"]);
+        proxy_scheme = proxy_scheme.value_parser(["http", "https", "none"]);
     }
 
     let command = app.arg(proxy_host).arg(proxy_scheme).arg(
         Arg::new("proxy-credentials")
             .long("proxy-credentials")
-            .action(ArgAction::Set)
+            .action(ArgAction::Append)
             .value_name("Username:Password")
             .help("The credentials for the proxy")
             .requires_all(&["proxy-host", "proxy-scheme"][..]),
@@ -57,25 +118,25 @@ fn proxy_args(app: Command) -> Command {
         let arg = Arg::new("proxy-https-cafile")
             .value_name("CAFILE")
             .long("proxy-https-cafile")
-            .action(ArgAction::Set);
+            .action(ArgAction::Append);
         let arg = if cfg!(feature = "native-certs") {
             arg.help(
-                "The path to the pem file, which contains the trusted CA \
-                      certificates for the https proxy\n\
+                "The path to the pem file or a directory of pem files, which contains the \
+                      trusted CA certificates for the https proxy\n\
                       If no path is given then the platform's native certificate store \
                       will be used",
             )
             .required(false)
         } else {
             arg.help(
-                "The path to the pem file, which contains the trusted CA \
-                      certificates for the https proxy",
+                "The path to the pem file or a directory of pem files, which contains the \
+                      trusted CA certificates for the https proxy",
             )
             .required_if_eq("proxy-scheme", "https")
         };
         command.arg(arg).arg(
             Arg::new("proxy-https-domain")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("Domain")
                 .long("proxy-https-domain")
                 .help("The domain name of the https proxy")
@@ -86,22 +147,82 @@ fn proxy_args(app: Command) -> Command {
     }
 }
 
+#[cfg(feature = "odoh")]
+fn odoh_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("odoh-target")
+                .long("odoh-target")
+                .action(ArgAction::Append)
+                .value_name("Domain[:Port][/Path]")
+                .help(
+                    "Use Oblivious DoH (RFC 9230): the remote host is the relay, which forwards \
+                    the encrypted queries to this target, e.g. odoh.cloudflare-dns.com/dns-query \
+                    and --path proxy, or a DNS stamp (sdns://) of the target. The ODoH config is \
+                    fetched from the target over the --proxy-* of the remote host, without a \
+                    proxy the target sees the address of the client",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("odoh-config")
+                .long("odoh-config")
+                .action(ArgAction::Append)
+                .value_name("BASE64")
+                .help(
+                    "The base64 encoded ODoH configs of the --odoh-target, which are used instead \
+                    of fetching them from the target",
+                )
+                .requires("odoh-target")
+                .required(false),
+        )
+}
+
 fn cafile(command: Command) -> Command {
     let arg = Arg::new("cafile")
         .action(ArgAction::Set)
         .value_name("CAFILE");
     let arg = if cfg!(feature = "native-certs") {
         arg.help(
-            "The path to the pem file, which contains the trusted CA certificates\n\
+            "The path to the pem file or a directory of pem files, which contains the trusted CA \
+                  certificates\n\
                   If no path is given then the platform's native certificate store will be \
                   used",
         )
         .required(false)
     } else {
-        arg.help("The path to the pem file, which contains the trusted CA certificates")
-            .required(true)
+        arg.help(
+            "The path to the pem file or a directory of pem files, which contains the trusted CA \
+                  certificates",
+        )
+        .required_unless_present("ca")
     };
-    command.arg(arg)
+    command
+        .arg(arg)
+        .arg(
+            Arg::new("ca")
+                .long("ca")
+                .action(ArgAction::Append)
+                .value_name("CAFILE/CADIR")
+                .help(
+                    "The path to a pem file or a directory of pem files, which contains CA \
+                    certificates, which are trusted in addition to the platform's native \
+                    certificate store or CAFILE, for the remote hosts and the https proxies. Can \
+                    be given multiple times",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("no-native-certs")
+                .long("no-native-certs")
+                .help(
+                    "Do not trust the platform's native certificate store, only the CA \
+                    certificates of --ca",
+                )
+                .action(ArgAction::SetTrue)
+                .requires("ca")
+                .required(false),
+        )
 }
 
 /// Get the `clap::App` object for the argument parsing.
@@ -117,9 +238,12 @@ pub fn get_command() -> Command {
                 .short('l')
                 .long("listen-addr")
                 .conflicts_with("listen-activation")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("Addr:Port")
-                .help("Listen address [default: 127.0.0.1:53]")
+                .help(
+                    "Listen address, can be given multiple times to listen on several addresses \
+                    [default: 127.0.0.1:53]",
+                )
                 .required(false),
         )
         .arg(
@@ -128,8 +252,18 @@ pub fn get_command() -> Command {
                 .action(ArgAction::SetTrue)
                 .conflicts_with("listen-addr")
                 .help(
-                    "Use file descriptor 3 under Unix as UDP socket or launch_activate_socket() \
-                    under Mac OS",
+                    "Use the UDP and TCP sockets passed by systemd (LISTEN_FDS) under Unix or \
+                    launch_activate_socket() under Mac OS",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("listen-tcp")
+                .long("listen-tcp")
+                .action(ArgAction::SetTrue)
+                .help(
+                    "Listen for DNS queries over TCP on the listen address too or use the passed \
+                    TCP sockets",
                 )
                 .required(false),
         )
@@ -137,9 +271,13 @@ pub fn get_command() -> Command {
             Arg::new("remote-host")
                 .short('r')
                 .long("remote-host")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("Addr/Domain:Port")
-                .help("Remote address/domain to the DOH server (see below)")
+                .help(
+                    "Remote address/domain to the DOH server (see below) or a DNS stamp \
+                    (sdns://), which also sets --domain, --path and --transport and pins the \
+                    certificate hashes of the stamp",
+                )
                 .default_value("1.1.1.1:443")
                 .required(false),
         )
@@ -147,12 +285,40 @@ pub fn get_command() -> Command {
             Arg::new("domain")
                 .short('d')
                 .long("domain")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("Domain")
                 .help("The domain name of the remote server")
                 .default_value("cloudflare-dns.com")
                 .required(false),
         )
+        .arg(
+            Arg::new("upstream")
+                .short('u')
+                .long("upstream")
+                .action(ArgAction::Append)
+                .value_name("URI TEMPLATE")
+                .help(
+                    "The URI template of the DoH server (RFC 8484), which replaces --remote-host, \
+                    --domain and --path, e.g. https://dns.example:8443/dns-query{?dns} or \
+                    https://[2606:4700:4700::1111]/dns-query, without {?dns} the queries are \
+                    always sent with POST",
+                )
+                .conflicts_with_all(["remote-host", "domain", "path"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("bootstrap-dns")
+                .value_parser(parse_bootstrap_dns)
+                .long("bootstrap-dns")
+                .action(ArgAction::Append)
+                .value_name("Addr[:Port]")
+                .help(
+                    "DNS server, which resolves the domain names of the remote hosts and the \
+                    proxies over UDP or TCP instead of the system resolver, can be given multiple \
+                    times [default port: 53]",
+                )
+                .required(false),
+        )
         .arg(
             Arg::new("retries")
                 .value_parser(value_parser!(u32))
@@ -163,6 +329,81 @@ pub fn get_command() -> Command {
                 .default_value("3")
                 .required(false),
         )
+        .arg(
+            Arg::new("failback")
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Set)
+                .long("failback")
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The time in seconds after that a failed remote host is preferred again, \
+                    e.g. the first remote host after a failover",
+                )
+                .default_value("60")
+                .required(false),
+        )
+        .arg(
+            Arg::new("strategy")
+                .action(ArgAction::Set)
+                .long("strategy")
+                .value_name("STRATEGY")
+                .value_parser(["failover", "round-robin", "random", "weighted", "fastest"])
+                .help("The strategy to select the remote host for a query")
+                .default_value("failover")
+                .required(false),
+        )
+        .arg(
+            Arg::new("weight")
+                .value_parser(value_parser!(u32))
+                .action(ArgAction::Append)
+                .long("weight")
+                .value_name("UNSIGNED INT")
+                .help("The weight of the remote host for the weighted strategy")
+                .default_value("1")
+                .required(false),
+        )
+        .arg(
+            Arg::new("max-connections")
+                .value_parser(value_parser!(NonZeroUsize))
+                .action(ArgAction::Append)
+                .long("max-connections")
+                .value_name("UNSIGNED INT")
+                .help(
+                    "The maximum number of HTTP/1.1 or HTTP/2 connections to the remote host, \
+                    another connection is opened if all HTTP/1.1 connections are busy or all \
+                    HTTP/2 connections reached the maximum number of concurrent streams of the \
+                    server and idle connections are closed again",
+                )
+                .default_value("4")
+                .required(false),
+        )
+        .arg(
+            Arg::new("keepalive")
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Append)
+                .long("keepalive")
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The interval in seconds of the PINGs on idle HTTP/2 connections, if the \
+                    server does not answer a PING, then the remote host is connected again \
+                    (0 disables the PINGs)",
+                )
+                .default_value("30")
+                .required(false),
+        )
+        .arg(
+            Arg::new("idle-timeout")
+                .value_parser(value_parser!(u64))
+                .action(ArgAction::Append)
+                .long("idle-timeout")
+                .value_name("UNSIGNED LONG")
+                .help(
+                    "The time in seconds after that idle HTTP/1.1 and HTTP/2 connections are \
+                    closed (0 keeps them open)",
+                )
+                .default_value("300")
+                .required(false),
+        )
         .arg(
             Arg::new("timeout")
                 .value_parser(value_parser!(u64))
@@ -171,22 +412,66 @@ pub fn get_command() -> Command {
                 .long("timeout")
                 .value_name("UNSIGNED LONG")
                 .help(
-                    "The time in seconds after that the connection would be closed if no response \
-                    is received from the server",
+                    "The time in seconds after that a query fails, if no response is received. The \
+                    remote hosts, which are tried in turn, share this time: each one gets an equal \
+                    part of the remaining time, so a query takes at most this long in total",
                 )
                 .default_value("2")
                 .required(false),
         )
+        .arg(
+            Arg::new("hedge-delay")
+                .value_parser(parse_hedge_delay)
+                .action(ArgAction::Set)
+                .long("hedge-delay")
+                .value_name("MILLISECONDS|PERCENTILE")
+                .help(
+                    "Send the query to the next remote host too, if the remote host has not \
+                    answered within this delay, e.g. 50 or p90",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("transport")
+                .action(ArgAction::Append)
+                .long("transport")
+                .value_name("TRANSPORT")
+                .value_parser(PossibleValuesParser::new(transports()))
+                .help(
+                    "The protocol to connect to the remote host: h2 falls back to HTTP/1.1 if the \
+                    server does not support it, h3 needs the http3 feature, dot is DNS over TLS \
+                    (RFC 7858) and doq is DNS over QUIC (RFC 9250, needs the doq feature), both \
+                    without HTTP, so --path and --get are ignored",
+                )
+                .default_value("h2")
+                .required(false),
+        )
         .arg(
             Arg::new("path")
                 .short('p')
                 .long("path")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("STRING")
                 .help("The path of the URI")
                 .default_value("dns-query")
                 .required(false),
         )
+        .arg(
+            Arg::new("tls-early-data")
+                .long("tls-early-data")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::Append)
+                .value_name("BOOL")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("true")
+                .help(
+                    "Send the first request as TLS 1.3 early data (0-RTT), if the TLS session to \
+                    the remote host is resumed. Over HTTPS only, if the resumed session used \
+                    HTTP/2. Use --tls-early-data=false to disable it for a remote host",
+                )
+                .required(false),
+        )
         .arg(
             Arg::new("get")
                 .short('g')
@@ -217,10 +502,56 @@ pub fn get_command() -> Command {
                 .help("Use expired cache entries if no response is received from the server")
                 .required(false),
         )
+        .arg(
+            Arg::new("pin-sha256")
+                .long("pin-sha256")
+                .action(ArgAction::Append)
+                .value_name("BASE64,...")
+                .help(
+                    "The base64 encoded SHA256 hashes of the public keys (SPKI), which are allowed \
+                    for the certificate of the remote host, separated by commas",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("pin-only")
+                .long("pin-only")
+                .help(
+                    "Only check the public key pins of the remote hosts with --pin-sha256, but \
+                    not the CA certificates and the domain, e.g. for self-signed certificates",
+                )
+                .action(ArgAction::SetTrue)
+                .requires("pin-sha256")
+                .required(false),
+        )
+        .arg(
+            Arg::new("ech-config")
+                .long("ech-config")
+                .action(ArgAction::Append)
+                .value_name("BASE64/dns")
+                .help(
+                    "Encrypt the TLS client hello to the remote host with ECH, so the domain is \
+                    not sent in cleartext. The value is the base64 encoded ECHConfigList of the \
+                    server or dns to fetch it from the HTTPS record of the domain with the \
+                    --bootstrap-dns servers. Needs TLS 1.3 and is not supported over QUIC",
+                )
+                .required(false),
+        )
+        .arg(
+            Arg::new("ech-fallback")
+                .long("ech-fallback")
+                .help(
+                    "Send the domain in cleartext, if no ECH config of --ech-config is available or \
+                    the server rejects ECH without retry configs. Otherwise the connection fails",
+                )
+                .action(ArgAction::SetTrue)
+                .requires("ech-config")
+                .required(false),
+        )
         .arg(
             Arg::new("client-auth-certs")
                 .long("client-auth-certs")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("CERTSFILE")
                 .help(
                     "The path to the pem file, which contains the certificates for the client \
@@ -232,7 +563,7 @@ pub fn get_command() -> Command {
         .arg(
             Arg::new("client-auth-key")
                 .long("client-auth-key")
-                .action(ArgAction::Set)
+                .action(ArgAction::Append)
                 .value_name("KEYFILE")
                 .help(
                     "The path to the pem file, which contains the key for the client \
@@ -247,5 +578,8 @@ pub fn get_command() -> Command {
     #[cfg(any(feature = "socks5", feature = "http-proxy"))]
     let command = proxy_args(command);
 
+    #[cfg(feature = "odoh")]
+    let command = odoh_args(command);
+
     command
 }
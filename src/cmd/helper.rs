@@ -0,0 +1,65 @@
+use crate::helper::CaCerts;
+use clap::ArgMatches;
+use std::any::Any;
+
+/// The arguments, which are given once for all remote hosts or once per remote host.
+pub(crate) const PER_HOST_ARGS: &[&str] = &[
+    "domain",
+    "path",
+    "weight",
+    "max-connections",
+    "keepalive",
+    "idle-timeout",
+    "transport",
+    "odoh-target",
+    "odoh-config",
+    "pin-sha256",
+    "ech-config",
+    "tls-early-data",
+    "client-auth-certs",
+    "client-auth-key",
+    "proxy-host",
+    "proxy-scheme",
+    "proxy-credentials",
+    "proxy-https-cafile",
+    "proxy-https-domain",
+];
+
+/// Get the number of the values of the argument `id`, which is 0 if the argument is not given or
+/// not supported by the enabled features.
+pub(crate) fn count_values(arg_matches: &ArgMatches, id: &str) -> usize {
+    match arg_matches.try_get_raw(id) {
+        Ok(Some(values)) => values.count(),
+        _ => 0,
+    }
+}
+
+/// Get the value of the argument for the remote host with the `index`. If the argument is given
+/// only once, then the value is used for all remote hosts (see `PER_HOST_ARGS`).
+pub(crate) fn get_nth_or_last<'a, T>(
+    arg_matches: &'a ArgMatches,
+    id: &str,
+    index: usize,
+) -> Option<&'a T>
+where
+    T: Any + Clone + Send + Sync + 'static,
+{
+    let values = arg_matches.get_many::<T>(id)?;
+    let values: Vec<&T> = values.collect();
+    values.get(index).or_else(|| values.last()).copied()
+}
+
+/// Get the trusted CA certificates: the ones of `cafile` replace the native certificate store, the
+/// ones of the `ca` arguments are added and `no-native-certs` removes the native certificate store.
+pub(crate) fn get_ca_certs(arg_matches: &ArgMatches, cafile: Option<&String>) -> CaCerts {
+    let mut ca_certs = CaCerts::from(cafile);
+    if let Some(paths) = arg_matches.get_many::<String>("ca") {
+        for path in paths {
+            ca_certs = ca_certs.with_path(path);
+        }
+    }
+    if arg_matches.get_flag("no-native-certs") {
+        ca_certs = ca_certs.without_native();
+    }
+    ca_certs
+}
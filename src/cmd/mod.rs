@@ -1,7 +1,9 @@
 mod app;
+mod helper;
 mod listen_config;
 mod remote_host;
 
 pub use app::get_command;
+pub(crate) use helper::{count_values, get_ca_certs, get_nth_or_last, PER_HOST_ARGS};
 pub use listen_config::get_listen_config;
-pub use remote_host::{get_remote_host, RemoteHostError};
+pub use remote_host::{get_bootstrap_dns, get_remote_host, get_remote_hosts, RemoteHostError};
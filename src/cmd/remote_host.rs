@@ -1,10 +1,14 @@
 #[cfg(feature = "http-proxy")]
+use super::get_ca_certs;
+use super::{count_values, get_nth_or_last, PER_HOST_ARGS};
+#[cfg(feature = "http-proxy")]
 use crate::helper::load_root_store;
-use crate::RemoteHost;
+use crate::helper::split_host_port;
+use crate::stamp::{is_stamp, Stamp};
+use crate::{RemoteHost, UriTemplate};
 use clap::ArgMatches;
 use std::io::Error as IoError;
-#[cfg(feature = "socks5")]
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
 #[cfg(feature = "http-proxy")]
 use std::sync::Arc;
 use thiserror::Error as ThisError;
@@ -23,66 +27,75 @@ pub enum RemoteHostError {
     Io(#[from] IoError),
     #[error("Unknown port: {0}")]
     UnknownPort(String),
-    #[error("Unknown hostm and port: {0}")]
+    #[error("Unknown host and port: {0}")]
     UnknownHostPort(String),
+    #[error("Could not parse the URI template: {0}")]
+    UriTemplate(String),
+    #[error("Could not parse the DNS stamp: {0}")]
+    Stamp(String),
+    #[error("--{0} is given {1} times, but it has to be given once or once per remote host ({2})")]
+    PerHostArgCount(&'static str, usize, usize),
 }
 
 fn parse_host_port(host_port: &str) -> Result<(&str, u16), RemoteHostError> {
-    let host_port_vec: Vec<&str> = host_port.rsplitn(2, ':').collect();
-    if host_port_vec.len() != 2 {
-        return Err(RemoteHostError::UnknownHostPort(host_port.to_owned()));
-    }
-    let host = host_port_vec[1];
-    if let Ok(port) = host_port_vec[0].parse() {
-        Ok((host, port))
-    } else {
-        Err(RemoteHostError::UnknownPort(host.to_owned()))
+    match split_host_port(host_port) {
+        Some((host, Some(port))) if !host.is_empty() => match port.parse() {
+            Ok(port) => Ok((host, port)),
+            Err(_) => Err(RemoteHostError::UnknownPort(port.to_owned())),
+        },
+        _ => Err(RemoteHostError::UnknownHostPort(host_port.to_owned())),
     }
 }
 
-fn get_remote_host_port(arg_matches: &ArgMatches) -> Result<(String, u16), RemoteHostError> {
-    let remote_host_port = arg_matches.get_one::<String>("remote-host").unwrap();
-    let (remote_host, remote_port) = parse_host_port(remote_host_port)?;
-    Ok((remote_host.to_owned(), remote_port))
+/// Get the host and the port of the remote host from the `upstream` argument, if it is given, or
+/// from the `remote-host` argument, which can be a DNS stamp too.
+fn get_remote_host_port(
+    arg_matches: &ArgMatches,
+    index: usize,
+) -> Result<(String, u16), RemoteHostError> {
+    if let Some(upstream) = get_nth_or_last::<String>(arg_matches, "upstream", index) {
+        match UriTemplate::parse(upstream) {
+            Some(uri_template) => Ok((uri_template.host, uri_template.port)),
+            None => Err(RemoteHostError::UriTemplate(upstream.to_owned())),
+        }
+    } else {
+        let remote_host_port =
+            get_nth_or_last::<String>(arg_matches, "remote-host", index).unwrap();
+        if is_stamp(remote_host_port) {
+            return match Stamp::parse(remote_host_port) {
+                Some(stamp) => Ok((stamp.host, stamp.port)),
+                None => Err(RemoteHostError::Stamp(remote_host_port.to_owned())),
+            };
+        }
+        let (remote_host, remote_port) = parse_host_port(remote_host_port)?;
+        Ok((remote_host.to_owned(), remote_port))
+    }
 }
 
-fn get_direct(arg_matches: &ArgMatches) -> Result<RemoteHost, RemoteHostError> {
-    let (remote_host, remote_port) = get_remote_host_port(arg_matches)?;
+fn get_direct(arg_matches: &ArgMatches, index: usize) -> Result<RemoteHost, RemoteHostError> {
+    let (remote_host, remote_port) = get_remote_host_port(arg_matches, index)?;
     let remote_host = RemoteHost::Direct(remote_host, remote_port);
     Ok(remote_host)
 }
 
 #[cfg(any(feature = "socks5", feature = "http-proxy"))]
-async fn get_proxy_host_port(arg_matches: &ArgMatches) -> Result<(String, u16), RemoteHostError> {
-    let proxy_host_port = arg_matches.get_one::<String>("proxy-host").unwrap();
+async fn get_proxy_host_port(
+    arg_matches: &ArgMatches,
+    index: usize,
+) -> Result<(String, u16), RemoteHostError> {
+    let proxy_host_port = get_nth_or_last::<String>(arg_matches, "proxy-host", index).unwrap();
     let (proxy_host, proxy_port) = parse_host_port(proxy_host_port)?;
     Ok((proxy_host.to_owned(), proxy_port))
 }
 
-#[cfg(feature = "socks5")]
-async fn get_proxy_remote_addrs(
-    arg_matches: &ArgMatches,
-) -> Result<Vec<SocketAddr>, RemoteHostError> {
-    let remote_host = arg_matches.get_one::<String>("remote-host").unwrap();
-    let (host, port) = parse_host_port(remote_host)?;
-    match host.parse::<IpAddr>() {
-        Ok(host) => {
-            let remote_addrs = vec![SocketAddr::new(host, port)];
-            Ok(remote_addrs)
-        }
-        Err(_) => {
-            let remote_addrs = tokio::net::lookup_host(remote_host).await?;
-            let remote_addrs = remote_addrs.collect();
-            Ok(remote_addrs)
-        }
-    }
-}
-
 #[cfg(any(feature = "socks5", feature = "http-proxy"))]
 fn get_proxy_credentials(
     arg_matches: &ArgMatches,
+    index: usize,
 ) -> Result<Option<(String, String)>, RemoteHostError> {
-    if let Some(proxy_credentials) = arg_matches.get_one::<String>("proxy-credentials") {
+    if let Some(proxy_credentials) =
+        get_nth_or_last::<String>(arg_matches, "proxy-credentials", index)
+    {
         let proxy_credentials_vec: Vec<&str> = proxy_credentials.splitn(2, ':').collect();
         if proxy_credentials_vec.len() == 2 {
             let username = proxy_credentials_vec[0].to_owned();
@@ -101,9 +114,10 @@ fn get_proxy_credentials(
 #[cfg(feature = "http-proxy")]
 fn get_proxy_https_client_config(
     arg_matches: &ArgMatches,
+    index: usize,
 ) -> Result<ClientConfig, RemoteHostError> {
-    let https_cafile = arg_matches.get_one::<String>("proxy-https-cafile");
-    let root_store = load_root_store(https_cafile)?;
+    let https_cafile = get_nth_or_last::<String>(arg_matches, "proxy-https-cafile", index);
+    let root_store = load_root_store(&get_ca_certs(arg_matches, https_cafile))?;
     let mut config = ClientConfig::builder()
         .with_root_certificates(root_store)
         .with_no_client_auth();
@@ -114,30 +128,33 @@ fn get_proxy_https_client_config(
 }
 
 #[cfg(feature = "http-proxy")]
-fn get_proxy_https_domain(arg_matches: &ArgMatches) -> &String {
-    arg_matches.get_one::<String>("proxy-https-domain").unwrap()
+fn get_proxy_https_domain(arg_matches: &ArgMatches, index: usize) -> &String {
+    get_nth_or_last::<String>(arg_matches, "proxy-https-domain", index).unwrap()
 }
 
 #[cfg(any(feature = "socks5", feature = "http-proxy"))]
-async fn get_proxy(arg_matches: &ArgMatches) -> Result<RemoteHost, RemoteHostError> {
-    let proxy_scheme = arg_matches.get_one::<String>("proxy-scheme");
+async fn get_proxy(arg_matches: &ArgMatches, index: usize) -> Result<RemoteHost, RemoteHostError> {
+    // The remote host is connected directly, if the scheme is `none`.
+    let proxy_scheme = get_nth_or_last::<String>(arg_matches, "proxy-scheme", index)
+        .filter(|proxy_scheme| proxy_scheme.as_str() != "none");
     if let Some(proxy_scheme) = proxy_scheme {
-        let (proxy_host, proxy_port) = get_proxy_host_port(arg_matches).await?;
-        let credentials = get_proxy_credentials(arg_matches)?;
+        let (proxy_host, proxy_port) = get_proxy_host_port(arg_matches, index).await?;
+        let credentials = get_proxy_credentials(arg_matches, index)?;
         match proxy_scheme.as_str() {
             #[cfg(feature = "socks5")]
             "socks5" => {
-                let remote_addrs = get_proxy_remote_addrs(arg_matches).await?;
+                let (remote_host, remote_port) = get_remote_host_port(arg_matches, index)?;
                 Ok(RemoteHost::Socks5(
                     proxy_host,
                     proxy_port,
                     credentials,
-                    remote_addrs,
+                    remote_host,
+                    remote_port,
                 ))
             }
             #[cfg(feature = "socks5")]
             "socks5h" => {
-                let (remote_host, remote_port) = get_remote_host_port(arg_matches)?;
+                let (remote_host, remote_port) = get_remote_host_port(arg_matches, index)?;
                 Ok(RemoteHost::Socks5h(
                     proxy_host,
                     proxy_port,
@@ -148,7 +165,7 @@ async fn get_proxy(arg_matches: &ArgMatches) -> Result<RemoteHost, RemoteHostErr
             }
             #[cfg(feature = "http-proxy")]
             "http" => {
-                let (remote_host, remote_port) = get_remote_host_port(arg_matches)?;
+                let (remote_host, remote_port) = get_remote_host_port(arg_matches, index)?;
                 Ok(RemoteHost::HttpProxy(
                     proxy_host,
                     proxy_port,
@@ -159,10 +176,10 @@ async fn get_proxy(arg_matches: &ArgMatches) -> Result<RemoteHost, RemoteHostErr
             }
             #[cfg(feature = "http-proxy")]
             "https" => {
-                let (remote_host, remote_port) = get_remote_host_port(arg_matches)?;
-                let https_client_config = get_proxy_https_client_config(arg_matches)?;
+                let (remote_host, remote_port) = get_remote_host_port(arg_matches, index)?;
+                let https_client_config = get_proxy_https_client_config(arg_matches, index)?;
                 let https_client_config = Arc::new(https_client_config);
-                let https_domain = get_proxy_https_domain(arg_matches);
+                let https_domain = get_proxy_https_domain(arg_matches, index);
                 Ok(RemoteHost::HttpsProxy(
                     proxy_host,
                     proxy_port,
@@ -176,22 +193,190 @@ async fn get_proxy(arg_matches: &ArgMatches) -> Result<RemoteHost, RemoteHostErr
             scheme => Err(RemoteHostError::ProxyScheme(scheme.to_string())),
         }
     } else {
-        get_direct(arg_matches)
+        get_direct(arg_matches, index)
     }
 }
 
 #[cfg(all(not(feature = "socks5"), not(feature = "http-proxy")))]
-async fn get_proxy(_: &ArgMatches) -> Result<RemoteHost, RemoteHostError> {
+async fn get_proxy(_: &ArgMatches, _: usize) -> Result<RemoteHost, RemoteHostError> {
     Err(RemoteHostError::Io(IoError::new(
         std::io::ErrorKind::Other,
         "Feature native-certs is not enabled",
     )))
 }
 
-pub async fn get_remote_host(arg_matches: &ArgMatches) -> Result<RemoteHost, RemoteHostError> {
+/// Get the bootstrap DNS servers, which resolve the domain names of the remote hosts and the
+/// proxies. The bootstrap DNS servers of the DNS stamps are used after the ones of the
+/// `bootstrap-dns` argument.
+pub fn get_bootstrap_dns(arg_matches: &ArgMatches) -> Vec<SocketAddr> {
+    let mut bootstrap_dns: Vec<SocketAddr> = arg_matches
+        .get_many::<SocketAddr>("bootstrap-dns")
+        .map(|bootstrap_dns| bootstrap_dns.copied().collect())
+        .unwrap_or_default();
+    if let Some(remote_hosts) = arg_matches.get_many::<String>("remote-host") {
+        for stamp in remote_hosts.filter_map(|remote_host| Stamp::parse(remote_host)) {
+            for addr in stamp.bootstrap_dns {
+                if !bootstrap_dns.contains(&addr) {
+                    bootstrap_dns.push(addr);
+                }
+            }
+        }
+    }
+    bootstrap_dns
+}
+
+/// Get the remote host with the `index`. The values of the proxy arguments are used in the same
+/// order as the values of the `remote-host` argument.
+pub async fn get_remote_host(
+    arg_matches: &ArgMatches,
+    index: usize,
+) -> Result<RemoteHost, RemoteHostError> {
     if cfg!(any(feature = "socks5", feature = "http-proxy")) {
-        get_proxy(arg_matches).await
+        get_proxy(arg_matches, index).await
     } else {
-        get_direct(arg_matches)
+        get_direct(arg_matches, index)
+    }
+}
+
+/// Check that each per remote host argument is given once or once per remote host, so a value
+/// cannot belong to the wrong remote host.
+fn check_per_host_args(arg_matches: &ArgMatches, count: usize) -> Result<(), RemoteHostError> {
+    for id in PER_HOST_ARGS {
+        let values = count_values(arg_matches, id);
+        if values > 1 && values != count {
+            return Err(RemoteHostError::PerHostArgCount(id, values, count));
+        }
+    }
+    Ok(())
+}
+
+/// Get all remote hosts in the order of the `upstream` argument, if it is given, or of the
+/// `remote-host` argument.
+pub async fn get_remote_hosts(
+    arg_matches: &ArgMatches,
+) -> Result<Vec<RemoteHost>, RemoteHostError> {
+    let id = if arg_matches.contains_id("upstream") {
+        "upstream"
+    } else {
+        "remote-host"
+    };
+    let count = arg_matches
+        .get_many::<String>(id)
+        .map(|remote_hosts| remote_hosts.count())
+        .unwrap_or_default();
+    check_per_host_args(arg_matches, count)?;
+    let mut remote_hosts = Vec::with_capacity(count);
+    for index in 0..count {
+        remote_hosts.push(get_remote_host(arg_matches, index).await?);
+    }
+    Ok(remote_hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_remote_hosts, parse_host_port, RemoteHostError};
+    use crate::{get_command, RemoteHost};
+
+    #[test]
+    fn test_parse_host_port() {
+        assert_eq!(parse_host_port("1.1.1.1:443").unwrap(), ("1.1.1.1", 443));
+        assert_eq!(
+            parse_host_port("dns.example:8443").unwrap(),
+            ("dns.example", 8443)
+        );
+        assert_eq!(
+            parse_host_port("[2606:4700:4700::1111]:443").unwrap(),
+            ("2606:4700:4700::1111", 443)
+        );
+    }
+
+    #[test]
+    fn test_parse_host_port_invalid() {
+        assert!(parse_host_port("1.1.1.1").is_err());
+        assert!(parse_host_port(":443").is_err());
+        assert!(parse_host_port("2606:4700:4700::1111:443").is_err());
+        assert!(parse_host_port("[2606:4700:4700::1111]").is_err());
+        assert!(parse_host_port("[2606:4700:4700::1111]443").is_err());
+        assert!(parse_host_port("[dns.example]:443").is_err());
+        assert!(parse_host_port("dns.example:port").is_err());
+    }
+
+    #[cfg(feature = "socks5")]
+    #[tokio::test]
+    async fn test_get_remote_hosts_per_host_args() {
+        let arg_matches = get_command()
+            .try_get_matches_from([
+                "doh-client",
+                "--remote-host",
+                "1.1.1.1:443",
+                "--remote-host",
+                "8.8.8.8:443",
+                "--remote-host",
+                "9.9.9.9:443",
+                "--proxy-scheme",
+                "socks5h",
+                "--proxy-scheme",
+                "none",
+                "--proxy-scheme",
+                "none",
+                "--proxy-host",
+                "127.0.0.1:1080",
+                "/path/to/the/ca/file.pem",
+            ])
+            .unwrap();
+        let remote_hosts = get_remote_hosts(&arg_matches).await.unwrap();
+        assert!(matches!(remote_hosts[0], RemoteHost::Socks5h(..)));
+        assert!(matches!(remote_hosts[1], RemoteHost::Direct(..)));
+        assert!(matches!(remote_hosts[2], RemoteHost::Direct(..)));
+    }
+
+    #[cfg(feature = "socks5")]
+    #[tokio::test]
+    async fn test_get_remote_hosts_socks5() {
+        // The domain name is resolved when the remote host is connected.
+        let arg_matches = get_command()
+            .try_get_matches_from([
+                "doh-client",
+                "--remote-host",
+                "doh.invalid:443",
+                "--proxy-scheme",
+                "socks5",
+                "--proxy-host",
+                "127.0.0.1:1080",
+                "/path/to/the/ca/file.pem",
+            ])
+            .unwrap();
+        let remote_hosts = get_remote_hosts(&arg_matches).await.unwrap();
+        match &remote_hosts[0] {
+            RemoteHost::Socks5(_, _, _, remote_host, remote_port) => {
+                assert_eq!(remote_host, "doh.invalid");
+                assert_eq!(*remote_port, 443);
+            }
+            _ => panic!("remote host is not connected via socks5"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_remote_hosts_per_host_args_mismatch() {
+        let arg_matches = get_command()
+            .try_get_matches_from([
+                "doh-client",
+                "--remote-host",
+                "1.1.1.1:443",
+                "--remote-host",
+                "8.8.8.8:443",
+                "--remote-host",
+                "9.9.9.9:443",
+                "--domain",
+                "one.one.one.one",
+                "--domain",
+                "dns.google",
+                "/path/to/the/ca/file.pem",
+            ])
+            .unwrap();
+        assert!(matches!(
+            get_remote_hosts(&arg_matches).await,
+            Err(RemoteHostError::PerHostArgCount("domain", 2, 3))
+        ));
     }
 }
@@ -1,22 +1,68 @@
+#[cfg(feature = "odoh")]
+use crate::remote::{parse_odoh_configs, OdohTarget};
+#[cfg(feature = "odoh")]
+use crate::stamp::parse_odoh_target;
 use crate::{
+    cmd::{get_ca_certs, get_nth_or_last},
     context::Context,
-    helper::{load_certs, load_private_key, load_root_store},
-    listen::Config as ListenConfig,
-    remote::{Host as RemoteHost, Session as RemoteSession},
-    {get_listen_config, get_remote_host, Cache, DohError, DohResult},
+    helper::{load_certs, load_private_key, load_root_store, CaCerts},
+    listen::{Config as ListenConfig, Listener},
+    remote::{
+        parse_ech_config_list, Ech, HedgeDelay, Host as RemoteHost, Session as RemoteSession,
+        Strategy, Transport, Upstreams,
+    },
+    stamp::{is_stamp, Stamp},
+    verifier::{parse_spki_hash, PinVerifier},
+    {
+        get_bootstrap_dns, get_listen_config, get_remote_hosts, Cache, DohError, DohResult,
+        Resolver, UriTemplate,
+    },
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use clap::ArgMatches;
 use futures::lock::Mutex;
-use std::{io::Result as IoResult, num::NonZeroUsize, sync::Arc};
-use tokio::net::UdpSocket;
+use std::{io::Result as IoResult, net::SocketAddr, num::NonZeroUsize, sync::Arc, time::Duration};
+use tokio_rustls::rustls::client::Resumption;
 use tokio_rustls::rustls::ClientConfig;
 
+/// The default maximum number of HTTP/1.1 or HTTP/2 connections to a remote host.
+const DEFAULT_MAX_CONNECTIONS: usize = 4;
+
+/// The default interval of the PINGs on idle HTTP/2 connections.
+const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// The default time after that idle HTTP/2 connections are closed.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The default number of attempts to connect to a remote host.
+const DEFAULT_RETRIES: u32 = 3;
+
+/// The default time, for that a failed remote host is tried last.
+const DEFAULT_FAILBACK: Duration = Duration::from_secs(60);
+
+/// The default time after that a query to a remote host times out.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The default maximum number of cached responses.
+const DEFAULT_CACHE_SIZE: usize = 1024;
+
+/// The number of servers, whose TLS sessions are stored to resume them.
+const TLS_SESSIONS: usize = 16;
+
+fn create_verifier(ca_certs: &CaCerts, cert_hashes: Vec<Vec<u8>>) -> DohResult<Arc<PinVerifier>> {
+    let root_store = load_root_store(ca_certs)?;
+    let verifier = PinVerifier::new(root_store, cert_hashes, Vec::new(), false)?;
+    Ok(Arc::new(verifier))
+}
+
 fn create_client_config(
-    cafile: Option<&String>,
-    client_auth: Option<(&String, &String)>,
+    verifier: Arc<PinVerifier>,
+    client_auth: Option<(&str, &str)>,
+    transport: Transport,
 ) -> DohResult<ClientConfig> {
-    let root_store = load_root_store(cafile)?;
-    let config_builder = ClientConfig::builder().with_root_certificates(root_store);
+    let config_builder = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
     let mut config = if let Some((certs, key)) = client_auth {
         let cert_chain = load_certs(certs)?;
         let key_der = load_private_key(key)?;
@@ -24,91 +70,563 @@ fn create_client_config(
     } else {
         config_builder.with_no_client_auth()
     };
-    config.alpn_protocols.push(vec![104, 50]); // h2
+    config.alpn_protocols = transport.alpn_protocols();
+    // The config is shared by all connections of the remote host, so a reconnect resumes the TLS
+    // session with a ticket of the store instead of a full handshake.
+    config.resumption = Resumption::in_memory_sessions(TLS_SESSIONS);
     Ok(config)
 }
 
-/// The configuration object for the `doh-client`.
-pub struct Config {
-    listen_config: ListenConfig,
+/// The configuration object for one remote DoH server.
+pub struct UpstreamConfig {
     remote_host: RemoteHost,
     domain: String,
     client_config: Arc<ClientConfig>,
+    /// The verifier of the client config, which is used for the ECH client config too.
+    verifier: Arc<PinVerifier>,
+    /// The certificate hashes of the DNS stamp, which are kept if public keys are pinned too.
+    cert_hashes: Vec<Vec<u8>>,
+    /// The ECH configs, if they are not fetched from the HTTPS record, and the fallback.
+    ech: Option<(Option<Vec<u8>>, bool)>,
     uri: String,
-    retries: u32,
-    timeout: u64,
-    post: bool,
-    cache_size: usize,
-    cache_fallback: bool,
+    /// `false` if the URI template of the server has no `dns` variable, so POST is always used.
+    get_supported: bool,
+    weight: u32,
+    transport: Transport,
+    max_connections: usize,
+    keepalive: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    #[cfg(feature = "odoh")]
+    odoh_target: Option<OdohTarget>,
 }
 
-impl Config {
-    /// Create a new `doh_client::Config` object.
+impl UpstreamConfig {
+    /// Create a new `doh_client::UpstreamConfig` object, which sends the queries over HTTPS to
+    /// `https://{domain}/{path}`. The `ca_certs` are a `CaCerts` object or the optional path of a
+    /// PEM file, which replaces the native certificate store.
     pub fn new(
-        listen_config: ListenConfig,
         remote_host: RemoteHost,
         domain: &str,
-        cafile: Option<&String>,
-        client_auth: Option<(&String, &String)>,
+        ca_certs: impl Into<CaCerts>,
         path: &str,
-        retries: u32,
-        timeout: u64,
-        post: bool,
-        cache_size: usize,
-        cache_fallback: bool,
-    ) -> DohResult<Config> {
-        let client_config = create_client_config(cafile, client_auth)?;
-
+    ) -> DohResult<UpstreamConfig> {
         let uri = format!("https://{}/{}", domain, path);
+        UpstreamConfig::with_uri(
+            remote_host,
+            domain,
+            &ca_certs.into(),
+            Vec::new(),
+            uri,
+            Transport::Http2,
+        )
+    }
 
-        if cache_fallback && cache_size == 0 {
-            return Err(DohError::CacheSize);
+    /// Create a new `doh_client::UpstreamConfig` object from the URI template of the DoH server
+    /// (see RFC 8484 section 4.1), e.g. `https://dns.example:8443/dns-query{?dns}`. The host of
+    /// the template is used to verify the certificate of the server, but the `remote_host` is
+    /// connected. If the template has no `dns` variable, then the queries are always sent with
+    /// POST.
+    pub fn from_uri_template(
+        remote_host: RemoteHost,
+        uri_template: &str,
+        ca_certs: impl Into<CaCerts>,
+    ) -> DohResult<UpstreamConfig> {
+        let UriTemplate {
+            host,
+            uri,
+            get_supported,
+            ..
+        } = UriTemplate::parse(uri_template)
+            .ok_or_else(|| DohError::UriTemplate(uri_template.to_owned()))?;
+        let mut upstream_config = UpstreamConfig::with_uri(
+            remote_host,
+            &host,
+            &ca_certs.into(),
+            Vec::new(),
+            uri,
+            Transport::Http2,
+        )?;
+        upstream_config.get_supported = get_supported;
+        Ok(upstream_config)
+    }
+
+    /// Create a new `doh_client::UpstreamConfig` object from the DNS stamp (`sdns://`) of a DoH,
+    /// DNS over TLS, DNS over QUIC server or an Oblivious DoH relay. The stamp determines the
+    /// domain, the path and the transport. If the stamp contains certificate hashes, then the
+    /// chain of the server has to contain one of these certificates too.
+    pub fn from_stamp(
+        remote_host: RemoteHost,
+        stamp: &str,
+        ca_certs: impl Into<CaCerts>,
+    ) -> DohResult<UpstreamConfig> {
+        let Stamp {
+            domain,
+            uri,
+            transport,
+            cert_hashes,
+            ..
+        } = Stamp::parse(stamp).ok_or_else(|| DohError::Stamp(stamp.to_owned()))?;
+        UpstreamConfig::with_uri(
+            remote_host,
+            &domain,
+            &ca_certs.into(),
+            cert_hashes,
+            uri,
+            transport,
+        )
+    }
+
+    fn with_uri(
+        remote_host: RemoteHost,
+        domain: &str,
+        ca_certs: &CaCerts,
+        cert_hashes: Vec<Vec<u8>>,
+        uri: String,
+        transport: Transport,
+    ) -> DohResult<UpstreamConfig> {
+        #[cfg(any(feature = "http3", feature = "doq"))]
+        if transport.is_quic() && !remote_host.is_direct() {
+            return Err(DohError::QuicProxy);
         }
 
-        Ok(Config {
-            listen_config,
+        let verifier = create_verifier(ca_certs, cert_hashes.clone())?;
+        let client_config = create_client_config(verifier.clone(), None, transport)?;
+
+        Ok(UpstreamConfig {
             remote_host,
             domain: domain.to_string(),
             client_config: Arc::new(client_config),
+            verifier,
+            cert_hashes,
+            ech: None,
             uri,
-            retries,
-            timeout,
-            post,
-            cache_size,
-            cache_fallback,
+            get_supported: true,
+            weight: 1,
+            transport,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            keepalive: Some(DEFAULT_KEEPALIVE),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            #[cfg(feature = "odoh")]
+            odoh_target: None,
+        })
+    }
+
+    /// Set the weight of the remote host, which is only used by the `Strategy::Weighted`. The
+    /// default is 1.
+    pub fn with_weight(mut self, weight: u32) -> UpstreamConfig {
+        self.weight = weight;
+        self
+    }
+
+    /// Set the transport of the queries. The default is HTTPS (HTTP/2 or HTTP/1.1). HTTP/3 and DNS
+    /// over QUIC are only supported if the remote host is connected without a proxy and without
+    /// ECH.
+    pub fn with_transport(mut self, transport: Transport) -> DohResult<UpstreamConfig> {
+        #[cfg(any(feature = "http3", feature = "doq"))]
+        if transport.is_quic() {
+            if !self.remote_host.is_direct() {
+                return Err(DohError::QuicProxy);
+            }
+            if self.ech.is_some() {
+                return Err(DohError::EchQuic);
+            }
+        }
+        #[cfg(feature = "odoh")]
+        if self.odoh_target.is_some() && !transport.is_http() {
+            return Err(DohError::OdohTransport);
+        }
+        Arc::make_mut(&mut self.client_config).alpn_protocols = transport.alpn_protocols();
+        self.transport = transport;
+        Ok(self)
+    }
+
+    /// Authenticate the client with the certificate chain of the PEM file `certs` and its private
+    /// key of the PEM file `key` (mutual TLS).
+    pub fn with_client_auth(mut self, certs: &str, key: &str) -> DohResult<UpstreamConfig> {
+        let mut client_config =
+            create_client_config(self.verifier.clone(), Some((certs, key)), self.transport)?;
+        client_config.enable_early_data = self.client_config.enable_early_data;
+        self.client_config = Arc::new(client_config);
+        Ok(self)
+    }
+
+    /// Set the maximum number of HTTP/1.1 or HTTP/2 connections to the remote host. Another
+    /// connection is opened, if all HTTP/1.1 connections are busy or all HTTP/2 connections reached
+    /// the maximum number of concurrent streams of the server. The default is 4.
+    pub fn with_max_connections(mut self, max_connections: NonZeroUsize) -> UpstreamConfig {
+        self.max_connections = max_connections.get();
+        self
+    }
+
+    /// Set the interval of the PINGs on idle HTTP/2 connections. If the PONG is not received
+    /// within the interval, then the connection is closed and the remote host is connected again
+    /// before the next query. `None` disables the PINGs. The default is 30 seconds.
+    pub fn with_keepalive(mut self, keepalive: Option<Duration>) -> UpstreamConfig {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Set the time after that idle HTTP/1.1 and HTTP/2 connections are closed, the last HTTP/1.1
+    /// connection is kept open until the server closes it. `None` keeps them open. The default is
+    /// 300 seconds.
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> UpstreamConfig {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Send the first request of a resumed TLS session as TLS 1.3 early data (0-RTT), which saves a
+    /// round trip on reconnects. The DNS queries can be replayed safely. Early data is only used
+    /// over TCP and over HTTPS only, if the resumed session used HTTP/2, because the first request
+    /// is sent before the protocol is negotiated.
+    pub fn with_early_data(mut self) -> UpstreamConfig {
+        Arc::make_mut(&mut self.client_config).enable_early_data = true;
+        self
+    }
+
+    /// Pin the public keys of the remote host. `spki_hashes` are the SHA256 hashes of the
+    /// `subjectPublicKeyInfo` of the allowed server certificates, so a new key can be pinned before
+    /// the certificate is rotated. The certificate is still verified with the CA certificates of
+    /// `ca_certs`, unless `pin_only` is `true`, which allows self-signed certificates of internal
+    /// servers.
+    pub fn with_spki_pins(
+        mut self,
+        ca_certs: impl Into<CaCerts>,
+        spki_hashes: Vec<Vec<u8>>,
+        pin_only: bool,
+    ) -> DohResult<UpstreamConfig> {
+        if spki_hashes.is_empty() {
+            return if pin_only {
+                Err(DohError::PinOnly)
+            } else {
+                Ok(self)
+            };
+        }
+        let root_store = load_root_store(&ca_certs.into())?;
+        let verifier =
+            PinVerifier::new(root_store, self.cert_hashes.clone(), spki_hashes, pin_only)?;
+        self.verifier = Arc::new(verifier);
+        Arc::make_mut(&mut self.client_config)
+            .dangerous()
+            .set_certificate_verifier(self.verifier.clone());
+        Ok(self)
+    }
+
+    /// Encrypt the client hello of the TLS connections with Encrypted Client Hello (ECH), so the
+    /// domain is not sent in cleartext, but the public name of the ECH configs. `config_list` are
+    /// the ECH configs (`ECHConfigList`) of the server, otherwise they are fetched from the HTTPS
+    /// record of the domain with the bootstrap DNS servers before connecting. If the server rejects
+    /// ECH with retry configs, then it is connected again with them. If no ECH configs are
+    /// available or the server rejects ECH without retry configs, then the domain is sent in
+    /// cleartext, if `fallback` is `true`, otherwise the connection fails. ECH needs TLS 1.3 and
+    /// is not supported over QUIC.
+    pub fn with_ech(
+        mut self,
+        config_list: Option<Vec<u8>>,
+        fallback: bool,
+    ) -> DohResult<UpstreamConfig> {
+        #[cfg(any(feature = "http3", feature = "doq"))]
+        if self.transport.is_quic() {
+            return Err(DohError::EchQuic);
+        }
+        if let Some(config_list) = &config_list {
+            parse_ech_config_list(config_list)?;
+        }
+        self.ech.replace((config_list, fallback));
+        Ok(self)
+    }
+
+    /// Send the queries through the remote host as relay to the Oblivious DoH target (see
+    /// RFC 9230) `target`, which is `Host[:Port][/Path]`. The ODoH config is fetched from the
+    /// target over the proxy of the remote host and its certificate is checked with the CA
+    /// certificates of `ca_certs`.
+    #[cfg(feature = "odoh")]
+    pub fn with_odoh_target(
+        mut self,
+        target: &str,
+        ca_certs: impl Into<CaCerts>,
+    ) -> DohResult<UpstreamConfig> {
+        if !self.transport.is_http() {
+            return Err(DohError::OdohTransport);
+        }
+        let verifier = create_verifier(&ca_certs.into(), Vec::new())?;
+        let client_config = create_client_config(verifier, None, Transport::Http2)?;
+        let odoh_target = if is_stamp(target) {
+            let target =
+                parse_odoh_target(target).ok_or_else(|| DohError::OdohTarget(target.to_owned()))?;
+            OdohTarget::new(&target, Arc::new(client_config))?
+        } else {
+            OdohTarget::new(target, Arc::new(client_config))?
+        };
+        self.odoh_target.replace(odoh_target);
+        Ok(self)
+    }
+
+    /// Use the ODoH configs `configs` (see RFC 9230 section 6) of the Oblivious DoH target instead
+    /// of fetching them from the target, so the target never sees the address of the client.
+    #[cfg(feature = "odoh")]
+    pub fn with_odoh_config(mut self, configs: &[u8]) -> DohResult<UpstreamConfig> {
+        let odoh_target = self
+            .odoh_target
+            .as_mut()
+            .ok_or(DohError::OdohConfigWithoutTarget)?;
+        let config = parse_odoh_configs(bytes::Bytes::copy_from_slice(configs))?;
+        odoh_target.set_config(config);
+        Ok(self)
+    }
+
+    fn try_from(
+        matches: &ArgMatches,
+        remote_host: RemoteHost,
+        index: usize,
+    ) -> DohResult<UpstreamConfig> {
+        let ca_certs = get_ca_certs(matches, matches.get_one::<String>("cafile"));
+        let weight = *get_nth_or_last::<u32>(matches, "weight", index).unwrap_or(&1);
+        let max_connections = get_nth_or_last::<NonZeroUsize>(matches, "max-connections", index);
+        let keepalive = get_nth_or_last::<u64>(matches, "keepalive", index);
+        let idle_timeout = get_nth_or_last::<u64>(matches, "idle-timeout", index);
+        let transport = match get_nth_or_last::<String>(matches, "transport", index) {
+            #[cfg(feature = "http3")]
+            Some(transport) if transport == "h3" => Transport::Http3,
+            Some(transport) if transport == "dot" => Transport::Dot,
+            #[cfg(feature = "doq")]
+            Some(transport) if transport == "doq" => Transport::Doq,
+            _ => Transport::Http2,
+        };
+        let remote_host_arg = get_nth_or_last::<String>(matches, "remote-host", index);
+        let upstream = if let Some(stamp) = remote_host_arg.filter(|value| is_stamp(value)) {
+            // The stamp determines the transport.
+            UpstreamConfig::from_stamp(remote_host, stamp, &ca_certs)?
+        } else if let Some(uri_template) = get_nth_or_last::<String>(matches, "upstream", index) {
+            UpstreamConfig::from_uri_template(remote_host, uri_template, &ca_certs)?
+                .with_transport(transport)?
+        } else {
+            let domain = get_nth_or_last::<String>(matches, "domain", index).unwrap();
+            let path = get_nth_or_last::<String>(matches, "path", index).unwrap();
+            UpstreamConfig::new(remote_host, domain, &ca_certs, path)?.with_transport(transport)?
+        };
+        let upstream = upstream.with_weight(weight);
+        let upstream = match get_nth_or_last::<String>(matches, "client-auth-certs", index) {
+            Some(certs) => {
+                let key = get_nth_or_last::<String>(matches, "client-auth-key", index).unwrap();
+                upstream.with_client_auth(certs, key)?
+            }
+            None => upstream,
+        };
+        let upstream = match get_nth_or_last::<String>(matches, "pin-sha256", index) {
+            Some(pins) => {
+                let spki_hashes = pins
+                    .split(',')
+                    .map(|pin| {
+                        parse_spki_hash(pin).ok_or_else(|| DohError::SpkiPin(pin.to_owned()))
+                    })
+                    .collect::<DohResult<Vec<_>>>()?;
+                upstream.with_spki_pins(&ca_certs, spki_hashes, matches.get_flag("pin-only"))?
+            }
+            None => upstream,
+        };
+        let upstream = match get_nth_or_last::<String>(matches, "ech-config", index) {
+            Some(config_list) => {
+                let config_list = if config_list == "dns" {
+                    None
+                } else {
+                    let config_list = STANDARD
+                        .decode(config_list)
+                        .map_err(|_| DohError::EchConfig(config_list.to_owned()))?;
+                    Some(config_list)
+                };
+                upstream.with_ech(config_list, matches.get_flag("ech-fallback"))?
+            }
+            None => upstream,
+        };
+        let upstream = match max_connections {
+            Some(max_connections) => upstream.with_max_connections(*max_connections),
+            None => upstream,
+        };
+        let upstream = if get_nth_or_last::<bool>(matches, "tls-early-data", index) == Some(&true) {
+            upstream.with_early_data()
+        } else {
+            upstream
+        };
+        // A value of 0 seconds disables the PINGs or the idle timeout.
+        let seconds = |seconds: &u64| Some(Duration::from_secs(*seconds)).filter(|d| !d.is_zero());
+        let upstream = match keepalive {
+            Some(keepalive) => upstream.with_keepalive(seconds(keepalive)),
+            None => upstream,
+        };
+        let upstream = match idle_timeout {
+            Some(idle_timeout) => upstream.with_idle_timeout(seconds(idle_timeout)),
+            None => upstream,
+        };
+        #[cfg(feature = "odoh")]
+        if let Some(target) = get_nth_or_last::<String>(matches, "odoh-target", index) {
+            let upstream = upstream.with_odoh_target(target, &ca_certs)?;
+            return match get_nth_or_last::<String>(matches, "odoh-config", index) {
+                Some(configs) => {
+                    let configs = STANDARD
+                        .decode(configs)
+                        .map_err(|_| DohError::OdohConfigBase64(configs.to_owned()))?;
+                    upstream.with_odoh_config(&configs)
+                }
+                None => Ok(upstream),
+            };
+        }
+        Ok(upstream)
+    }
+}
+
+/// The configuration object for the `doh-client`.
+pub struct Config {
+    listen_config: ListenConfig,
+    listen_tcp: bool,
+    upstreams: Vec<UpstreamConfig>,
+    bootstrap_dns: Vec<SocketAddr>,
+    strategy: Strategy,
+    retries: u32,
+    failback: Duration,
+    timeout: Duration,
+    hedge_delay: Option<HedgeDelay>,
+    post: bool,
+    cache_size: usize,
+    cache_fallback: bool,
+}
+
+impl Config {
+    /// Create a new `doh_client::Config` object, which listens on `listen_config` over UDP and
+    /// sends the queries to the `upstreams` with the `Strategy::Failover`.
+    pub fn new(listen_config: ListenConfig, upstreams: Vec<UpstreamConfig>) -> DohResult<Config> {
+        if upstreams.is_empty() {
+            return Err(DohError::NoUpstream);
+        }
+        if let ListenConfig::Addrs(socket_addrs) = &listen_config {
+            if socket_addrs.is_empty() {
+                return Err(DohError::NoListener);
+            }
+        }
+
+        Ok(Config {
+            listen_config,
+            listen_tcp: false,
+            upstreams,
+            bootstrap_dns: Vec::new(),
+            strategy: Strategy::Failover,
+            retries: DEFAULT_RETRIES,
+            failback: DEFAULT_FAILBACK,
+            timeout: DEFAULT_TIMEOUT,
+            hedge_delay: None,
+            post: true,
+            cache_size: DEFAULT_CACHE_SIZE,
+            cache_fallback: false,
         })
     }
 
+    /// Listen over TCP too, if `listen_tcp` is `true`. The default is `false`.
+    pub fn with_listen_tcp(mut self, listen_tcp: bool) -> Config {
+        self.listen_tcp = listen_tcp;
+        self
+    }
+
+    /// Resolve the domain names of the upstreams with the DNS servers `bootstrap_dns` instead of
+    /// the system resolver.
+    pub fn with_bootstrap_dns(mut self, bootstrap_dns: Vec<SocketAddr>) -> Config {
+        self.bootstrap_dns = bootstrap_dns;
+        self
+    }
+
+    /// Select the upstream for each query with the `strategy`, a failed upstream is tried last for
+    /// `failback`. The default is `Strategy::Failover` and 60 seconds.
+    pub fn with_strategy(mut self, strategy: Strategy, failback: Duration) -> Config {
+        self.strategy = strategy;
+        self.failback = failback;
+        self
+    }
+
+    /// Set the number of attempts to connect to an upstream. The default is 3.
+    pub fn with_retries(mut self, retries: u32) -> Config {
+        self.retries = retries;
+        self
+    }
+
+    /// Set the time after that a query times out. The upstreams, which are tried in turn, share
+    /// this time: each one gets an equal part of the remaining time, so a query takes at most the
+    /// timeout in total. The default is 2 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Config {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send the query to the next upstream too, if the upstream has not answered within the
+    /// `hedge_delay`, and use the first answer.
+    pub fn with_hedge_delay(mut self, hedge_delay: HedgeDelay) -> Config {
+        self.hedge_delay.replace(hedge_delay);
+        self
+    }
+
+    /// Send the queries with the GET method instead of POST.
+    pub fn with_get(mut self) -> Config {
+        self.post = false;
+        self
+    }
+
+    /// Set the maximum number of cached responses, 0 disables the cache. If `cache_fallback` is
+    /// `true`, then an expired response is used, if no upstream answers. The default is 1024 and
+    /// `false`.
+    pub fn with_cache(mut self, cache_size: usize, cache_fallback: bool) -> DohResult<Config> {
+        if cache_fallback && cache_size == 0 {
+            return Err(DohError::CacheSize);
+        }
+        self.cache_size = cache_size;
+        self.cache_fallback = cache_fallback;
+        Ok(self)
+    }
+
     pub async fn try_from(matches: ArgMatches) -> DohResult<Config> {
         let listen_config = get_listen_config(&matches)?;
-        let remote_host = get_remote_host(&matches).await?;
-        let domain = matches.get_one::<String>("domain").unwrap();
-        let cafile = matches.get_one::<String>("cafile");
-        let client_auth = matches
-            .get_one::<String>("client-auth-certs")
-            .map(|certs| (certs, matches.get_one::<String>("client-auth-key").unwrap()));
-        let path = matches.get_one::<String>("path").unwrap();
-        let retries: u32 = *matches.get_one::<u32>("retries").unwrap_or(&3);
-        let timeout: u64 = *matches.get_one::<u64>("timeout").unwrap_or(&2);
-        let post: bool = !matches.get_flag("get");
-        let cache_size: usize = *matches.get_one::<usize>("cache-size").unwrap_or(&1024);
-        let cache_fallback: bool = matches.get_flag("cache-fallback");
-        Config::new(
-            listen_config,
-            remote_host,
-            domain,
-            cafile,
-            client_auth,
-            path,
-            retries,
-            timeout,
-            post,
-            cache_size,
-            cache_fallback,
-        )
+        let listen_tcp = matches.get_flag("listen-tcp");
+        let remote_hosts = get_remote_hosts(&matches).await?;
+        let mut upstreams = Vec::with_capacity(remote_hosts.len());
+        for (index, remote_host) in remote_hosts.into_iter().enumerate() {
+            upstreams.push(UpstreamConfig::try_from(&matches, remote_host, index)?);
+        }
+        let bootstrap_dns = get_bootstrap_dns(&matches);
+        let strategy = match matches.get_one::<String>("strategy").map(String::as_str) {
+            Some("round-robin") => Strategy::RoundRobin,
+            Some("random") => Strategy::Random,
+            Some("weighted") => Strategy::Weighted,
+            Some("fastest") => Strategy::Fastest,
+            _ => Strategy::Failover,
+        };
+        let failback = match matches.get_one::<u64>("failback") {
+            Some(failback) => Duration::from_secs(*failback),
+            None => DEFAULT_FAILBACK,
+        };
+        let config = Config::new(listen_config, upstreams)?
+            .with_listen_tcp(listen_tcp)
+            .with_bootstrap_dns(bootstrap_dns)
+            .with_strategy(strategy, failback);
+        let config = match matches.get_one::<u32>("retries") {
+            Some(retries) => config.with_retries(*retries),
+            None => config,
+        };
+        let config = match matches.get_one::<u64>("timeout") {
+            Some(timeout) => config.with_timeout(Duration::from_secs(*timeout)),
+            None => config,
+        };
+        let config = match matches.get_one::<HedgeDelay>("hedge-delay") {
+            Some(hedge_delay) => config.with_hedge_delay(*hedge_delay),
+            None => config,
+        };
+        let config = if matches.get_flag("get") {
+            config.with_get()
+        } else {
+            config
+        };
+        let cache_size = *matches
+            .get_one::<usize>("cache-size")
+            .unwrap_or(&DEFAULT_CACHE_SIZE);
+        config.with_cache(cache_size, matches.get_flag("cache-fallback"))
     }
 
-    pub(crate) async fn into(self) -> IoResult<(Arc<UdpSocket>, Context)> {
+    pub(crate) async fn into(self) -> IoResult<(Vec<Listener>, Context)> {
         let cache = if self.cache_size == 0 {
             None
         } else {
@@ -117,23 +635,65 @@ impl Config {
         };
         let cache_fallback = self.cache_fallback;
         let timeout = self.timeout;
-        let socket = self.listen_config.into_socket().await?;
-        let socket = Arc::new(socket);
-        let remote_session = RemoteSession::new(
-            self.remote_host,
-            self.domain,
-            self.client_config,
-            self.uri,
-            self.retries,
-            self.post,
-        );
-        let context = Context::new(
-            cache,
-            cache_fallback,
-            timeout,
-            remote_session,
-            socket.clone(),
-        );
-        Ok((socket, context))
+        let hedge_delay = self.hedge_delay;
+        let listeners = self.listen_config.into_listeners(self.listen_tcp).await?;
+        let resolver = Arc::new(Resolver::new(self.bootstrap_dns));
+        let remote_sessions = self
+            .upstreams
+            .into_iter()
+            .map(|upstream| {
+                #[allow(unused_mut)]
+                let mut remote_session = RemoteSession::new(
+                    upstream.remote_host,
+                    resolver.clone(),
+                    upstream.domain,
+                    upstream.client_config,
+                    upstream.uri,
+                    self.retries,
+                    self.post || !upstream.get_supported,
+                    upstream.transport,
+                    upstream.max_connections,
+                    upstream.keepalive,
+                    upstream.idle_timeout,
+                );
+                if let Some((config_list, fallback)) = upstream.ech {
+                    let ech = Ech::new(config_list, fallback, upstream.verifier);
+                    remote_session.set_ech(ech);
+                }
+                #[cfg(feature = "odoh")]
+                if let Some(odoh_target) = upstream.odoh_target {
+                    remote_session.set_odoh_target(odoh_target);
+                }
+                (remote_session, upstream.weight)
+            })
+            .collect();
+        let upstreams = Upstreams::new(remote_sessions, self.strategy, self.failback);
+        let context = Context::new(cache, cache_fallback, timeout, hedge_delay, upstreams);
+        Ok((listeners, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, UpstreamConfig};
+    use crate::{run, CaCerts, DohError, ListenConfig, RemoteHost};
+
+    fn create_upstream() -> UpstreamConfig {
+        let remote_host = RemoteHost::Direct("127.0.0.1".to_owned(), 443);
+        UpstreamConfig::new(remote_host, "dns.test", CaCerts::native(), "dns-query").unwrap()
+    }
+
+    #[test]
+    fn test_new_without_listen_addrs() {
+        let config = Config::new(ListenConfig::Addrs(Vec::new()), vec![create_upstream()]);
+        assert!(matches!(config, Err(DohError::NoListener)));
+    }
+
+    #[tokio::test]
+    async fn test_run_without_listeners() {
+        let listen_config = ListenConfig::Addrs(vec!["127.0.0.1:0".parse().unwrap()]);
+        let mut config = Config::new(listen_config, vec![create_upstream()]).unwrap();
+        config.listen_config = ListenConfig::Addrs(Vec::new());
+        assert!(matches!(run(config).await, Err(DohError::NoListener)));
     }
 }
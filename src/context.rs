@@ -1,19 +1,17 @@
-use crate::remote::Session as RemoteSession;
+use crate::remote::{HedgeDelay, Upstreams};
 use crate::Cache;
 use dns_message_parser::question::Question;
 use dns_message_parser::Dns;
 use futures::lock::Mutex;
-use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::UdpSocket;
 
 /// The context object for a running instance.
 pub struct Context {
-    pub(crate) sender: Arc<UdpSocket>,
-    pub(crate) remote_session: Mutex<RemoteSession>,
+    pub(crate) upstreams: Upstreams,
     pub(crate) cache: Option<Mutex<Cache<Question, Dns>>>,
     pub(super) cache_fallback: bool,
     pub(crate) timeout: Duration,
+    pub(crate) hedge_delay: Option<HedgeDelay>,
 }
 
 impl Context {
@@ -21,16 +19,16 @@ impl Context {
     pub(super) fn new(
         cache: Option<Mutex<Cache<Question, Dns>>>,
         cache_fallback: bool,
-        timeout: u64,
-        remote_session: RemoteSession,
-        sender: Arc<UdpSocket>,
+        timeout: Duration,
+        hedge_delay: Option<HedgeDelay>,
+        upstreams: Upstreams,
     ) -> Context {
         Context {
-            sender,
-            remote_session: Mutex::new(remote_session),
+            upstreams,
             cache,
             cache_fallback,
-            timeout: Duration::from_secs(timeout),
+            timeout,
+            hedge_delay,
         }
     }
 }
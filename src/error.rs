@@ -2,16 +2,20 @@ use crate::cmd::RemoteHostError;
 #[cfg(feature = "http-proxy")]
 use async_http_proxy::HttpError as HttpProxyError;
 use bytes::Bytes;
-use dns_message_parser::{DecodeError, Dns, EncodeError};
+use dns_message_parser::{DecodeError, Dns, EncodeError, Opcode, RCode};
 use futures::channel::mpsc::TrySendError;
 use h2::Error as H2Error;
+use h2::Reason;
 use http::{HeaderValue, StatusCode};
+use hyper::Error as HyperError;
+use rustls::client::VerifierBuilderError;
 use rustls::Error as RustlsError;
 use std::{
-    io::Error as IoError,
-    net::{AddrParseError, SocketAddr},
+    io::{Error as IoError, ErrorKind},
+    net::AddrParseError,
 };
 use thiserror::Error as ThisError;
+use tokio::task::JoinError;
 #[cfg(feature = "socks5")]
 use tokio_socks::Error as SocksError;
 
@@ -21,12 +25,68 @@ pub enum Error {
     Io(#[from] IoError),
     #[error("H2 Error: {0}")]
     H2(#[from] H2Error),
+    #[error("HTTP1 Error: {0}")]
+    Http1(#[from] HyperError),
+    #[cfg(feature = "http3")]
+    #[error("H3 Error: {0}")]
+    H3Connection(#[from] h3::error::ConnectionError),
+    #[cfg(feature = "http3")]
+    #[error("H3 Error: {0}")]
+    H3Stream(#[from] h3::error::StreamError),
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    #[error("QUIC Error: {0}")]
+    QuicConnect(#[from] quinn::ConnectError),
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    #[error("QUIC Error: {0}")]
+    QuicConnection(#[from] quinn::ConnectionError),
+    #[cfg(feature = "doq")]
+    #[error("QUIC Error: {0}")]
+    QuicWrite(#[from] quinn::WriteError),
+    #[cfg(feature = "doq")]
+    #[error("QUIC Error: {0}")]
+    QuicClosedStream(#[from] quinn::ClosedStream),
+    #[cfg(feature = "doq")]
+    #[error("QUIC Error: {0}")]
+    QuicRead(#[from] quinn::ReadToEndError),
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    #[error("QUIC TLS Error: {0}")]
+    QuicTls(#[from] quinn::crypto::rustls::NoInitialCipherSuite),
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    #[error("QUIC is not supported over a proxy")]
+    QuicProxy,
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    #[error("ECH is not supported over QUIC")]
+    EchQuic,
+    #[cfg(feature = "odoh")]
+    #[error("ODoH Error: {0}")]
+    Odoh(#[from] odoh_rs::Error),
+    #[cfg(feature = "odoh")]
+    #[error("Could not parse ODoH target: {0}")]
+    OdohTarget(String),
+    #[cfg(feature = "odoh")]
+    #[error("ODoH target has no supported config")]
+    OdohConfig,
+    #[cfg(feature = "odoh")]
+    #[error("ODoH is only supported over HTTP")]
+    OdohTransport,
+    #[cfg(feature = "odoh")]
+    #[error("Could not decode ODoH config: {0}")]
+    OdohConfigBase64(String),
+    #[cfg(feature = "odoh")]
+    #[error("ODoH config without ODoH target")]
+    OdohConfigWithoutTarget,
+    #[error("Could not resolve {0} with the bootstrap DNS servers")]
+    Bootstrap(String),
+    #[error("Bootstrap DNS server answered with {0:?}")]
+    BootstrapRCode(RCode),
+    #[error("Invalid domain name for the bootstrap DNS servers: {0}")]
+    BootstrapDomain(String),
     #[error("Decode Error: {0:?}")]
     Decode(#[from] DecodeError),
     #[error("Encode Error: {0:?}")]
     Encode(#[from] EncodeError),
     #[error("Could not send to the response handler: {0}")]
-    TrySend(#[from] TrySendError<(Bytes, SocketAddr)>),
+    TrySend(#[from] TrySendError<Bytes>),
     #[cfg(feature = "http-proxy")]
     #[error("HTTP Proxy Error: {0}")]
     HttpProxy(#[from] HttpProxyError),
@@ -39,6 +99,24 @@ pub enum Error {
     PEMParser,
     #[error("TLS error: {0}")]
     Rustls(#[from] RustlsError),
+    #[error("No remote host is given")]
+    NoUpstream,
+    #[error("No address or socket to listen on is given")]
+    NoListener,
+    #[error("Could not parse the URI template: {0}")]
+    UriTemplate(String),
+    #[error("Could not parse the DNS stamp: {0}")]
+    Stamp(String),
+    #[error("Could not create the certificate verifier: {0}")]
+    Verifier(#[from] VerifierBuilderError),
+    #[error("Could not parse the public key pin: {0}")]
+    SpkiPin(String),
+    #[error("The pin only mode needs at least one public key pin")]
+    PinOnly,
+    #[error("Could not parse the ECH configs: {0}")]
+    EchConfig(String),
+    #[error("No ECH configs are available for {0}")]
+    EchUnavailable(String),
     #[error("Cache size is zero and cache fallback is enabled simultaneously")]
     CacheSize,
     #[error("Could not connect to DoH server")]
@@ -55,12 +133,85 @@ pub enum Error {
     HeaderNoContentType,
     #[error("DNS packet is not a request: {0:?}")]
     DnsNotRequest(Dns),
+    #[error("DNS opcode is not implemented: {0:?}")]
+    DnsOpcode(Opcode),
+    #[error("DNS message is too big: {0} bytes")]
+    MessageSize(usize),
+    #[error("Too many pending DNS queries on the connection: {0}")]
+    PendingQueries(usize),
+    #[error("DNS message length does not match: got {0} expected {1}")]
+    MessageLength(usize, usize),
     #[error("DNS packet is not a response: {0:?}")]
     DnsNotResponse(Dns),
     #[error("Could not get listen config: {0}")]
     AddrParse(#[from] AddrParseError),
     #[error("Remote Error: {0}")]
     RemoteHost(#[from] RemoteHostError),
+    #[error("Task Error: {0}")]
+    Join(#[from] JoinError),
+}
+
+/// Returns `true` if the IO error means, that the connection was lost.
+fn is_connection_lost_io(e: &IoError) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::NotConnected
+    )
+}
+
+impl Error {
+    /// Returns `true` if the request failed, because the connection to the remote host was lost,
+    /// e.g. by a GOAWAY, a refused stream or a broken pipe. The server has not answered such a
+    /// request, so it can be sent again over a new connection. Errors of the server, e.g. an error
+    /// status, are not retried.
+    pub(crate) fn is_connection_lost(&self) -> bool {
+        match self {
+            Error::Io(e) => is_connection_lost_io(e),
+            Error::H2(e) => {
+                e.is_go_away()
+                    || e.reason() == Some(Reason::REFUSED_STREAM)
+                    || e.get_io().is_some_and(is_connection_lost_io)
+            }
+            Error::Http1(e) => e.is_canceled() || e.is_closed() || e.is_incomplete_message(),
+            #[cfg(feature = "http3")]
+            Error::H3Connection(_) => true,
+            #[cfg(feature = "http3")]
+            Error::H3Stream(e) => matches!(
+                e,
+                h3::error::StreamError::ConnectionError { .. }
+                    | h3::error::StreamError::RemoteClosing { .. }
+            ),
+            #[cfg(any(feature = "http3", feature = "doq"))]
+            Error::QuicConnection(_) => true,
+            #[cfg(feature = "doq")]
+            Error::QuicWrite(quinn::WriteError::ConnectionLost(_)) => true,
+            #[cfg(feature = "doq")]
+            Error::QuicRead(quinn::ReadToEndError::Read(quinn::ReadError::ConnectionLost(_))) => {
+                true
+            }
+            Error::IsNotConnected => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the ODoH config of the target could be outdated, because the response
+    /// could not be decrypted or the target rejected the query with a client error, e.g. after
+    /// it rotated its key (see RFC 9230 section 4.3).
+    #[cfg(feature = "odoh")]
+    pub(crate) fn is_odoh_config_outdated(&self) -> bool {
+        match self {
+            Error::Odoh(e) => matches!(
+                e,
+                odoh_rs::Error::Hpke(_) | odoh_rs::Error::AesGcm(_) | odoh_rs::Error::KeyIdMismatch
+            ),
+            Error::HeaderStatus(status) => status.is_client_error(),
+            _ => false,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
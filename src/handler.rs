@@ -1,26 +1,25 @@
 use crate::context::Context;
-use crate::{Cache, DohError, DohResult};
+use crate::message::{create_error_response, create_error_response_from_header, encode_response};
+use crate::responder::Responder;
+use crate::{Cache, DohError, DohResult, HedgeDelay};
 use bytes::Bytes;
 use dns_message_parser::question::Question;
-use dns_message_parser::Dns;
+use dns_message_parser::{Dns, Opcode, RCode};
+use futures::future::{select, Either};
 use futures::lock::Mutex;
 use std::future::Future;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::pin::pin;
 use std::time::Duration;
-use tokio::net::UdpSocket;
-use tokio::time::timeout as create_timeout;
+use tokio::time::{sleep, timeout_at, Instant};
 
 async fn send_response(
-    dns_response: &mut Dns,
-    id: u16,
-    addr: SocketAddr,
-    sender: Arc<UdpSocket>,
+    dns_response: &Dns,
+    dns_request: &Dns,
+    responder: &Responder,
 ) -> DohResult<()> {
-    dns_response.id = id;
-    let bytes = dns_response.encode()?;
-    sender.send_to(bytes.as_ref(), addr).await?;
-    Ok(())
+    let max_size = responder.max_size(dns_request);
+    let bytes = encode_response(dns_response, dns_request, max_size)?;
+    responder.send(bytes).await
 }
 
 enum CacheReturn<'a> {
@@ -32,7 +31,7 @@ enum CacheReturn<'a> {
 async fn get_response_from_cache<'a>(
     context: &'a Context,
     dns_request: &Dns,
-    addr: &SocketAddr,
+    responder: &Responder,
 ) -> CacheReturn<'a> {
     if let Some(cache) = &context.cache {
         let questions = &dns_request.questions;
@@ -46,11 +45,8 @@ async fn get_response_from_cache<'a>(
             };
 
             if let Some(dns_response) = entry {
-                let id = dns_request.id;
-                let sender = context.sender.clone();
-                let addr = *addr;
                 debug!("Question is found in cache");
-                let result = send_response(dns_response, id, addr, sender).await;
+                let result = send_response(dns_response, dns_request, responder).await;
                 CacheReturn::Found(result)
             } else {
                 debug!("Question is not found in cache");
@@ -66,66 +62,167 @@ async fn get_response_from_cache<'a>(
     }
 }
 
-async fn get_response(
+/// Start the request to the next remote host of `order`, which can be contacted, and return the
+/// deadline of its response. The time until `query_deadline` is split evenly across the remote
+/// hosts, which are left, so a query never takes longer than the timeout in total.
+async fn start_request(
     context: &Context,
-    cache_question: &Option<(&Mutex<Cache<Question, Dns>>, Question)>,
+    order: &mut impl ExactSizeIterator<Item = usize>,
+    dns_request: &mut Dns,
+    query_deadline: Instant,
+) -> Option<(
+    usize,
+    (
+        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
+        u32,
+    ),
+    Instant,
+)> {
+    while let Some(index) = order.next() {
+        let remaining = query_deadline.saturating_duration_since(Instant::now());
+        let deadline = Instant::now() + remaining / (order.len() as u32 + 1);
+        let result = timeout_at(
+            deadline,
+            context.upstreams.get(index).start_request(dns_request),
+        )
+        .await;
+        match result {
+            Ok(Ok(response)) => return Some((index, response, deadline)),
+            Ok(Err(e)) => info!("Could not contact DNS server: {}", e),
+            Err(e) => info!("Timeout while contacting DNS server: {}", e),
+        }
+        context.upstreams.failed(index);
+    }
+    None
+}
+
+/// Wait until `deadline` for the response of the remote host and feed the latency or the failure
+/// back into the selection of the remote hosts.
+async fn wait_response(
+    context: &Context,
+    index: usize,
     response: (
         impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
         u32,
     ),
-    id: u16,
-    addr: &SocketAddr,
-) -> Option<DohResult<()>> {
+    deadline: Instant,
+) -> Option<(Dns, Option<Duration>)> {
     let (response_future, connection_id) = response;
-    let timeout = context.timeout;
-    match create_timeout(timeout, response_future).await {
-        Ok(Ok((mut dns_response, duration))) => {
-            let addr = *addr;
-            let sender = context.sender.clone();
-            let result = send_response(&mut dns_response, id, addr, sender).await;
-            if let Some(duration) = duration {
-                if let Some((cache, question)) = cache_question {
-                    let mut guard_cache = cache.lock().await;
-                    debug!(
-                        "Add records in cache: {}, {}, {:?}",
-                        question, dns_response, duration
-                    );
-                    guard_cache.put(question.clone(), dns_response, duration);
-                }
-            }
-            return Some(result);
+    let start = Instant::now();
+    match timeout_at(deadline, response_future).await {
+        Ok(Ok(response)) => {
+            context.upstreams.succeeded(index, start.elapsed());
+            return Some(response);
         }
         Ok(Err(e)) => {
+            // A lost connection is already dropped by the session. Otherwise the server answered
+            // with an error and the connection can still be used.
             error!("Could not retrieve DNS response from server: {}", e);
         }
         Err(e) => {
             error!("Timeout: {}", e);
+            context.upstreams.get(index).disconnect(connection_id);
         }
     }
-    let mut guard_remote_session = context.remote_session.lock().await;
-    guard_remote_session.disconnect(connection_id);
+    context.upstreams.failed(index);
     None
 }
 
+/// Wait for the response of the first remote host. If it has not answered within the hedge delay
+/// or it fails, then the query is sent to the next remote host too and the first answer is used.
+/// The request of the other remote host is cancelled by dropping it, which resets the HTTP/2
+/// stream.
+async fn wait_hedged_response(
+    context: &Context,
+    order: &mut impl ExactSizeIterator<Item = usize>,
+    dns_request: &mut Dns,
+    first: (
+        usize,
+        (
+            impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
+            u32,
+        ),
+        Instant,
+    ),
+    hedge_delay: HedgeDelay,
+    query_deadline: Instant,
+) -> Option<(Dns, Option<Duration>)> {
+    let (index, response, deadline) = first;
+    let delay = context
+        .upstreams
+        .hedge_delay(index, hedge_delay, context.timeout / 2);
+    let mut first = pin!(wait_response(context, index, response, deadline));
+    let first = match select(first.as_mut(), pin!(sleep(delay))).await {
+        Either::Left((Some(response), _)) => return Some(response),
+        Either::Left((None, _)) => None,
+        Either::Right(_) => Some(first),
+    };
+
+    let second = async {
+        let (index, response, deadline) =
+            start_request(context, order, dns_request, query_deadline).await?;
+        debug!("Send hedged request to remote host {}", index + 1);
+        wait_response(context, index, response, deadline).await
+    };
+    let second = pin!(second);
+    match first {
+        Some(first) => match select(first, second).await {
+            Either::Left((Some(response), _)) | Either::Right((Some(response), _)) => {
+                Some(response)
+            }
+            Either::Left((None, second)) => second.await,
+            Either::Right((None, first)) => first.await,
+        },
+        None => second.await,
+    }
+}
+
 async fn get_response_from_remote(
     context: &Context,
     cache_question: &Option<(&Mutex<Cache<Question, Dns>>, Question)>,
     dns_request: &mut Dns,
-    addr: &SocketAddr,
+    responder: &Responder,
 ) -> Option<DohResult<()>> {
-    let mut guard_remote_session = context.remote_session.lock().await;
-    let result = guard_remote_session.start_request(dns_request).await;
-    drop(guard_remote_session);
-    match result {
-        Ok(response) => {
-            let id = dns_request.id;
-            get_response(context, cache_question, response, id, addr).await
+    let mut order = context.upstreams.order().into_iter();
+    // Fail over to the next remote host, if the query times out or fails, until the remote hosts
+    // are exhausted. The remote hosts share the timeout of the query.
+    let query_deadline = Instant::now() + context.timeout;
+    let (dns_response, duration) = loop {
+        let first = start_request(context, &mut order, dns_request, query_deadline).await?;
+        let response = match context.hedge_delay {
+            Some(hedge_delay) => {
+                wait_hedged_response(
+                    context,
+                    &mut order,
+                    dns_request,
+                    first,
+                    hedge_delay,
+                    query_deadline,
+                )
+                .await
+            }
+            None => {
+                let (index, response, deadline) = first;
+                wait_response(context, index, response, deadline).await
+            }
+        };
+        if let Some(response) = response {
+            break response;
         }
-        Err(e) => {
-            info!("Could not contact DNS server: {}", e);
-            None
+    };
+
+    let result = send_response(&dns_response, dns_request, responder).await;
+    if let Some(duration) = duration {
+        if let Some((cache, question)) = cache_question {
+            let mut guard_cache = cache.lock().await;
+            debug!(
+                "Add records in cache: {}, {}, {:?}",
+                question, dns_response, duration
+            );
+            guard_cache.put(question.clone(), dns_response, duration);
         }
     }
+    Some(result)
 }
 
 #[allow(clippy::needless_lifetimes)]
@@ -133,16 +230,14 @@ async fn get_response_from_cache_fallback<'a>(
     context: &'a Context,
     cache_question: Option<(&Mutex<Cache<Question, Dns>>, Question)>,
     dns_request: &Dns,
-    addr: SocketAddr,
+    responder: &Responder,
 ) -> Option<DohResult<()>> {
     if context.cache_fallback {
         if let Some((cache, question)) = &cache_question {
             let mut guard_cache = cache.lock().await;
             if let Some(dns_response) = guard_cache.get_expired_fallback(question) {
-                let id = dns_request.id;
-                let sender = context.sender.clone();
                 debug!("Question is found in cache fallback");
-                let result = send_response(dns_response, id, addr, sender).await;
+                let result = send_response(dns_response, dns_request, responder).await;
                 Some(result)
             } else {
                 debug!("Question is not found in cache fallback");
@@ -158,28 +253,56 @@ async fn get_response_from_cache_fallback<'a>(
     }
 }
 
-pub async fn request_handler(msg: Bytes, addr: SocketAddr, context: &Context) -> DohResult<()> {
-    let mut dns_request = Dns::decode(msg)?;
+async fn send_error_response(
+    dns_request: &Dns,
+    rcode: RCode,
+    responder: &Responder,
+) -> DohResult<()> {
+    debug!("Send {:?} response", rcode);
+    let dns_response = create_error_response(dns_request, rcode);
+    send_response(&dns_response, dns_request, responder).await
+}
+
+pub(crate) async fn request_handler(
+    msg: Bytes,
+    responder: &Responder,
+    context: &Context,
+) -> DohResult<()> {
+    let mut dns_request = match Dns::decode(msg.clone()) {
+        Ok(dns_request) => dns_request,
+        Err(e) => {
+            if let Some(response) = create_error_response_from_header(msg.as_ref()) {
+                responder.send(response).await?;
+            }
+            return Err(e.into());
+        }
+    };
     if dns_request.is_response() {
         return Err(DohError::DnsNotRequest(dns_request));
     }
+    if dns_request.flags.opcode != Opcode::Query {
+        send_error_response(&dns_request, RCode::NotImp, responder).await?;
+        return Err(DohError::DnsOpcode(dns_request.flags.opcode));
+    }
 
-    let cache = get_response_from_cache(context, &dns_request, &addr).await;
+    let cache = get_response_from_cache(context, &dns_request, responder).await;
     let cache_question = match cache {
         CacheReturn::Found(result) => return result,
         CacheReturn::NotFound(cache_question) => cache_question,
     };
 
-    let remote = get_response_from_remote(context, &cache_question, &mut dns_request, &addr).await;
+    let remote =
+        get_response_from_remote(context, &cache_question, &mut dns_request, responder).await;
     if let Some(result) = remote {
         return result;
     }
 
     let fallback =
-        get_response_from_cache_fallback(context, cache_question, &dns_request, addr).await;
+        get_response_from_cache_fallback(context, cache_question, &dns_request, responder).await;
     if let Some(result) = fallback {
         return result;
     }
 
+    send_error_response(&dns_request, RCode::ServFail, responder).await?;
     Err(DohError::CouldNotGetResponse(dns_request))
 }
@@ -2,8 +2,9 @@ use rustls::RootCertStore;
 use rustls_pemfile::{certs, read_one, Item};
 use rustls_pki_types::{CertificateDer, PrivateKeyDer};
 use std::{
-    fs::File,
+    fs::{metadata, read_dir, File},
     io::{BufReader, Error as IoError, Result as IoResult},
+    net::Ipv6Addr,
 };
 
 pub(super) fn load_certs(path: &str) -> IoResult<Vec<CertificateDer<'static>>> {
@@ -41,21 +42,94 @@ fn load_native_certs() -> (Vec<CertificateDer<'static>>, usize) {
     (result.certs, result.errors.len())
 }
 
+/// There is no native certificate store, so only the PEM files are trusted.
 #[cfg(not(feature = "native-certs"))]
 fn load_native_certs() -> (Vec<CertificateDer<'static>>, usize) {
-    (Vec::new(), 1)
+    (Vec::new(), 0)
 }
 
-pub(super) fn load_root_store(cafile: Option<&String>) -> IoResult<RootCertStore> {
+/// The trusted CA certificates: the native certificate store of the platform and the PEM files.
+#[derive(Debug, Clone)]
+pub struct CaCerts {
+    native: bool,
+    paths: Vec<String>,
+}
+
+impl CaCerts {
+    /// Trust the CA certificates of the native certificate store of the platform.
+    pub fn native() -> CaCerts {
+        CaCerts {
+            native: true,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Trust only the CA certificates of the PEM file or of the PEM files in the directory
+    /// `path` instead of the native certificate store.
+    pub fn path(path: &str) -> CaCerts {
+        CaCerts {
+            native: false,
+            paths: vec![path.to_owned()],
+        }
+    }
+
+    /// Trust the CA certificates of the PEM file or of the PEM files in the directory `path` too.
+    pub fn with_path(mut self, path: &str) -> CaCerts {
+        self.paths.push(path.to_owned());
+        self
+    }
+
+    /// Do not trust the CA certificates of the native certificate store.
+    pub fn without_native(mut self) -> CaCerts {
+        self.native = false;
+        self
+    }
+}
+
+impl From<Option<&String>> for CaCerts {
+    /// The CA certificates of the PEM file, if it is given, replace the native certificate store.
+    fn from(cafile: Option<&String>) -> CaCerts {
+        match cafile {
+            Some(cafile) => CaCerts::path(cafile),
+            None => CaCerts::native(),
+        }
+    }
+}
+
+impl From<&CaCerts> for CaCerts {
+    fn from(ca_certs: &CaCerts) -> CaCerts {
+        ca_certs.clone()
+    }
+}
+
+/// Load the certificates of the PEM file or of all PEM files in the directory.
+fn load_ca_path(path: &str) -> IoResult<Vec<CertificateDer<'static>>> {
+    if !metadata(path)?.is_dir() {
+        return load_certs(path);
+    }
+    let mut paths = Vec::new();
+    for entry in read_dir(path)? {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut result = Vec::new();
+    for path in paths {
+        let path = path
+            .to_str()
+            .ok_or_else(|| IoError::other(format!("Invalid path: {}", path.display())))?;
+        result.extend(load_certs(path)?);
+    }
+    Ok(result)
+}
+
+pub(super) fn load_root_store(ca_certs: &CaCerts) -> IoResult<RootCertStore> {
     let mut root_store = RootCertStore::empty();
     let mut added = 0;
     let mut ignored = 0;
-    if let Some(cafile) = cafile {
-        let certs = load_certs(cafile)?;
-        let result = root_store.add_parsable_certificates(certs);
-        added = result.0;
-        ignored = result.1;
-    } else {
+    if ca_certs.native {
         let (certs, errors) = load_native_certs();
         ignored += errors;
         for cert in certs {
@@ -65,6 +139,12 @@ pub(super) fn load_root_store(cafile: Option<&String>) -> IoResult<RootCertStore
             }
         }
     }
+    for path in &ca_certs.paths {
+        let certs = load_ca_path(path)?;
+        let result = root_store.add_parsable_certificates(certs);
+        added += result.0;
+        ignored += result.1;
+    }
 
     if added == 0 {
         Err(IoError::other("Could not add any certificates"))
@@ -75,3 +155,26 @@ pub(super) fn load_root_store(cafile: Option<&String>) -> IoResult<RootCertStore
         Ok(root_store)
     }
 }
+
+/// Split `host_port` into the host and the optional port. IPv6 addresses have to be enclosed in
+/// brackets (e.g. `[::1]:443`), the brackets are removed from the host. Returns `None` if the
+/// brackets are unbalanced or an IPv6 address is not enclosed in brackets.
+pub(super) fn split_host_port(host_port: &str) -> Option<(&str, Option<&str>)> {
+    if let Some(host_port) = host_port.strip_prefix('[') {
+        let (host, port) = host_port.split_once(']')?;
+        if host.parse::<Ipv6Addr>().is_err() {
+            return None;
+        }
+        if port.is_empty() {
+            Some((host, None))
+        } else {
+            Some((host, Some(port.strip_prefix(':')?)))
+        }
+    } else {
+        match host_port.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') => None,
+            Some((host, port)) => Some((host, Some(port))),
+            None => Some((host_port, None)),
+        }
+    }
+}
@@ -9,13 +9,24 @@ mod error;
 mod handler;
 mod helper;
 mod listen;
+mod message;
 mod remote;
+mod resolver;
+mod responder;
 mod run;
+mod stamp;
+mod uri_template;
+mod verifier;
 
 use cache::Cache;
-pub use cmd::{get_command, get_listen_config, get_remote_host};
-pub use config::Config;
+pub use cmd::{
+    get_bootstrap_dns, get_command, get_listen_config, get_remote_host, get_remote_hosts,
+};
+pub use config::{Config, UpstreamConfig};
 use error::{Error as DohError, Result as DohResult};
+pub use helper::CaCerts;
 pub use listen::Config as ListenConfig;
-pub use remote::Host as RemoteHost;
+pub use remote::{HedgeDelay, Host as RemoteHost, Strategy, Transport};
+use resolver::Resolver;
 pub use run::run;
+use uri_template::UriTemplate;
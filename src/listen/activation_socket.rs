@@ -1,9 +1,18 @@
 #[cfg(target_os = "macos")]
 use libc::size_t;
 use std::io::Result as IoResult;
-use std::net::UdpSocket;
+use std::net::{TcpListener, UdpSocket};
 #[cfg(target_os = "macos")]
 use std::os::raw::{c_char, c_int, c_void};
+#[cfg(target_family = "unix")]
+use std::os::unix::io::RawFd;
+
+/// A socket, which is passed by the service manager, with its name (`FileDescriptorName=` of
+/// systemd), if it is known.
+pub(super) enum ActivationSocket {
+    Udp(UdpSocket, Option<String>),
+    Tcp(TcpListener, Option<String>),
+}
 
 #[cfg(target_os = "macos")]
 extern "C" {
@@ -11,13 +20,96 @@ extern "C" {
         -> c_int;
 }
 
+/// Get the value of the integer socket option `option` (`SOL_SOCKET` level) of the socket.
+#[cfg(target_family = "unix")]
+fn get_socket_option(fd: RawFd, option: libc::c_int) -> IoResult<libc::c_int> {
+    use libc::{c_int, getsockopt, socklen_t, SOL_SOCKET};
+    use std::io::Error as IoError;
+    use std::mem::size_of;
+
+    let mut value: c_int = 0;
+    let mut len = size_of::<c_int>() as socklen_t;
+    let result = unsafe {
+        getsockopt(
+            fd,
+            SOL_SOCKET,
+            option,
+            &mut value as *mut c_int as *mut _,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Detect the type of the socket and take the ownership of the file descriptor. A stream socket
+/// has to be listening, connected sockets (`Accept=yes` of systemd) are not supported.
+#[cfg(target_family = "unix")]
+fn from_raw_fd(fd: RawFd, name: Option<String>) -> IoResult<ActivationSocket> {
+    use libc::{SOCK_DGRAM, SOCK_STREAM, SO_ACCEPTCONN, SO_TYPE};
+    use std::io::Error as IoError;
+    use std::os::unix::io::FromRawFd;
+
+    match get_socket_option(fd, SO_TYPE)? {
+        SOCK_DGRAM => Ok(ActivationSocket::Udp(
+            unsafe { UdpSocket::from_raw_fd(fd) },
+            name,
+        )),
+        SOCK_STREAM => {
+            if get_socket_option(fd, SO_ACCEPTCONN)? == 0 {
+                return Err(IoError::other(format!(
+                    "File descriptor {} is a stream socket, which is not listening (Accept=yes is \
+                    not supported)",
+                    fd
+                )));
+            }
+            Ok(ActivationSocket::Tcp(
+                unsafe { TcpListener::from_raw_fd(fd) },
+                name,
+            ))
+        }
+        socket_type => Err(IoError::other(format!(
+            "File descriptor {} has an unsupported socket type: {}",
+            fd, socket_type
+        ))),
+    }
+}
+
+/// Select the passed sockets, which are used. The stream sockets are closed, if `tcp` is
+/// `false`. At least one socket has to be left.
+pub(super) fn select_activation_sockets(
+    sockets: Vec<ActivationSocket>,
+    tcp: bool,
+) -> IoResult<Vec<ActivationSocket>> {
+    use std::io::Error as IoError;
+
+    let sockets: Vec<ActivationSocket> = sockets
+        .into_iter()
+        .filter(|socket| match socket {
+            ActivationSocket::Udp(_, _) => true,
+            ActivationSocket::Tcp(_, _) if tcp => true,
+            ActivationSocket::Tcp(_, _) => {
+                info!("Ignore the passed stream socket");
+                false
+            }
+        })
+        .collect();
+    if sockets.is_empty() {
+        return Err(IoError::other(
+            "No usable activation socket (stream sockets need --listen-tcp)",
+        ));
+    }
+    Ok(sockets)
+}
+
 #[cfg(target_os = "macos")]
-pub(super) fn get_activation_socket() -> IoResult<UdpSocket> {
+pub(super) fn get_activation_sockets() -> IoResult<Vec<ActivationSocket>> {
     use libc::free;
     use std::ffi::CString;
     use std::io;
     use std::io::ErrorKind::Other;
-    use std::os::unix::io::FromRawFd;
     use std::ptr::null_mut;
     unsafe {
         let mut fds: *mut c_int = null_mut();
@@ -25,13 +117,15 @@ pub(super) fn get_activation_socket() -> IoResult<UdpSocket> {
 
         let name = CString::new("Listeners").expect("CString::new failed");
         if launch_activate_socket(name.as_ptr(), &mut fds, &mut cnt) == 0 {
-            if cnt == 1 {
-                let socket = UdpSocket::from_raw_fd(*fds.offset(0));
-                free(fds as *mut c_void);
-                Ok(socket)
-            } else {
-                Err(io::Error::new(Other, "Could not get fd: cnt != 1"))
+            let raw_fds: Vec<RawFd> = (0..cnt).map(|i| *fds.add(i)).collect();
+            free(fds as *mut c_void);
+            if raw_fds.is_empty() {
+                return Err(io::Error::new(Other, "Could not get fd: cnt == 0"));
             }
+            raw_fds
+                .into_iter()
+                .map(|fd| from_raw_fd(fd, None))
+                .collect()
         } else {
             Err(io::Error::new(
                 Other,
@@ -41,15 +135,138 @@ pub(super) fn get_activation_socket() -> IoResult<UdpSocket> {
     }
 }
 
-#[allow(clippy::unnecessary_wraps)]
+/// The first file descriptor, which is passed by systemd (see `sd_listen_fds(3)`).
 #[cfg(all(target_family = "unix", not(target_os = "macos")))]
-pub(super) fn get_activation_socket() -> IoResult<UdpSocket> {
-    use std::os::unix::io::FromRawFd;
-    unsafe { Ok(UdpSocket::from_raw_fd(3)) }
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// The name of the sockets of `doh-client.socket` (`FileDescriptorName=`).
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+const SD_LISTEN_FD_NAME: &str = "dns";
+
+/// Validate the environment variables `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES` and return
+/// the passed file descriptors with their names.
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn parse_listen_fds(
+    pid: u32,
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    listen_fdnames: Option<&str>,
+) -> IoResult<Vec<(RawFd, Option<String>)>> {
+    use std::io::Error as IoError;
+
+    let listen_pid = listen_pid.ok_or_else(|| IoError::other("LISTEN_PID is not set"))?;
+    match listen_pid.parse::<u32>() {
+        Ok(listen_pid) if listen_pid == pid => {}
+        _ => {
+            return Err(IoError::other(format!(
+                "LISTEN_PID is not for this process: {}",
+                listen_pid
+            )))
+        }
+    }
+
+    let listen_fds = listen_fds.ok_or_else(|| IoError::other("LISTEN_FDS is not set"))?;
+    let listen_fds = match listen_fds.parse::<RawFd>() {
+        Ok(listen_fds) if listen_fds > 0 => listen_fds,
+        _ => {
+            return Err(IoError::other(format!(
+                "LISTEN_FDS is not a positive number: {}",
+                listen_fds
+            )))
+        }
+    };
+    let end = SD_LISTEN_FDS_START
+        .checked_add(listen_fds)
+        .ok_or_else(|| IoError::other(format!("LISTEN_FDS is too large: {}", listen_fds)))?;
+
+    let names = match listen_fdnames {
+        Some(listen_fdnames) => {
+            let names: Vec<Option<String>> = listen_fdnames
+                .split(':')
+                .map(|name| Some(name.to_owned()))
+                .collect();
+            if names.len() != listen_fds as usize {
+                return Err(IoError::other(format!(
+                    "LISTEN_FDNAMES does not match LISTEN_FDS ({}): {}",
+                    listen_fds, listen_fdnames
+                )));
+            }
+            names
+        }
+        None => vec![None; listen_fds as usize],
+    };
+
+    Ok((SD_LISTEN_FDS_START..end).zip(names).collect())
+}
+
+/// Select the file descriptors of the `doh-client`. If one of them is named `dns`, then the ones
+/// with other names are passed by the unit for other purposes and are skipped.
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+fn select_listen_fds(fds: Vec<(RawFd, Option<String>)>) -> Vec<(RawFd, Option<String>)> {
+    let named = |name: &Option<String>| name.as_deref() == Some(SD_LISTEN_FD_NAME);
+    if !fds.iter().any(|(_, name)| named(name)) {
+        return fds;
+    }
+    fds.into_iter()
+        .filter(|(fd, name)| {
+            if named(name) {
+                true
+            } else {
+                info!(
+                    "Skip the passed fd {} ({})",
+                    fd,
+                    name.as_deref().unwrap_or("unknown")
+                );
+                false
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+pub(super) fn get_activation_sockets() -> IoResult<Vec<ActivationSocket>> {
+    use libc::{fcntl, FD_CLOEXEC, F_SETFD};
+    use std::env::var;
+    use std::io::Error as IoError;
+    use std::process::id;
+
+    let listen_pid = var("LISTEN_PID").ok();
+    let listen_fds = var("LISTEN_FDS").ok();
+    let listen_fdnames = var("LISTEN_FDNAMES").ok();
+    let fds = parse_listen_fds(
+        id(),
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        listen_fdnames.as_deref(),
+    )?;
+    // The variables are left set, because modifying the environment is not thread-safe and the
+    // runtime is already running. The `doh-client` does not start child processes, which could
+    // inherit them, and the file descriptors are closed on exec.
+
+    let fds = select_listen_fds(fds);
+    let mut sockets = Vec::with_capacity(fds.len());
+    for (fd, name) in fds {
+        if unsafe { fcntl(fd, F_SETFD, FD_CLOEXEC) } != 0 {
+            return Err(IoError::last_os_error());
+        }
+        let socket = from_raw_fd(fd, name)?;
+        let (socket_type, name) = match &socket {
+            ActivationSocket::Udp(_, name) => ("datagram", name),
+            ActivationSocket::Tcp(_, name) => ("stream", name),
+        };
+        info!(
+            "Got {} socket from systemd: fd {} ({})",
+            socket_type,
+            fd,
+            name.as_deref().unwrap_or("unknown")
+        );
+        sockets.push(socket);
+    }
+    Ok(sockets)
 }
 
 #[cfg(target_family = "windows")]
-pub(super) fn get_activation_socket() -> IoResult<UdpSocket> {
+pub(super) fn get_activation_sockets() -> IoResult<Vec<ActivationSocket>> {
     use std::io;
     use std::io::ErrorKind::Other;
     Err(io::Error::new(
@@ -57,3 +274,113 @@ pub(super) fn get_activation_socket() -> IoResult<UdpSocket> {
         "This is not supported in windows platforms",
     ))
 }
+
+#[cfg(all(test, target_family = "unix", not(target_os = "macos")))]
+mod tests {
+    use super::{
+        from_raw_fd, parse_listen_fds, select_activation_sockets, select_listen_fds,
+        ActivationSocket,
+    };
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    #[test]
+    fn test_from_raw_fd() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = from_raw_fd(socket.into_raw_fd(), None).unwrap();
+        assert!(matches!(socket, ActivationSocket::Udp(_, None)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = from_raw_fd(listener.into_raw_fd(), None).unwrap();
+        assert!(matches!(listener, ActivationSocket::Tcp(_, None)));
+
+        // A connected stream socket is rejected.
+        let fd = TcpStream::connect(addr).unwrap().into_raw_fd();
+        assert!(from_raw_fd(fd, None).is_err());
+        drop(unsafe { TcpStream::from_raw_fd(fd) });
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        let fds = parse_listen_fds(42, Some("42"), Some("1"), None).unwrap();
+        assert_eq!(fds, vec![(3, None)]);
+
+        let fds = parse_listen_fds(42, Some("42"), Some("2"), None).unwrap();
+        assert_eq!(fds, vec![(3, None), (4, None)]);
+    }
+
+    #[test]
+    fn test_parse_listen_fds_names() {
+        let fds = parse_listen_fds(42, Some("42"), Some("3"), Some("dns:dns:metrics")).unwrap();
+        assert_eq!(
+            fds,
+            vec![
+                (3, Some("dns".to_owned())),
+                (4, Some("dns".to_owned())),
+                (5, Some("metrics".to_owned()))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_listen_fds_names_mismatch() {
+        assert!(parse_listen_fds(42, Some("42"), Some("2"), Some("dns")).is_err());
+        assert!(parse_listen_fds(42, Some("42"), Some("1"), Some("dns:dns")).is_err());
+    }
+
+    #[test]
+    fn test_select_listen_fds() {
+        let fds = vec![
+            (3, Some("dns".to_owned())),
+            (4, Some("metrics".to_owned())),
+            (5, Some("dns".to_owned())),
+        ];
+        assert_eq!(
+            select_listen_fds(fds),
+            vec![(3, Some("dns".to_owned())), (5, Some("dns".to_owned()))]
+        );
+
+        // Without a socket named `dns`, all sockets are used, e.g. with the default names.
+        let fds = vec![
+            (3, Some("doh-client.socket".to_owned())),
+            (4, Some("doh-client.socket".to_owned())),
+        ];
+        assert_eq!(select_listen_fds(fds.clone()), fds);
+        assert_eq!(select_listen_fds(vec![(3, None)]), vec![(3, None)]);
+    }
+
+    #[test]
+    fn test_select_activation_sockets() {
+        let udp = || ActivationSocket::Udp(UdpSocket::bind("127.0.0.1:0").unwrap(), None);
+        let tcp = || ActivationSocket::Tcp(TcpListener::bind("127.0.0.1:0").unwrap(), None);
+
+        let sockets = select_activation_sockets(vec![udp(), tcp()], false).unwrap();
+        assert!(matches!(sockets[..], [ActivationSocket::Udp(_, None)]));
+        let sockets = select_activation_sockets(vec![udp(), tcp()], true).unwrap();
+        assert_eq!(sockets.len(), 2);
+
+        // Only stream sockets are passed, e.g. by a unit with `ListenStream=` only.
+        assert!(select_activation_sockets(vec![tcp()], false).is_err());
+        assert_eq!(
+            select_activation_sockets(vec![tcp()], true).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_parse_listen_fds_other_pid() {
+        assert!(parse_listen_fds(42, Some("43"), Some("1"), None).is_err());
+        assert!(parse_listen_fds(42, None, Some("1"), None).is_err());
+    }
+
+    #[test]
+    fn test_parse_listen_fds_invalid_fds() {
+        assert!(parse_listen_fds(42, Some("42"), None, None).is_err());
+        assert!(parse_listen_fds(42, Some("42"), Some("0"), None).is_err());
+        assert!(parse_listen_fds(42, Some("42"), Some("-1"), None).is_err());
+        assert!(parse_listen_fds(42, Some("42"), Some("a"), None).is_err());
+        let max = i32::MAX.to_string();
+        assert!(parse_listen_fds(42, Some("42"), Some(&max), None).is_err());
+    }
+}
@@ -1,22 +1,48 @@
-use super::activation_socket::get_activation_socket;
+use super::activation_socket::{
+    get_activation_sockets, select_activation_sockets, ActivationSocket,
+};
+use super::Listener;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::Result as IoResult;
 use std::net::SocketAddr;
-use tokio::net::UdpSocket;
+use tokio::net::{TcpListener, UdpSocket};
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum Config {
-    Addr(SocketAddr),
+    Addrs(Vec<SocketAddr>),
     Activation,
 }
 
 impl Config {
-    pub(crate) async fn into_socket(self) -> IoResult<UdpSocket> {
+    /// Bind an UDP socket and, if `tcp` is `true`, a TCP socket on every address. In the case of
+    /// the activation, the passed stream sockets are closed if `tcp` is `false` and it fails, if no
+    /// passed socket is left.
+    pub(crate) async fn into_listeners(self, tcp: bool) -> IoResult<Vec<Listener>> {
         match self {
-            Config::Addr(socket_addr) => UdpSocket::bind(&socket_addr).await,
+            Config::Addrs(socket_addrs) => {
+                let mut listeners = Vec::with_capacity(socket_addrs.len() * 2);
+                for socket_addr in socket_addrs {
+                    listeners.push(Listener::Udp(UdpSocket::bind(&socket_addr).await?));
+                    if tcp {
+                        listeners.push(Listener::Tcp(TcpListener::bind(&socket_addr).await?));
+                    }
+                }
+                Ok(listeners)
+            }
             Config::Activation => {
-                let socket = get_activation_socket()?;
-                UdpSocket::from_std(socket)
+                let sockets = select_activation_sockets(get_activation_sockets()?, tcp)?;
+                let mut listeners = Vec::with_capacity(sockets.len());
+                for socket in sockets {
+                    match socket {
+                        ActivationSocket::Udp(socket, _) => {
+                            listeners.push(Listener::from_std_udp(socket)?)
+                        }
+                        ActivationSocket::Tcp(listener, _) => {
+                            listeners.push(Listener::from_std_tcp(listener)?)
+                        }
+                    }
+                }
+                Ok(listeners)
             }
         }
     }
@@ -25,12 +51,20 @@ impl Config {
 impl Display for Config {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         match self {
-            Config::Addr(socket_addr) => write!(f, "{}", socket_addr),
+            Config::Addrs(socket_addrs) => {
+                for (i, socket_addr) in socket_addrs.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", socket_addr)?;
+                }
+                Ok(())
+            }
             Config::Activation => {
                 if cfg!(target_os = "macos") {
                     write!(f, "file descriptor of launch_activate_socket()")
                 } else if cfg!(target_family = "unix") {
-                    write!(f, "file descriptor 3")
+                    write!(f, "file descriptors of LISTEN_FDS")
                 } else {
                     write!(f, "this is not supported on windows")
                 }
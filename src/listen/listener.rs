@@ -0,0 +1,36 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::Result as IoResult;
+use tokio::net::{TcpListener, UdpSocket};
+
+/// A bound socket, which receives DNS queries from the clients.
+pub(crate) enum Listener {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    pub(crate) fn from_std_udp(socket: std::net::UdpSocket) -> IoResult<Listener> {
+        socket.set_nonblocking(true)?;
+        Ok(Listener::Udp(UdpSocket::from_std(socket)?))
+    }
+
+    pub(crate) fn from_std_tcp(listener: std::net::TcpListener) -> IoResult<Listener> {
+        listener.set_nonblocking(true)?;
+        Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Listener::Udp(socket) => match socket.local_addr() {
+                Ok(addr) => write!(f, "UDP {}", addr),
+                Err(_) => write!(f, "UDP socket"),
+            },
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "TCP {}", addr),
+                Err(_) => write!(f, "TCP socket"),
+            },
+        }
+    }
+}
@@ -1,4 +1,6 @@
 mod activation_socket;
 mod config;
+mod listener;
 
 pub use config::Config;
+pub(crate) use listener::Listener;
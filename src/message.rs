@@ -0,0 +1,370 @@
+use crate::DohResult;
+use bytes::Bytes;
+use dns_message_parser::rr::{OPT, RR};
+use dns_message_parser::{Dns, Flags, Opcode, RCode};
+
+/// The size of the DNS header (see RFC 1035 section 4.1.1).
+const HEADER_SIZE: usize = 12;
+
+/// The maximum size of an UDP message without EDNS (see RFC 1035 section 4.2.1).
+const UDP_PAYLOAD_SIZE: usize = 512;
+
+/// The UDP payload size, which is advertised to the clients (see DNS Flag Day 2020).
+pub(crate) const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// Get the maximum size of an UDP response for the request, which is the requestor's payload size
+/// of the OPT record, but at least 512 bytes (see RFC 6891 section 6.2.5) and at most the
+/// advertised payload size to avoid IP fragmentation.
+pub(crate) fn get_udp_payload_size(dns_request: &Dns) -> usize {
+    for additional in &dns_request.additionals {
+        if let RR::OPT(opt) = additional {
+            let requestor_payload_size = opt.requestor_payload_size.min(EDNS_UDP_PAYLOAD_SIZE);
+            return UDP_PAYLOAD_SIZE.max(requestor_payload_size as usize);
+        }
+    }
+    UDP_PAYLOAD_SIZE
+}
+
+fn get_opt(dns: &Dns) -> Option<&OPT> {
+    dns.additionals
+        .iter()
+        .find_map(|additional| match additional {
+            RR::OPT(opt) => Some(opt),
+            _ => None,
+        })
+}
+
+/// Add an OPT record with the payload size, which the `doh-client` supports, to the response if
+/// the request has an OPT record, otherwise remove the OPT record from the response (see RFC 6891
+/// section 7). The OPT record is built from the request, only the extended rcode is kept from the
+/// response. The options of the remote host, e.g. the client subnet or the padding, belong to
+/// another client or connection, because the response can be from the cache.
+fn set_edns(dns_response: &mut Dns, dns_request: &Dns) {
+    let extend_rcode = get_opt(dns_response).map_or(0, |opt| opt.extend_rcode);
+    let opt = get_opt(dns_request).map(|opt_request| OPT {
+        requestor_payload_size: EDNS_UDP_PAYLOAD_SIZE,
+        extend_rcode,
+        version: 0,
+        dnssec: opt_request.dnssec,
+        edns_options: Vec::new(),
+    });
+    dns_response
+        .additionals
+        .retain(|additional| !matches!(additional, RR::OPT(_)));
+    if let Some(opt) = opt {
+        dns_response.additionals.push(RR::OPT(opt));
+    }
+}
+
+/// Create a truncated response, which only contains the question and the OPT record of the
+/// response and has the TC bit set, so the client retries the query over TCP.
+fn create_truncated_response(dns_response: &Dns) -> Dns {
+    let mut flags = dns_response.flags.clone();
+    flags.tc = true;
+    let additionals = dns_response
+        .additionals
+        .iter()
+        .filter(|additional| matches!(additional, RR::OPT(_)))
+        .cloned()
+        .collect();
+    Dns {
+        id: dns_response.id,
+        flags,
+        questions: dns_response.questions.clone(),
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals,
+    }
+}
+
+/// Encode the response to the request. If the encoded response is bigger than `max_size` then a
+/// truncated response is encoded instead. The response is not changed, because it can be an entry
+/// of the cache, which is shared by all clients.
+pub(crate) fn encode_response(
+    dns_response: &Dns,
+    dns_request: &Dns,
+    max_size: Option<usize>,
+) -> DohResult<Bytes> {
+    let mut dns_response = dns_response.clone();
+    dns_response.id = dns_request.id;
+    set_edns(&mut dns_response, dns_request);
+    let bytes = dns_response.encode()?;
+    match max_size {
+        Some(max_size) if max_size < bytes.len() => {
+            debug!(
+                "Response is truncated: {} bytes are bigger than {} bytes",
+                bytes.len(),
+                max_size
+            );
+            let dns_truncated_response = create_truncated_response(&dns_response);
+            let bytes = dns_truncated_response.encode()?;
+            Ok(bytes.freeze())
+        }
+        _ => Ok(bytes.freeze()),
+    }
+}
+
+/// Create a response without any records for the request, which contains the question of the
+/// request and the `rcode`.
+pub(crate) fn create_error_response(dns_request: &Dns, rcode: RCode) -> Dns {
+    let flags = Flags {
+        qr: true,
+        opcode: dns_request.flags.opcode,
+        aa: false,
+        tc: false,
+        rd: dns_request.flags.rd,
+        ra: true,
+        ad: false,
+        cd: dns_request.flags.cd,
+        rcode,
+    };
+    Dns {
+        id: dns_request.id,
+        flags,
+        questions: dns_request.questions.clone(),
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+/// Create a response for a request, which could not be decoded, by only using the header of the
+/// request. If the opcode is not a standard query then the rcode is `NotImp` else `FormErr`.
+/// Returns `None` if the header is incomplete or the message is a response.
+pub(crate) fn create_error_response_from_header(msg: &[u8]) -> Option<Bytes> {
+    if msg.len() < HEADER_SIZE {
+        return None;
+    }
+
+    let flags = msg[2];
+    let qr = flags & 0b1000_0000 != 0;
+    if qr {
+        return None;
+    }
+    let opcode = (flags >> 3) & 0b0000_1111;
+    let rd = flags & 0b0000_0001;
+    let rcode = if opcode == Opcode::Query as u8 {
+        RCode::FormErr
+    } else {
+        RCode::NotImp
+    };
+
+    let mut response = [0; HEADER_SIZE];
+    response[0] = msg[0];
+    response[1] = msg[1];
+    response[2] = 0b1000_0000 | (opcode << 3) | rd;
+    response[3] = 0b1000_0000 | rcode as u8;
+    Some(Bytes::copy_from_slice(&response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_error_response, create_error_response_from_header, encode_response,
+        get_udp_payload_size, EDNS_UDP_PAYLOAD_SIZE,
+    };
+    use bytes::Bytes;
+    use dns_message_parser::rr::edns::{EDNSOption, Padding};
+    use dns_message_parser::rr::{A, OPT, RR};
+    use dns_message_parser::{Dns, Opcode, RCode};
+
+    const REQUEST: [u8; 33] = [
+        0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x77, 0x77,
+        0x77, 0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00,
+        0x01, 0x00, 0x01,
+    ];
+
+    #[test]
+    fn test_create_error_response() {
+        let dns_request = Dns::decode(Bytes::from_static(&REQUEST)).unwrap();
+        let dns_response = create_error_response(&dns_request, RCode::ServFail);
+        let dns_response = Dns::decode(dns_response.encode().unwrap().freeze()).unwrap();
+        assert_eq!(dns_response.id, 0xabcd);
+        assert!(dns_response.is_response());
+        assert!(dns_response.flags.rd);
+        assert_eq!(dns_response.flags.rcode, RCode::ServFail);
+        assert_eq!(dns_response.questions, dns_request.questions);
+        assert!(dns_response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_create_error_response_from_header_form_err() {
+        let response = create_error_response_from_header(&REQUEST[..20]).unwrap();
+        let dns_response = Dns::decode(response).unwrap();
+        assert_eq!(dns_response.id, 0xabcd);
+        assert!(dns_response.is_response());
+        assert!(dns_response.flags.rd);
+        assert_eq!(dns_response.flags.opcode, Opcode::Query);
+        assert_eq!(dns_response.flags.rcode, RCode::FormErr);
+        assert!(dns_response.questions.is_empty());
+    }
+
+    #[test]
+    fn test_create_error_response_from_header_not_imp() {
+        let mut request = REQUEST;
+        request[2] = (Opcode::Update as u8) << 3;
+        let response = create_error_response_from_header(&request).unwrap();
+        let dns_response = Dns::decode(response).unwrap();
+        assert_eq!(dns_response.flags.opcode, Opcode::Update);
+        assert_eq!(dns_response.flags.rcode, RCode::NotImp);
+    }
+
+    #[test]
+    fn test_create_error_response_from_header_invalid() {
+        assert!(create_error_response_from_header(&REQUEST[..11]).is_none());
+        let mut response = REQUEST;
+        response[2] |= 0b1000_0000;
+        assert!(create_error_response_from_header(&response).is_none());
+    }
+
+    fn create_opt(requestor_payload_size: u16) -> RR {
+        RR::OPT(OPT {
+            requestor_payload_size,
+            extend_rcode: 0,
+            version: 0,
+            dnssec: false,
+            edns_options: Vec::new(),
+        })
+    }
+
+    fn create_request(opt: bool) -> Dns {
+        let mut dns_request = Dns::decode(Bytes::from_static(&REQUEST)).unwrap();
+        if opt {
+            dns_request.additionals.push(create_opt(4096));
+        }
+        dns_request
+    }
+
+    fn create_response(answers: u8, opt: bool) -> Dns {
+        let dns_request = create_request(false);
+        let mut dns_response = create_error_response(&dns_request, RCode::NoError);
+        let domain_name = dns_request.questions[0].domain_name.clone();
+        for i in 0..answers {
+            dns_response.answers.push(RR::A(A {
+                domain_name: domain_name.clone(),
+                ttl: 60,
+                ipv4_addr: [10, 0, 0, i].into(),
+            }));
+        }
+        if opt {
+            dns_response.additionals.push(create_opt(4096));
+        }
+        dns_response
+    }
+
+    #[test]
+    fn test_get_udp_payload_size() {
+        let mut dns_request = Dns::decode(Bytes::from_static(&REQUEST)).unwrap();
+        assert_eq!(get_udp_payload_size(&dns_request), 512);
+
+        dns_request.additionals.push(create_opt(256));
+        assert_eq!(get_udp_payload_size(&dns_request), 512);
+
+        dns_request.additionals[0] = create_opt(1024);
+        assert_eq!(get_udp_payload_size(&dns_request), 1024);
+
+        dns_request.additionals[0] = create_opt(4096);
+        assert_eq!(
+            get_udp_payload_size(&dns_request),
+            EDNS_UDP_PAYLOAD_SIZE as usize
+        );
+    }
+
+    #[test]
+    fn test_encode_response_fits() {
+        let dns_request = create_request(true);
+        let dns_response = create_response(2, true);
+        let bytes = encode_response(&dns_response, &dns_request, Some(512)).unwrap();
+        let dns_response = Dns::decode(bytes).unwrap();
+        assert!(!dns_response.flags.tc);
+        assert_eq!(dns_response.answers.len(), 2);
+        assert_eq!(
+            dns_response.additionals[0],
+            create_opt(EDNS_UDP_PAYLOAD_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_encode_response_truncated() {
+        let dns_request = create_request(true);
+        let dns_response = create_response(100, true);
+        let bytes = encode_response(&dns_response, &dns_request, Some(512)).unwrap();
+        assert!(bytes.len() <= 512);
+        let dns_response = Dns::decode(bytes).unwrap();
+        assert!(dns_response.flags.tc);
+        assert_eq!(dns_response.id, 0xabcd);
+        assert_eq!(dns_response.questions.len(), 1);
+        assert!(dns_response.answers.is_empty());
+        assert_eq!(
+            dns_response.additionals,
+            vec![create_opt(EDNS_UDP_PAYLOAD_SIZE)]
+        );
+    }
+
+    #[test]
+    fn test_encode_response_unlimited() {
+        let dns_request = create_request(false);
+        let dns_response = create_response(100, false);
+        let bytes = encode_response(&dns_response, &dns_request, None).unwrap();
+        let dns_response = Dns::decode(bytes).unwrap();
+        assert!(!dns_response.flags.tc);
+        assert_eq!(dns_response.answers.len(), 100);
+    }
+
+    #[test]
+    fn test_encode_response_edns_of_request() {
+        let dns_request = create_request(false);
+        let dns_response = create_response(1, true);
+        let bytes = encode_response(&dns_response, &dns_request, Some(512)).unwrap();
+        let dns_response = Dns::decode(bytes).unwrap();
+        assert!(dns_response.additionals.is_empty());
+
+        let dns_request = create_request(true);
+        let dns_response = create_response(1, false);
+        let bytes = encode_response(&dns_response, &dns_request, Some(512)).unwrap();
+        let dns_response = Dns::decode(bytes).unwrap();
+        assert_eq!(
+            dns_response.additionals,
+            vec![create_opt(EDNS_UDP_PAYLOAD_SIZE)]
+        );
+    }
+
+    #[test]
+    fn test_encode_response_cached_for_clients() {
+        let mut dns_response = create_response(40, false);
+        dns_response.additionals.push(RR::OPT(OPT {
+            requestor_payload_size: 4096,
+            extend_rcode: 0,
+            version: 0,
+            dnssec: false,
+            edns_options: vec![EDNSOption::Padding(Padding(64))],
+        }));
+        let cached = dns_response.clone();
+
+        let dns_request_edns = create_request(true);
+        let max_size = get_udp_payload_size(&dns_request_edns);
+        let bytes = encode_response(&dns_response, &dns_request_edns, Some(max_size)).unwrap();
+        let dns_response_edns = Dns::decode(bytes).unwrap();
+        assert!(!dns_response_edns.flags.tc);
+        assert_eq!(dns_response_edns.answers.len(), 40);
+        // The options of the remote host are not sent to the client.
+        assert_eq!(
+            dns_response_edns.additionals,
+            vec![create_opt(EDNS_UDP_PAYLOAD_SIZE)]
+        );
+
+        let mut dns_request = create_request(false);
+        dns_request.id = 0x1234;
+        let max_size = get_udp_payload_size(&dns_request);
+        let bytes = encode_response(&dns_response, &dns_request, Some(max_size)).unwrap();
+        let dns_response_no_edns = Dns::decode(bytes).unwrap();
+        assert_eq!(dns_response_no_edns.id, 0x1234);
+        assert!(dns_response_no_edns.flags.tc);
+        assert!(dns_response_no_edns.additionals.is_empty());
+
+        // The cached response is not changed by the clients.
+        assert_eq!(dns_response, cached);
+        let bytes = encode_response(&dns_response, &dns_request_edns, Some(1232)).unwrap();
+        assert_eq!(Dns::decode(bytes).unwrap(), dns_response_edns);
+    }
+}
@@ -1,4 +1,6 @@
+use super::Transport;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio_rustls::rustls::ClientConfig;
 
 pub(super) struct Config {
@@ -7,6 +9,15 @@ pub(super) struct Config {
     pub(super) uri: String,
     pub(super) retries: u32,
     pub(super) post: bool,
+    pub(super) transport: Transport,
+    /// The maximum number of HTTP/1.1 or HTTP/2 connections to the remote host.
+    pub(super) max_connections: usize,
+    /// The interval of the PINGs on idle HTTP/2 and QUIC connections.
+    pub(super) keepalive: Option<Duration>,
+    /// The time after that idle HTTP/1.1 and HTTP/2 connections are closed.
+    pub(super) idle_timeout: Option<Duration>,
+    /// The content type of the queries and the responses.
+    pub(super) content_type: &'static str,
 }
 
 impl Config {
@@ -16,6 +27,10 @@ impl Config {
         uri: String,
         retries: u32,
         post: bool,
+        transport: Transport,
+        max_connections: usize,
+        keepalive: Option<Duration>,
+        idle_timeout: Option<Duration>,
     ) -> Config {
         Config {
             domain,
@@ -23,6 +38,11 @@ impl Config {
             uri,
             retries,
             post,
+            transport,
+            max_connections,
+            keepalive,
+            idle_timeout,
+            content_type: "application/dns-message",
         }
     }
 }
@@ -0,0 +1,219 @@
+#[cfg(feature = "http3")]
+use super::http3_response_handler;
+#[cfg(feature = "doq")]
+use super::{doq_response_handler, encode_query};
+use super::{
+    dot_response_handler, http1_response_handler, http2_response_handler, Config, DotConnection,
+    Http1Pool, Http1Sender, Http2Pool, Http2Stream,
+};
+use crate::{DohError, DohResult};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use futures::channel::oneshot::Receiver;
+use http::Request;
+use std::time::Duration;
+
+/// An established connection to a remote host. The connection is a handle, which can be cloned to
+/// send requests over the same connection.
+#[derive(Clone)]
+pub(super) enum Connection {
+    Http1(Http1Pool),
+    Http2(Http2Pool),
+    #[cfg(feature = "http3")]
+    Http3(
+        h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>,
+        quinn::Connection,
+    ),
+    Dot(DotConnection),
+    #[cfg(feature = "doq")]
+    Doq(quinn::Connection),
+}
+
+/// A request, which is sent or is ready to be sent to the remote host. The HTTP requests contain
+/// the expected content type of the response, if it should be checked.
+pub(super) enum PendingRequest {
+    Http1(
+        Http1Sender,
+        Box<Request<()>>,
+        Option<Bytes>,
+        Option<&'static str>,
+    ),
+    Http2(
+        Http2Stream,
+        Box<Request<()>>,
+        Option<Bytes>,
+        Option<&'static str>,
+    ),
+    #[cfg(feature = "http3")]
+    Http3(
+        h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>,
+        Box<Request<()>>,
+        Option<Bytes>,
+        Option<&'static str>,
+    ),
+    Dot(Receiver<Bytes>),
+    #[cfg(feature = "doq")]
+    Doq(quinn::Connection, Bytes),
+}
+
+impl PendingRequest {
+    /// Send the DNS message `data` over the HTTP/1.1 connection, which is taken out of the pool.
+    pub(super) fn http1(sender: Http1Sender, config: &Config, data: Bytes) -> PendingRequest {
+        let (request, body) = create_request(config, data);
+        PendingRequest::Http1(sender, Box::new(request), body, Some(config.content_type))
+    }
+
+    /// Wait for the body of the response and its cache duration.
+    pub(super) async fn response(self) -> DohResult<(Bytes, Option<Duration>)> {
+        match self {
+            PendingRequest::Http1(sender, request, body, content_type) => {
+                http1_response_handler(sender, *request, body, content_type).await
+            }
+            PendingRequest::Http2(stream, request, body, content_type) => {
+                http2_response_handler(stream, *request, body, content_type).await
+            }
+            #[cfg(feature = "http3")]
+            PendingRequest::Http3(send_request, request, body, content_type) => {
+                http3_response_handler(send_request, *request, body, content_type).await
+            }
+            PendingRequest::Dot(receiver) => dot_response_handler(receiver).await,
+            #[cfg(feature = "doq")]
+            PendingRequest::Doq(quic_connection, frame) => {
+                doq_response_handler(quic_connection, frame).await
+            }
+        }
+    }
+}
+
+/// Create the DoH request (see RFC 8484) and the body of it, if the POST method is used.
+fn create_request(config: &Config, data: Bytes) -> (Request<()>, Option<Bytes>) {
+    let content_type = config.content_type;
+    let (request, body) = if config.post {
+        let request = Request::builder()
+            .method("POST")
+            .uri(config.uri.clone())
+            .header("accept", content_type)
+            .header("content-type", content_type)
+            .header("content-length", data.len().to_string())
+            .body(())
+            .unwrap();
+        (request, Some(data))
+    } else {
+        // The URI of a template could already contain a query.
+        let separator = if config.uri.contains('?') { '&' } else { '?' };
+        let uri = format!(
+            "{}{}dns={}",
+            config.uri,
+            separator,
+            URL_SAFE_NO_PAD.encode(&data[..])
+        );
+        let request = Request::builder()
+            .method("GET")
+            .uri(uri)
+            .header("accept", content_type)
+            .body(())
+            .unwrap();
+        (request, None)
+    };
+    debug!("Send HTTP request to server: {:?}", request);
+    (request, body)
+}
+
+impl Connection {
+    /// The protocol of the connection.
+    pub(super) fn version(&self) -> &'static str {
+        match self {
+            Connection::Http1(_) => "HTTP/1.1",
+            Connection::Http2(_) => "HTTP/2",
+            #[cfg(feature = "http3")]
+            Connection::Http3(_, _) => "HTTP/3",
+            Connection::Dot(_) => "DoT",
+            #[cfg(feature = "doq")]
+            Connection::Doq(_) => "DoQ",
+        }
+    }
+
+    /// Returns `true` if it is known, that the connection is closed.
+    pub(super) fn is_closed(&self) -> bool {
+        match self {
+            Connection::Http2(pool) => pool.is_closed(),
+            Connection::Dot(dot_connection) => dot_connection.is_closed(),
+            #[cfg(feature = "http3")]
+            Connection::Http3(_, quic_connection) => quic_connection.close_reason().is_some(),
+            #[cfg(feature = "doq")]
+            Connection::Doq(quic_connection) => quic_connection.close_reason().is_some(),
+            _ => false,
+        }
+    }
+
+    /// Send the HTTP request over the connection.
+    fn send_http_request(
+        &mut self,
+        request: Request<()>,
+        body: Option<Bytes>,
+        content_type: Option<&'static str>,
+    ) -> DohResult<PendingRequest> {
+        match self {
+            Connection::Http1(pool) => {
+                let sender = pool.take().ok_or(DohError::IsNotConnected)?;
+                Ok(PendingRequest::Http1(
+                    sender,
+                    Box::new(request),
+                    body,
+                    content_type,
+                ))
+            }
+            Connection::Http2(pool) => {
+                let stream = pool.take().ok_or(DohError::IsNotConnected)?;
+                Ok(PendingRequest::Http2(
+                    stream,
+                    Box::new(request),
+                    body,
+                    content_type,
+                ))
+            }
+            #[cfg(feature = "http3")]
+            Connection::Http3(send_request, _) => Ok(PendingRequest::Http3(
+                send_request.clone(),
+                Box::new(request),
+                body,
+                content_type,
+            )),
+            _ => Err(DohError::IsNotConnected),
+        }
+    }
+
+    /// Send the DNS message `data` and return the pending request.
+    pub(super) fn send_request(
+        &mut self,
+        config: &Config,
+        data: Bytes,
+    ) -> DohResult<PendingRequest> {
+        let pending_request = match self {
+            Connection::Dot(dot_connection) => {
+                let receiver = dot_connection.send_query(data)?;
+                PendingRequest::Dot(receiver)
+            }
+            #[cfg(feature = "doq")]
+            Connection::Doq(quic_connection) => {
+                PendingRequest::Doq(quic_connection.clone(), encode_query(data)?)
+            }
+            _ => {
+                let (request, body) = create_request(config, data);
+                self.send_http_request(request, body, Some(config.content_type))?
+            }
+        };
+        Ok(pending_request)
+    }
+
+    /// Get the resource of `uri` with the GET method and return the body of the response and its
+    /// cache duration.
+    #[cfg(feature = "odoh")]
+    pub(super) async fn get(&mut self, uri: &str) -> DohResult<(Bytes, Option<Duration>)> {
+        let request = Request::builder().method("GET").uri(uri).body(()).unwrap();
+        debug!("Send HTTP request to server: {:?}", request);
+        self.send_http_request(request, None, None)?
+            .response()
+            .await
+    }
+}
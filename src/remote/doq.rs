@@ -0,0 +1,37 @@
+use crate::{DohError, DohResult};
+use bytes::{BufMut, Bytes, BytesMut};
+use quinn::Connection;
+use std::time::Duration;
+
+/// The maximum size of a response, which is the length prefix and a DNS message.
+const MAX_RESPONSE_SIZE: usize = 2 + u16::MAX as usize;
+
+/// Create the length prefixed DNS message, which is sent over a QUIC stream.
+pub(super) fn encode_query(msg: Bytes) -> DohResult<Bytes> {
+    let len = u16::try_from(msg.len()).map_err(|_| DohError::MessageSize(msg.len()))?;
+    let mut frame = BytesMut::with_capacity(2 + msg.len());
+    frame.put_u16(len);
+    frame.put_slice(&msg);
+    Ok(frame.freeze())
+}
+
+/// Send the query over a new bidirectional QUIC stream (see RFC 9250) and wait for the response.
+/// Every query uses its own stream, so a lost packet only delays the query of its stream.
+pub(super) async fn doq_response_handler(
+    connection: Connection,
+    frame: Bytes,
+) -> DohResult<(Bytes, Option<Duration>)> {
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&frame).await?;
+    send.finish()?;
+
+    let response = recv.read_to_end(MAX_RESPONSE_SIZE).await?;
+    if response.len() < 2 {
+        return Err(DohError::MessageLength(response.len(), 2));
+    }
+    let len = u16::from_be_bytes([response[0], response[1]]) as usize;
+    if response.len() - 2 != len {
+        return Err(DohError::MessageLength(response.len() - 2, len));
+    }
+    Ok((Bytes::from(response).slice(2..), None))
+}
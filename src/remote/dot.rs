@@ -0,0 +1,187 @@
+use crate::{DohError, DohResult};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::channel::oneshot::{channel, Receiver, Sender};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::spawn;
+
+/// The maximum number of queries on a connection, which wait for a response.
+const MAX_PENDING_QUERIES: usize = 1024;
+
+/// The queries, which are sent and wait for a response.
+#[derive(Default)]
+struct Pending {
+    closed: bool,
+    queries: HashMap<u16, Sender<Bytes>>,
+}
+
+/// A DNS over TLS connection (see RFC 7858). The queries are pipelined and the responses are
+/// matched by the DNS ID, so they can arrive out of order.
+#[derive(Clone)]
+pub(super) struct DotConnection {
+    sender: UnboundedSender<Bytes>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+async fn write_queries<T>(mut writer: WriteHalf<T>, mut receiver: UnboundedReceiver<Bytes>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    while let Some(frame) = receiver.next().await {
+        // The flush finishes the TLS handshake, if the query is sent as early data.
+        let result = match writer.write_all(&frame).await {
+            Ok(()) => writer.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Could not send DNS query over TLS: {}", e);
+            return;
+        }
+    }
+    // The connection is not used anymore, so close it.
+    let _ = writer.shutdown().await;
+}
+
+async fn read_response<T>(reader: &mut ReadHalf<T>) -> std::io::Result<Bytes>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let len = reader.read_u16().await?;
+    let mut msg = vec![0; len as usize];
+    reader.read_exact(&mut msg).await?;
+    Ok(Bytes::from(msg))
+}
+
+async fn read_responses<T>(mut reader: ReadHalf<T>, pending: Arc<Mutex<Pending>>)
+where
+    T: AsyncRead + AsyncWrite,
+{
+    loop {
+        match read_response(&mut reader).await {
+            Ok(msg) if msg.len() >= 2 => {
+                let id = u16::from_be_bytes([msg[0], msg[1]]);
+                let sender = pending.lock().unwrap().queries.remove(&id);
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(msg);
+                    }
+                    None => debug!("Got DNS response over TLS for an unknown ID: {}", id),
+                }
+            }
+            Ok(_) => error!("Got too short DNS response over TLS"),
+            Err(e) => {
+                debug!("DNS over TLS connection close: {}", e);
+                break;
+            }
+        }
+    }
+    // Drop all senders, so the waiting queries fail immediately.
+    let mut pending = pending.lock().unwrap();
+    pending.closed = true;
+    pending.queries.clear();
+}
+
+impl DotConnection {
+    pub(super) fn new<T>(connection: T) -> DotConnection
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = split(connection);
+        let (sender, receiver) = unbounded();
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        spawn(write_queries(writer, receiver));
+        spawn(read_responses(reader, pending.clone()));
+        DotConnection { sender, pending }
+    }
+
+    /// Returns `true` if the connection is closed by the server or because of an error.
+    pub(super) fn is_closed(&self) -> bool {
+        self.sender.is_closed() || self.pending.lock().unwrap().closed
+    }
+
+    /// Send the query with a DNS ID, which is unique on this connection, and return a receiver of
+    /// the response.
+    pub(super) fn send_query(&self, msg: Bytes) -> DohResult<Receiver<Bytes>> {
+        let len = u16::try_from(msg.len()).map_err(|_| DohError::MessageSize(msg.len()))?;
+        let (sender, receiver) = channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.closed {
+                return Err(DohError::IsNotConnected);
+            }
+            // Remove the queries, which timed out or were cancelled, so their IDs are free again.
+            pending.queries.retain(|_, sender| !sender.is_canceled());
+            if pending.queries.len() >= MAX_PENDING_QUERIES {
+                return Err(DohError::PendingQueries(pending.queries.len()));
+            }
+            let mut id = fastrand::u16(..);
+            while pending.queries.contains_key(&id) {
+                id = fastrand::u16(..);
+            }
+            pending.queries.insert(id, sender);
+            id
+        };
+
+        let mut frame = BytesMut::with_capacity(2 + msg.len());
+        frame.put_u16(len);
+        frame.put_u16(id);
+        frame.put_slice(&msg[2..]);
+        debug!("Send DNS query over TLS: {}", id);
+        if self.sender.unbounded_send(frame.freeze()).is_err() {
+            self.pending.lock().unwrap().queries.remove(&id);
+            return Err(DohError::IsNotConnected);
+        }
+
+        Ok(receiver)
+    }
+}
+
+/// Wait for the response of the query. There is no cache duration, so the minimum TTL of the
+/// records is used.
+pub(super) async fn dot_response_handler(
+    receiver: Receiver<Bytes>,
+) -> DohResult<(Bytes, Option<Duration>)> {
+    let msg = receiver.await.map_err(|_| DohError::IsNotConnected)?;
+    Ok((msg, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DotConnection, MAX_PENDING_QUERIES};
+    use bytes::Bytes;
+    use tokio::io::duplex;
+
+    fn query() -> Bytes {
+        Bytes::from_static(&[0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+    }
+
+    #[tokio::test]
+    async fn test_send_query_removes_cancelled_queries() {
+        let (client, _server) = duplex(1 << 20);
+        let connection = DotConnection::new(client);
+
+        let receiver = connection.send_query(query()).unwrap();
+        assert_eq!(connection.pending.lock().unwrap().queries.len(), 1);
+        // The query timed out or lost the hedge.
+        drop(receiver);
+        let _receiver = connection.send_query(query()).unwrap();
+        assert_eq!(connection.pending.lock().unwrap().queries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_query_max_pending_queries() {
+        let (client, _server) = duplex(1 << 20);
+        let connection = DotConnection::new(client);
+
+        let receivers: Vec<_> = (0..MAX_PENDING_QUERIES)
+            .map(|_| connection.send_query(query()).unwrap())
+            .collect();
+        assert!(connection.send_query(query()).is_err());
+        drop(receivers);
+        assert!(connection.send_query(query()).is_ok());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{DohError, DohResult, Resolver};
+use rustls_pki_types::EchConfigListBytes;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_rustls::rustls::client::danger::ServerCertVerifier;
+use tokio_rustls::rustls::client::{EchConfig, EchMode};
+use tokio_rustls::rustls::crypto::aws_lc_rs::hpke::ALL_SUPPORTED_SUITES;
+use tokio_rustls::rustls::internal::msgs::codec::Codec;
+use tokio_rustls::rustls::{ClientConfig, Error as RustlsError, PeerIncompatible};
+
+/// Parse the ECH configs (`ECHConfigList`), one of them has to be supported.
+pub(crate) fn parse_ech_config_list(config_list: &[u8]) -> DohResult<EchConfig> {
+    let ech_config = EchConfig::new(EchConfigListBytes::from(config_list), ALL_SUPPORTED_SUITES)?;
+    Ok(ech_config)
+}
+
+/// Create a client config, which encrypts the client hello with the ECH configs. The other
+/// settings are taken from the `client_config`, so the TLS sessions are resumed from the same
+/// store. ECH needs TLS 1.3.
+fn create_ech_client_config(
+    client_config: &ClientConfig,
+    verifier: Arc<dyn ServerCertVerifier>,
+    config_list: &[u8],
+) -> DohResult<ClientConfig> {
+    let ech_config = parse_ech_config_list(config_list)?;
+    let provider = client_config.crypto_provider().clone();
+    let mut ech_client_config = ClientConfig::builder_with_provider(provider)
+        .with_ech(EchMode::from(ech_config))?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    ech_client_config.client_auth_cert_resolver = client_config.client_auth_cert_resolver.clone();
+    ech_client_config.alpn_protocols = client_config.alpn_protocols.clone();
+    ech_client_config.resumption = client_config.resumption.clone();
+    ech_client_config.enable_early_data = client_config.enable_early_data;
+    Ok(ech_client_config)
+}
+
+/// Get the retry configs (`ECHConfigList`) of the server, if the connection failed, because the
+/// server rejected ECH (see draft-ietf-tls-esni section 6.1.6). The list is empty, if the server
+/// sent no retry configs.
+///
+/// rustls only gives the decoded retry configs, so they are encoded again with
+/// `rustls::internal`, which is why rustls is pinned in `Cargo.toml`.
+fn get_retry_configs(e: &DohError) -> Option<Vec<u8>> {
+    let DohError::Io(e) = e else {
+        return None;
+    };
+    match e.get_ref()?.downcast_ref::<RustlsError>()? {
+        RustlsError::PeerIncompatible(PeerIncompatible::ServerRejectedEncryptedClientHello(
+            retry_configs,
+        )) => Some(
+            retry_configs
+                .as_ref()
+                .map(|retry_configs| retry_configs.get_encoding())
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    }
+}
+
+/// Encrypted Client Hello (ECH) for the TLS connections to the remote host, so the domain is not
+/// sent in cleartext (SNI), but the public name of the ECH configs.
+pub(crate) struct Ech {
+    /// The ECH configs of the configuration, otherwise they are fetched from the HTTPS record of
+    /// the domain.
+    config_list: Option<Vec<u8>>,
+    /// Connect with the domain in cleartext, if no ECH configs are available. Otherwise the
+    /// connection fails.
+    fallback: bool,
+    verifier: Arc<dyn ServerCertVerifier>,
+    /// The client config with the ECH configs and the time, when the ECH configs of the HTTPS
+    /// record expire.
+    client_config: Mutex<Option<(Arc<ClientConfig>, Option<Instant>)>>,
+}
+
+impl Ech {
+    pub(crate) fn new(
+        config_list: Option<Vec<u8>>,
+        fallback: bool,
+        verifier: Arc<dyn ServerCertVerifier>,
+    ) -> Ech {
+        Ech {
+            config_list,
+            fallback,
+            verifier,
+            client_config: Mutex::new(None),
+        }
+    }
+
+    /// Get the ECH configs of the configuration or of the HTTPS record of the domain and the time,
+    /// when they expire.
+    async fn get_config_list(
+        &self,
+        resolver: &Resolver,
+        domain: &str,
+    ) -> DohResult<(Vec<u8>, Option<Instant>)> {
+        if let Some(config_list) = &self.config_list {
+            return Ok((config_list.clone(), None));
+        }
+        match resolver.lookup_ech(domain).await {
+            Some((config_list, ttl)) => {
+                let expire = Instant::now() + Duration::from_secs(ttl.into());
+                Ok((config_list, Some(expire)))
+            }
+            None => Err(DohError::EchUnavailable(domain.to_owned())),
+        }
+    }
+
+    /// Get the client config with the ECH configs for the next connection to the remote host. If
+    /// no ECH configs are available, then the `client_config` is returned for the fallback or an
+    /// error otherwise.
+    pub(super) async fn client_config(
+        &self,
+        resolver: &Resolver,
+        domain: &str,
+        client_config: &Arc<ClientConfig>,
+    ) -> DohResult<Arc<ClientConfig>> {
+        if let Some((ech_client_config, expire)) = &*self.client_config.lock().unwrap() {
+            match expire {
+                Some(expire) if *expire <= Instant::now() => {}
+                _ => return Ok(ech_client_config.clone()),
+            }
+        }
+
+        let result = match self.get_config_list(resolver, domain).await {
+            Ok((config_list, expire)) => {
+                create_ech_client_config(client_config, self.verifier.clone(), &config_list)
+                    .map(|ech_client_config| (Arc::new(ech_client_config), expire))
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((ech_client_config, expire)) => {
+                debug!("Connect to {} with ECH", domain);
+                self.client_config
+                    .lock()
+                    .unwrap()
+                    .replace((ech_client_config.clone(), expire));
+                Ok(ech_client_config)
+            }
+            Err(e) if self.fallback => {
+                warn!("Connect to {} without ECH: {}", domain, e);
+                Ok(client_config.clone())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the client config for one more connection attempt, if the server rejected ECH. The retry
+    /// configs of the server replace the ECH configs. If the server sent none, then the domain is
+    /// sent in cleartext for the fallback. Otherwise, `None` is returned and the connection fails.
+    pub(super) fn rejected(
+        &self,
+        e: &DohError,
+        domain: &str,
+        client_config: &Arc<ClientConfig>,
+    ) -> Option<Arc<ClientConfig>> {
+        let retry_configs = get_retry_configs(e)?;
+        if !retry_configs.is_empty() {
+            match create_ech_client_config(client_config, self.verifier.clone(), &retry_configs) {
+                Ok(ech_client_config) => {
+                    info!("{} rejected ECH, retry with its ECH configs", domain);
+                    let ech_client_config = Arc::new(ech_client_config);
+                    let mut guard = self.client_config.lock().unwrap();
+                    let expire = guard.as_ref().and_then(|(_, expire)| *expire);
+                    guard.replace((ech_client_config.clone(), expire));
+                    return Some(ech_client_config);
+                }
+                Err(e) => warn!("Could not use the retry ECH configs of {}: {}", domain, e),
+            }
+        }
+        if self.fallback {
+            warn!("{} rejected ECH, connect without ECH", domain);
+            Some(client_config.clone())
+        } else {
+            error!("{} rejected ECH", domain);
+            None
+        }
+    }
+
+    /// Forget the ECH configs, because the connection failed and they could be outdated.
+    pub(super) fn reset(&self) {
+        self.client_config.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_retry_configs, parse_ech_config_list};
+    use crate::DohError;
+    use std::io::{Error as IoError, ErrorKind};
+    use tokio_rustls::rustls::internal::msgs::codec::{Codec, Reader};
+    use tokio_rustls::rustls::internal::msgs::handshake::EchConfigPayload;
+    use tokio_rustls::rustls::{Error as RustlsError, PeerIncompatible};
+
+    /// An `ECHConfigList` with one X25519, HKDF-SHA256 and AES-128-GCM config of `public.test`.
+    #[rustfmt::skip]
+    const ECH_CONFIG_LIST: [u8; 64] = [
+        // The length of the list, the version and the length of the config.
+        0x00, 0x3e, 0xfe, 0x0d, 0x00, 0x3a,
+        // The config ID, the KEM and the public key.
+        0x01, 0x00, 0x20, 0x00, 0x20,
+        0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42,
+        0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42,
+        0x42, 0x42,
+        // The cipher suites and the maximum name length.
+        0x00, 0x04, 0x00, 0x01, 0x00, 0x01, 0x00,
+        // The public name and the extensions.
+        0x0b, 0x70, 0x75, 0x62, 0x6c, 0x69, 0x63, 0x2e, 0x74, 0x65, 0x73, 0x74,
+        0x00, 0x00,
+    ];
+
+    fn rejected(retry_configs: Option<Vec<EchConfigPayload>>) -> DohError {
+        let e = RustlsError::PeerIncompatible(
+            PeerIncompatible::ServerRejectedEncryptedClientHello(retry_configs),
+        );
+        DohError::Io(IoError::new(ErrorKind::InvalidData, e))
+    }
+
+    #[test]
+    fn test_get_retry_configs() {
+        let payloads = Vec::<EchConfigPayload>::read(&mut Reader::init(&ECH_CONFIG_LIST)).unwrap();
+        let retry_configs = get_retry_configs(&rejected(Some(payloads))).unwrap();
+        assert_eq!(retry_configs, ECH_CONFIG_LIST);
+        assert!(parse_ech_config_list(&retry_configs).is_ok());
+
+        assert_eq!(get_retry_configs(&rejected(None)), Some(Vec::new()));
+        let e = DohError::Io(IoError::new(
+            ErrorKind::InvalidData,
+            RustlsError::DecryptError,
+        ));
+        assert_eq!(get_retry_configs(&e), None);
+        let e = DohError::Io(IoError::from(ErrorKind::ConnectionRefused));
+        assert_eq!(get_retry_configs(&e), None);
+    }
+}
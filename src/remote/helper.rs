@@ -1,34 +1,155 @@
-use crate::DohResult;
+use super::{Connection, DotConnection, Http1Pool, Http2Pool, Transport};
+use crate::{DohError, DohResult, Resolver};
 #[cfg(feature = "http-proxy")]
 use async_http_proxy::{http_connect_tokio, http_connect_tokio_with_basic_auth, HttpError};
 use bytes::Bytes;
-use h2::client::{handshake, SendRequest};
+use h2::client::handshake;
+use http_body_util::Full;
+use hyper::client::conn::http1::{handshake as http1_handshake, SendRequest as Http1SendRequest};
+use hyper_util::rt::TokioIo;
 use rustls_pki_types::ServerName;
 use std::io::Result as IoResult;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::spawn;
-use tokio_rustls::{client::TlsStream, rustls::ClientConfig, TlsConnector};
+use tokio_rustls::rustls::{ClientConfig, HandshakeKind};
+use tokio_rustls::{client::TlsStream, TlsConnector};
 #[cfg(feature = "socks5")]
 use tokio_socks::tcp::Socks5Stream;
 #[cfg(feature = "socks5")]
 use tokio_socks::TargetAddr;
 
-pub(super) async fn try_http2_connect<T>(connection: T) -> DohResult<SendRequest<Bytes>>
+async fn try_http1_connect<T>(connection: T) -> DohResult<Http1SendRequest<Full<Bytes>>>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    debug!("HTTP2 handshake");
-    let (send_request, connection) = handshake(connection).await?;
+    debug!("HTTP1 handshake");
+    let (send_request, connection) = http1_handshake(TokioIo::new(connection)).await?;
     spawn(async move {
         if let Err(e) = connection.await {
-            error!("HTTP2 connection close: {}", e);
+            error!("HTTP1 connection close: {}", e);
         }
     });
     Ok(send_request)
 }
 
+async fn try_http2_connect<T>(connection: T, idle_timeout: Option<Duration>) -> DohResult<Http2Pool>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    debug!("HTTP2 handshake");
+    let (send_request, connection) = handshake(connection).await?;
+    Ok(Http2Pool::new(send_request, connection, idle_timeout))
+}
+
+#[cfg(any(feature = "http3", feature = "doq"))]
+pub(super) async fn try_quic_connect(
+    resolver: &Resolver,
+    host: &str,
+    port: u16,
+    config: &Arc<ClientConfig>,
+    domain: &str,
+    keepalive: Option<Duration>,
+) -> DohResult<quinn::Connection> {
+    use quinn::crypto::rustls::QuicClientConfig;
+    use quinn::{ClientConfig as QuinnClientConfig, Endpoint, TransportConfig};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    let quic_client_config = QuicClientConfig::try_from(config.clone())?;
+    let mut client_config = QuinnClientConfig::new(Arc::new(quic_client_config));
+    // The PINGs keep the idle connection open and detect a dead connection before the next query.
+    let mut transport_config = TransportConfig::default();
+    transport_config.keep_alive_interval(keepalive);
+    client_config.transport_config(Arc::new(transport_config));
+    for remote_addr in resolver.lookup(host, port).await? {
+        let local_addr: SocketAddr = if remote_addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let mut endpoint = Endpoint::client(local_addr)?;
+        endpoint.set_default_client_config(client_config.clone());
+        match endpoint.connect(remote_addr, domain)?.await {
+            Ok(quic_connection) => return Ok(quic_connection),
+            Err(e) => error!("Could not connect to {}: {}", remote_addr, e),
+        }
+    }
+    resolver.forget(host);
+    Err(DohError::CouldNotConnect(host.to_owned(), port))
+}
+
+#[cfg(feature = "http3")]
+pub(super) async fn try_http3_connect(quic_connection: quinn::Connection) -> DohResult<Connection> {
+    use quinn::VarInt;
+    use std::future::poll_fn;
+
+    /// The error codes of HTTP/3 (see RFC 9114 section 8.1).
+    const H3_NO_ERROR: u32 = 0x100;
+    const H3_INTERNAL_ERROR: u32 = 0x102;
+
+    debug!("HTTP3 handshake");
+    let (mut connection, send_request) =
+        h3::client::new(h3_quinn::Connection::new(quic_connection.clone())).await?;
+    let driver_quic_connection = quic_connection.clone();
+    spawn(async move {
+        let e = poll_fn(|cx| connection.poll_close(cx)).await;
+        let code = if e.is_h3_no_error() {
+            debug!("HTTP3 connection close: {}", e);
+            H3_NO_ERROR
+        } else {
+            error!("HTTP3 connection close: {}", e);
+            H3_INTERNAL_ERROR
+        };
+        // The HTTP/3 connection is unusable, e.g. after a GOAWAY, so the QUIC connection is closed
+        // too and the session reconnects before the next query.
+        driver_quic_connection.close(VarInt::from_u32(code), b"");
+    });
+    Ok(Connection::Http3(send_request, quic_connection))
+}
+
+/// Start the protocol of the transport over the TLS connection. The additional HTTP/1.1 and HTTP/2
+/// connections are closed, if they are idle for `idle_timeout`.
+pub(super) async fn try_stream_connect<T>(
+    connection: TlsStream<T>,
+    transport: Transport,
+    idle_timeout: Option<Duration>,
+) -> DohResult<Connection>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    match transport {
+        Transport::Dot => Ok(Connection::Dot(DotConnection::new(connection))),
+        _ => try_https_connect(connection, idle_timeout).await,
+    }
+}
+
+/// Use HTTP/2 if the server negotiated it with ALPN, otherwise fall back to HTTP/1.1.
+async fn try_https_connect<T>(
+    connection: TlsStream<T>,
+    idle_timeout: Option<Duration>,
+) -> DohResult<Connection>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (_, client_connection) = connection.get_ref();
+    // The protocol is not negotiated yet, while the first request is sent as early data. The
+    // early data is only enabled, if the resumed session negotiated HTTP/2.
+    let early_data = client_connection.is_handshaking();
+    if early_data || client_connection.alpn_protocol() == Some(b"h2") {
+        let pool = try_http2_connect(connection, idle_timeout).await?;
+        Ok(Connection::Http2(pool))
+    } else {
+        debug!("Server does not support HTTP2, fall back to HTTP1");
+        let send_request = try_http1_connect(connection).await?;
+        Ok(Connection::Http1(Http1Pool::new(
+            send_request,
+            idle_timeout,
+        )))
+    }
+}
+
 pub(super) async fn try_tls_connect<T>(
     connection: T,
     config: &Arc<ClientConfig>,
@@ -37,30 +158,54 @@ pub(super) async fn try_tls_connect<T>(
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let tls_connector = TlsConnector::from(config.clone());
-    tls_connector
+    let tls_connector = TlsConnector::from(config.clone()).early_data(config.enable_early_data);
+    let tls_stream = tls_connector
         .connect(ServerName::try_from(domain.to_owned()).unwrap(), connection)
-        .await
+        .await?;
+    let (_, client_connection) = tls_stream.get_ref();
+    if client_connection.is_handshaking() {
+        info!("TLS session to {} is resumed, send early data", domain);
+    } else if client_connection.handshake_kind() == Some(HandshakeKind::Resumed) {
+        info!("TLS session to {} is resumed", domain);
+    } else {
+        debug!("Full TLS handshake with {}", domain);
+    }
+    Ok(tls_stream)
 }
 
-pub(super) async fn try_tcp_connect(host: &str, port: u16) -> DohResult<TcpStream> {
-    let tcp_connection = TcpStream::connect(&(host, port)).await?;
-    tcp_connection.set_nodelay(true)?;
-    Ok(tcp_connection)
+/// Connect to the addresses of the host one after another, until a connection is established.
+pub(super) async fn try_tcp_connect(
+    resolver: &Resolver,
+    host: &str,
+    port: u16,
+) -> DohResult<TcpStream> {
+    for addr in resolver.lookup(host, port).await? {
+        match TcpStream::connect(addr).await {
+            Ok(tcp_connection) => {
+                tcp_connection.set_nodelay(true)?;
+                return Ok(tcp_connection);
+            }
+            Err(e) => error!("Could not connect to {}: {}", addr, e),
+        }
+    }
+    resolver.forget(host);
+    Err(DohError::CouldNotConnect(host.to_owned(), port))
 }
 
 #[cfg(feature = "socks5")]
 async fn socks5_connect(
+    resolver: &Resolver,
     host: &str,
     port: u16,
     dest_addr: &TargetAddr<'static>,
     credentials: &Option<(String, String)>,
 ) -> DohResult<TcpStream> {
+    let proxy_addrs = resolver.lookup(host, port).await?;
     let dest_addr = dest_addr.to_owned();
     let socks5_connection = if let Some((username, password)) = credentials {
-        Socks5Stream::connect_with_password((host, port), dest_addr, username, password).await
+        Socks5Stream::connect_with_password(&proxy_addrs[..], dest_addr, username, password).await
     } else {
-        Socks5Stream::connect((host, port), dest_addr).await
+        Socks5Stream::connect(&proxy_addrs[..], dest_addr).await
     }?;
     let tcp_connection = socks5_connection.into_inner();
     tcp_connection.set_nodelay(true)?;
@@ -69,15 +214,18 @@ async fn socks5_connect(
 
 #[cfg(feature = "socks5")]
 pub(super) async fn try_socks5_connect(
+    resolver: &Resolver,
     proxy_host: &str,
     proxy_port: u16,
-    remote_addrs: &[std::net::SocketAddr],
+    remote_host: &str,
+    remote_port: u16,
     credentials: &Option<(String, String)>,
 ) -> DohResult<TcpStream> {
-    for remote_addr in remote_addrs {
-        let target_addr = TargetAddr::Ip(*remote_addr);
+    // The remote host is resolved locally, but connected through the proxy.
+    for remote_addr in resolver.lookup(remote_host, remote_port).await? {
+        let target_addr = TargetAddr::Ip(remote_addr);
         let tcp_connection =
-            socks5_connect(proxy_host, proxy_port, &target_addr, credentials).await;
+            socks5_connect(resolver, proxy_host, proxy_port, &target_addr, credentials).await;
         match tcp_connection {
             Ok(tcp_connection) => return Ok(tcp_connection),
             Err(e) => {
@@ -88,14 +236,13 @@ pub(super) async fn try_socks5_connect(
             }
         }
     }
-    Err(crate::DohError::CouldNotConnect(
-        proxy_host.to_owned(),
-        proxy_port,
-    ))
+    resolver.forget(remote_host);
+    Err(DohError::CouldNotConnect(proxy_host.to_owned(), proxy_port))
 }
 
 #[cfg(feature = "socks5")]
 pub(super) async fn try_socks5h_connect(
+    resolver: &Resolver,
     proxy_host: &str,
     proxy_port: u16,
     remote_host: &str,
@@ -105,7 +252,7 @@ pub(super) async fn try_socks5h_connect(
     use std::borrow::Cow;
 
     let target_addr = TargetAddr::Domain(Cow::Owned(remote_host.to_owned()), remote_port);
-    socks5_connect(proxy_host, proxy_port, &target_addr, credentials).await
+    socks5_connect(resolver, proxy_host, proxy_port, &target_addr, credentials).await
 }
 
 #[cfg(feature = "http-proxy")]
@@ -1,24 +1,22 @@
+#[cfg(feature = "http3")]
+use super::try_http3_connect;
 #[cfg(feature = "http-proxy")]
 use super::try_http_proxy_connect;
-use super::{try_http2_connect, try_tcp_connect, try_tls_connect};
+#[cfg(any(feature = "http3", feature = "doq"))]
+use super::try_quic_connect;
 #[cfg(feature = "socks5")]
 use super::{try_socks5_connect, try_socks5h_connect};
-use crate::DohResult;
-use bytes::Bytes;
-use h2::client::SendRequest;
+use super::{try_stream_connect, try_tcp_connect, try_tls_connect, Connection, Transport};
+use crate::{DohResult, Resolver};
 use std::fmt::{Display, Formatter, Result};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio_rustls::rustls::ClientConfig;
 
 pub enum Host {
     Direct(String, u16),
     #[cfg(feature = "socks5")]
-    Socks5(
-        String,
-        u16,
-        Option<(String, String)>,
-        Vec<std::net::SocketAddr>,
-    ),
+    Socks5(String, u16, Option<(String, String)>, String, u16),
     #[cfg(feature = "socks5")]
     Socks5h(String, u16, Option<(String, String)>, String, u16),
     #[cfg(feature = "http-proxy")]
@@ -36,29 +34,152 @@ pub enum Host {
 }
 
 impl Host {
+    /// Returns `true` if the remote host is connected without a proxy.
+    #[cfg(any(feature = "http3", feature = "doq", feature = "odoh"))]
+    pub(crate) fn is_direct(&self) -> bool {
+        matches!(self, Host::Direct(_, _))
+    }
+
+    /// The other remote host, which is connected over the same proxy.
+    #[cfg(feature = "odoh")]
+    pub(crate) fn with_remote(&self, remote_host: &str, remote_port: u16) -> Host {
+        let remote_host = remote_host.to_owned();
+        match self {
+            Host::Direct(_, _) => Host::Direct(remote_host, remote_port),
+            #[cfg(feature = "socks5")]
+            Host::Socks5(proxy_host, proxy_port, credentials, _, _) => Host::Socks5(
+                proxy_host.clone(),
+                *proxy_port,
+                credentials.clone(),
+                remote_host,
+                remote_port,
+            ),
+            #[cfg(feature = "socks5")]
+            Host::Socks5h(proxy_host, proxy_port, credentials, _, _) => Host::Socks5h(
+                proxy_host.clone(),
+                *proxy_port,
+                credentials.clone(),
+                remote_host,
+                remote_port,
+            ),
+            #[cfg(feature = "http-proxy")]
+            Host::HttpProxy(proxy_host, proxy_port, credentials, _, _) => Host::HttpProxy(
+                proxy_host.clone(),
+                *proxy_port,
+                credentials.clone(),
+                remote_host,
+                remote_port,
+            ),
+            #[cfg(feature = "http-proxy")]
+            Host::HttpsProxy(
+                proxy_host,
+                proxy_port,
+                credentials,
+                _,
+                _,
+                https_client_config,
+                https_domain,
+            ) => Host::HttpsProxy(
+                proxy_host.clone(),
+                *proxy_port,
+                credentials.clone(),
+                remote_host,
+                remote_port,
+                https_client_config.clone(),
+                https_domain.clone(),
+            ),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "http3", feature = "doq")), allow(unused_variables))]
     pub(super) async fn connect(
-        &mut self,
+        &self,
+        resolver: &Resolver,
         client_config: &Arc<ClientConfig>,
         domain: &str,
-    ) -> DohResult<SendRequest<Bytes>> {
+        transport: Transport,
+        keepalive: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> DohResult<Connection> {
+        match transport {
+            #[cfg(feature = "http3")]
+            Transport::Http3 => {
+                let quic_connection = self
+                    .connect_quic(resolver, client_config, domain, keepalive)
+                    .await?;
+                try_http3_connect(quic_connection).await
+            }
+            #[cfg(feature = "doq")]
+            Transport::Doq => {
+                let quic_connection = self
+                    .connect_quic(resolver, client_config, domain, keepalive)
+                    .await?;
+                Ok(Connection::Doq(quic_connection))
+            }
+            transport => {
+                self.connect_tls(resolver, client_config, domain, transport, idle_timeout)
+                    .await
+            }
+        }
+    }
+
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    async fn connect_quic(
+        &self,
+        resolver: &Resolver,
+        client_config: &Arc<ClientConfig>,
+        domain: &str,
+        keepalive: Option<Duration>,
+    ) -> DohResult<quinn::Connection> {
+        match self {
+            Host::Direct(remote_host, remote_port) => {
+                try_quic_connect(
+                    resolver,
+                    remote_host,
+                    *remote_port,
+                    client_config,
+                    domain,
+                    keepalive,
+                )
+                .await
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(crate::DohError::QuicProxy),
+        }
+    }
+
+    async fn connect_tls(
+        &self,
+        resolver: &Resolver,
+        client_config: &Arc<ClientConfig>,
+        domain: &str,
+        transport: Transport,
+        idle_timeout: Option<Duration>,
+    ) -> DohResult<Connection> {
         match self {
             Host::Direct(remote_host, remote_port) => {
-                let tcp_connection = try_tcp_connect(remote_host, *remote_port).await?;
+                let tcp_connection = try_tcp_connect(resolver, remote_host, *remote_port).await?;
                 let tls_connection = try_tls_connect(tcp_connection, client_config, domain).await?;
-                let http2_connection = try_http2_connect(tls_connection).await?;
-                Ok(http2_connection)
+                try_stream_connect(tls_connection, transport, idle_timeout).await
             }
             #[cfg(feature = "socks5")]
-            Host::Socks5(proxy_host, proxy_port, credentials, remote_addrs) => {
-                let tcp_connection =
-                    try_socks5_connect(proxy_host, *proxy_port, remote_addrs, credentials).await?;
+            Host::Socks5(proxy_host, proxy_port, credentials, remote_host, remote_port) => {
+                let tcp_connection = try_socks5_connect(
+                    resolver,
+                    proxy_host,
+                    *proxy_port,
+                    remote_host,
+                    *remote_port,
+                    credentials,
+                )
+                .await?;
                 let tls_connection = try_tls_connect(tcp_connection, client_config, domain).await?;
-                let http2_connection = try_http2_connect(tls_connection).await?;
-                Ok(http2_connection)
+                try_stream_connect(tls_connection, transport, idle_timeout).await
             }
             #[cfg(feature = "socks5")]
             Host::Socks5h(proxy_host, proxy_port, credentials, remote_host, remote_port) => {
                 let tcp_connection = try_socks5h_connect(
+                    resolver,
                     proxy_host,
                     *proxy_port,
                     remote_host,
@@ -67,17 +188,15 @@ impl Host {
                 )
                 .await?;
                 let tls_connection = try_tls_connect(tcp_connection, client_config, domain).await?;
-                let http2_connection = try_http2_connect(tls_connection).await?;
-                Ok(http2_connection)
+                try_stream_connect(tls_connection, transport, idle_timeout).await
             }
             #[cfg(feature = "http-proxy")]
             Host::HttpProxy(proxy_host, proxy_port, credentials, remote_host, remote_port) => {
-                let mut tcp_connection = try_tcp_connect(proxy_host, *proxy_port).await?;
+                let mut tcp_connection = try_tcp_connect(resolver, proxy_host, *proxy_port).await?;
                 try_http_proxy_connect(&mut tcp_connection, remote_host, *remote_port, credentials)
                     .await?;
                 let tls_connection = try_tls_connect(tcp_connection, client_config, domain).await?;
-                let http2_connection = try_http2_connect(tls_connection).await?;
-                Ok(http2_connection)
+                try_stream_connect(tls_connection, transport, idle_timeout).await
             }
             #[cfg(feature = "http-proxy")]
             Host::HttpsProxy(
@@ -89,14 +208,13 @@ impl Host {
                 https_client_config,
                 https_domain,
             ) => {
-                let tcp_connection = try_tcp_connect(proxy_host, *proxy_port).await?;
+                let tcp_connection = try_tcp_connect(resolver, proxy_host, *proxy_port).await?;
                 let mut tls_connection =
                     try_tls_connect(tcp_connection, https_client_config, https_domain).await?;
                 try_http_proxy_connect(&mut tls_connection, remote_host, *remote_port, credentials)
                     .await?;
                 let tls_connection = try_tls_connect(tls_connection, client_config, domain).await?;
-                let http2_connection = try_http2_connect(tls_connection).await?;
-                Ok(http2_connection)
+                try_stream_connect(tls_connection, transport, idle_timeout).await
             }
         }
     }
@@ -107,11 +225,11 @@ impl Display for Host {
         match self {
             Host::Direct(remote_host, remote_port) => write!(f, "{}:{}", remote_host, remote_port),
             #[cfg(feature = "socks5")]
-            Host::Socks5(proxy_host, proxy_port, _, remote_addrs) => {
+            Host::Socks5(proxy_host, proxy_port, _, remote_host, remote_port) => {
                 write!(
                     f,
-                    "{}:{} via socks5 {:?}",
-                    proxy_host, proxy_port, remote_addrs
+                    "{}:{} via socks5 {}:{}",
+                    proxy_host, proxy_port, remote_host, remote_port
                 )
             }
             #[cfg(feature = "socks5")]
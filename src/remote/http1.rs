@@ -0,0 +1,164 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::client::conn::http1::SendRequest;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// An idle keep-alive HTTP/1.1 connection of the pool.
+struct Http1Connection {
+    send_request: SendRequest<Full<Bytes>>,
+    idle_since: Instant,
+}
+
+impl Http1Connection {
+    fn new(send_request: SendRequest<Full<Bytes>>) -> Self {
+        Http1Connection {
+            send_request,
+            idle_since: Instant::now(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Http1Connections {
+    idle: Vec<Http1Connection>,
+    /// The number of connections, which send a request at the moment.
+    busy: usize,
+    /// The number of connections, which are opened at the moment.
+    connecting: usize,
+    /// The time after that an idle connection, which is not the last one, is closed.
+    idle_timeout: Option<Duration>,
+}
+
+impl Http1Connections {
+    /// Remove the closed connections and the idle connections except the last one.
+    fn shrink(&mut self) {
+        self.idle
+            .retain(|connection| !connection.send_request.is_closed());
+        let mut open = self.idle.len() + self.busy;
+        let idle_timeout = self.idle_timeout;
+        self.idle.retain(|connection| {
+            let idle = idle_timeout
+                .is_some_and(|idle_timeout| connection.idle_since.elapsed() >= idle_timeout);
+            if idle && open > 1 {
+                debug!("Close idle HTTP1 connection");
+                open -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Take the most recently used idle connection.
+    fn take(&mut self, pool: &Http1Pool) -> Option<Http1Sender> {
+        self.shrink();
+        let connection = self.idle.pop()?;
+        self.busy += 1;
+        Some(Http1Sender {
+            pool: pool.clone(),
+            send_request: Some(connection.send_request),
+            reusable: false,
+        })
+    }
+}
+
+/// The keep-alive HTTP/1.1 connections to a remote host. Each connection sends one request at the
+/// same time, therefore the connection is taken out of the pool while the request is sent.
+#[derive(Clone)]
+pub(super) struct Http1Pool {
+    connections: Arc<Mutex<Http1Connections>>,
+    /// Is notified, when a connection is put back into the pool or closed.
+    released: Arc<Notify>,
+}
+
+impl Http1Pool {
+    pub(super) fn new(
+        send_request: SendRequest<Full<Bytes>>,
+        idle_timeout: Option<Duration>,
+    ) -> Http1Pool {
+        let connections = Http1Connections {
+            idle: vec![Http1Connection::new(send_request)],
+            busy: 0,
+            connecting: 0,
+            idle_timeout,
+        };
+        Http1Pool {
+            connections: Arc::new(Mutex::new(connections)),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Take an idle connection, which is still open.
+    pub(super) fn take(&self) -> Option<Http1Sender> {
+        self.connections.lock().unwrap().take(self)
+    }
+
+    /// Reserve the opening of another connection, if the number of the connections is less than
+    /// `max_connections`. The reservation has to be finished with `insert` or `cancel`.
+    pub(super) fn reserve(&self, max_connections: usize) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        connections.shrink();
+        if connections.idle.len() + connections.busy + connections.connecting < max_connections {
+            connections.connecting += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Cancel the reservation, because the connection could not be opened.
+    pub(super) fn cancel(&self) {
+        self.connections.lock().unwrap().connecting -= 1;
+        self.released.notify_one();
+    }
+
+    /// Move the connections of the other pool into this pool, finish the reservation and take one
+    /// of them, so no other request can take the new connection in the meantime.
+    pub(super) fn insert(&self, other: Http1Pool) -> Option<Http1Sender> {
+        let other = std::mem::take(&mut other.connections.lock().unwrap().idle);
+        let mut connections = self.connections.lock().unwrap();
+        connections.connecting -= 1;
+        connections.idle.extend(other);
+        connections.take(self)
+    }
+
+    /// Wait until a connection is put back into the pool or closed.
+    pub(super) async fn released(&self) {
+        self.released.notified().await
+    }
+}
+
+/// A connection, which is taken out of the pool. The connection is put back into the pool, when it
+/// is dropped, if the response was received completely.
+pub(super) struct Http1Sender {
+    pool: Http1Pool,
+    send_request: Option<SendRequest<Full<Bytes>>>,
+    reusable: bool,
+}
+
+impl Http1Sender {
+    pub(super) fn send_request(&mut self) -> &mut SendRequest<Full<Bytes>> {
+        self.send_request.as_mut().unwrap()
+    }
+
+    /// Put the connection back into the pool after the response is received, so it can be reused.
+    pub(super) fn put(mut self) {
+        self.reusable = true;
+    }
+}
+
+impl Drop for Http1Sender {
+    fn drop(&mut self) {
+        let send_request = self.send_request.take().unwrap();
+        {
+            let mut connections = self.pool.connections.lock().unwrap();
+            connections.busy -= 1;
+            if self.reusable && !send_request.is_closed() {
+                connections.idle.push(Http1Connection::new(send_request));
+            }
+        }
+        self.pool.released.notify_one();
+    }
+}
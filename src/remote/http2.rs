@@ -0,0 +1,305 @@
+use bytes::Bytes;
+use h2::client::{Connection, SendRequest};
+use h2::{Ping, PingPong};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::spawn;
+use tokio::task::AbortHandle;
+use tokio::time::timeout;
+
+/// An HTTP/2 connection of the pool and the number of its streams, which are in use.
+struct Http2Connection {
+    id: u64,
+    send_request: SendRequest<Bytes>,
+    /// Is taken while a PING is sent.
+    ping_pong: Option<PingPong>,
+    /// Is set by the task of the connection, when the connection is closed.
+    closed: Arc<AtomicBool>,
+    task: AbortHandle,
+    streams: usize,
+    idle_since: Instant,
+}
+
+impl Http2Connection {
+    /// Spawn the task of the connection.
+    fn new<T>(
+        id: u64,
+        send_request: SendRequest<Bytes>,
+        mut connection: Connection<T, Bytes>,
+    ) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let ping_pong = connection.ping_pong();
+        let closed = Arc::new(AtomicBool::new(false));
+        let is_closed = closed.clone();
+        let task = spawn(async move {
+            if let Err(e) = connection.await {
+                error!("HTTP2 connection close: {}", e);
+            }
+            is_closed.store(true, Ordering::Relaxed);
+        });
+        Http2Connection {
+            id,
+            send_request,
+            ping_pong,
+            closed,
+            task: task.abort_handle(),
+            streams: 0,
+            idle_since: Instant::now(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Close the connection immediately, e.g. if the server does not answer the PINGs anymore.
+    fn abort(&self) {
+        self.task.abort();
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the number of streams reached the maximum number of concurrent streams,
+    /// which is allowed by the server.
+    fn is_full(&self) -> bool {
+        self.send_request.current_max_send_streams() <= self.streams
+    }
+}
+
+/// The result of the keepalive of the pool.
+pub(super) enum Keepalive {
+    /// The pool has open connections.
+    Alive,
+    /// All connections are closed, because they were idle for the idle timeout or the server
+    /// closed them.
+    Idle,
+    /// All connections are closed and at least one of them did not answer a PING.
+    Dead,
+}
+
+#[derive(Default)]
+struct Http2Connections {
+    next_id: u64,
+    connections: Vec<Http2Connection>,
+    /// The number of connections, which are opened at the moment.
+    connecting: usize,
+    /// The time after that an idle connection, which is not the last one, is closed.
+    idle_timeout: Option<Duration>,
+}
+
+impl Http2Connections {
+    /// Remove the closed connections and the idle connections except the last one.
+    fn shrink(&mut self) {
+        self.connections
+            .retain(|connection| !connection.is_closed());
+        let mut open = self.connections.len();
+        let idle_timeout = self.idle_timeout;
+        self.connections.retain(|connection| {
+            let idle = connection.streams == 0
+                && idle_timeout
+                    .is_some_and(|idle_timeout| connection.idle_since.elapsed() >= idle_timeout);
+            if idle && open > 1 {
+                debug!("Close idle HTTP2 connection");
+                open -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// The HTTP/2 connections to a remote host. The number of streams of each connection is tracked, so
+/// another connection can be opened, if all connections reached the maximum number of concurrent
+/// streams of the server.
+#[derive(Clone)]
+pub(super) struct Http2Pool {
+    connections: Arc<Mutex<Http2Connections>>,
+}
+
+impl Http2Pool {
+    /// Create a pool of the connection after the handshake and spawn the task of it.
+    pub(super) fn new<T>(
+        send_request: SendRequest<Bytes>,
+        connection: Connection<T, Bytes>,
+        idle_timeout: Option<Duration>,
+    ) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let connection = Http2Connection::new(0, send_request, connection);
+        let connections = Http2Connections {
+            next_id: 1,
+            connections: vec![connection],
+            connecting: 0,
+            idle_timeout,
+        };
+        Http2Pool {
+            connections: Arc::new(Mutex::new(connections)),
+        }
+    }
+
+    /// Returns `true` if all connections are closed.
+    pub(super) fn is_closed(&self) -> bool {
+        let connections = self.connections.lock().unwrap();
+        connections
+            .connections
+            .iter()
+            .all(Http2Connection::is_closed)
+    }
+
+    /// Reserve the opening of another connection, if all open connections are full and the number
+    /// of the connections is less than `max_connections`. The reservation has to be finished with
+    /// `append` or `cancel`.
+    pub(super) fn reserve(&self, max_connections: usize) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        connections.shrink();
+        let full = connections.connections.iter().all(Http2Connection::is_full);
+        if full && connections.connections.len() + connections.connecting < max_connections {
+            connections.connecting += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Cancel the reservation, because the connection could not be opened.
+    pub(super) fn cancel(&self) {
+        self.connections.lock().unwrap().connecting -= 1;
+    }
+
+    /// Move the connections of the other pool into this pool and finish the reservation.
+    pub(super) fn append(&self, other: Http2Pool) {
+        let other = std::mem::take(&mut other.connections.lock().unwrap().connections);
+        let mut connections = self.connections.lock().unwrap();
+        connections.connecting -= 1;
+        for mut connection in other {
+            connection.id = connections.next_id;
+            connections.next_id += 1;
+            connections.connections.push(connection);
+        }
+    }
+
+    /// Take a stream of the open connection with the fewest streams in use. If all connections are
+    /// full, then the stream waits until the server allows to open it.
+    pub(super) fn take(&self) -> Option<Http2Stream> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.shrink();
+        let connection = connections
+            .connections
+            .iter_mut()
+            .min_by_key(|connection| connection.streams)?;
+        connection.streams += 1;
+        Some(Http2Stream {
+            pool: self.clone(),
+            id: connection.id,
+            send_request: connection.send_request.clone(),
+        })
+    }
+
+    /// Release the stream of the connection.
+    fn release(&self, id: u64) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(connection) = connections
+            .connections
+            .iter_mut()
+            .find(|connection| connection.id == id)
+        {
+            connection.streams -= 1;
+            if connection.streams == 0 {
+                connection.idle_since = Instant::now();
+            }
+        }
+    }
+
+    /// Close all connections, if they were idle for `idle_timeout`. Otherwise send a PING over
+    /// each connection, which was idle for `keepalive`, and close the connection, if the PONG is
+    /// not received within `keepalive`.
+    pub(super) async fn keepalive(
+        &self,
+        keepalive: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> Keepalive {
+        let pings = {
+            let mut connections = self.connections.lock().unwrap();
+            connections.shrink();
+            let idle = |connection: &Http2Connection, timeout| {
+                connection.streams == 0 && connection.idle_since.elapsed() >= timeout
+            };
+            if let Some(idle_timeout) = idle_timeout {
+                if connections
+                    .connections
+                    .iter()
+                    .all(|connection| idle(connection, idle_timeout))
+                {
+                    connections.connections.clear();
+                    return Keepalive::Idle;
+                }
+            }
+            match keepalive {
+                Some(keepalive) => connections
+                    .connections
+                    .iter_mut()
+                    .filter(|connection| idle(connection, keepalive))
+                    .filter_map(|connection| Some((connection.id, connection.ping_pong.take()?)))
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+
+        let mut dead = false;
+        for (id, mut ping_pong) in pings {
+            let keepalive = keepalive.unwrap();
+            let pong = timeout(keepalive, ping_pong.ping(Ping::opaque())).await;
+            let mut connections = self.connections.lock().unwrap();
+            let Some(connection) = connections
+                .connections
+                .iter_mut()
+                .find(|connection| connection.id == id)
+            else {
+                continue;
+            };
+            match pong {
+                Ok(Ok(_)) => {
+                    connection.ping_pong.replace(ping_pong);
+                }
+                Ok(Err(e)) => {
+                    error!("HTTP2 PING failed: {}", e);
+                    connection.abort();
+                    dead = true;
+                }
+                Err(_) => {
+                    error!("HTTP2 PING timed out");
+                    connection.abort();
+                    dead = true;
+                }
+            }
+        }
+
+        let mut connections = self.connections.lock().unwrap();
+        connections.shrink();
+        if !connections.connections.is_empty() {
+            Keepalive::Alive
+        } else if dead {
+            Keepalive::Dead
+        } else {
+            Keepalive::Idle
+        }
+    }
+}
+
+/// A stream of a connection of the pool, which is released when it is dropped.
+pub(super) struct Http2Stream {
+    pool: Http2Pool,
+    id: u64,
+    pub(super) send_request: SendRequest<Bytes>,
+}
+
+impl Drop for Http2Stream {
+    fn drop(&mut self) {
+        self.pool.release(self.id);
+    }
+}
@@ -1,15 +1,48 @@
 mod config;
+mod connection;
+#[cfg(feature = "doq")]
+mod doq;
+mod dot;
+mod ech;
 mod helper;
 mod host;
+mod http1;
+mod http2;
+#[cfg(feature = "odoh")]
+mod odoh;
 mod response;
+mod selector;
 mod session;
+mod transport;
+mod upstreams;
 
 use config::Config;
+use connection::{Connection, PendingRequest};
+#[cfg(feature = "doq")]
+use doq::{doq_response_handler, encode_query};
+use dot::{dot_response_handler, DotConnection};
+pub(crate) use ech::{parse_ech_config_list, Ech};
+#[cfg(feature = "http3")]
+use helper::try_http3_connect;
 #[cfg(feature = "http-proxy")]
 use helper::try_http_proxy_connect;
-use helper::{try_http2_connect, try_tcp_connect, try_tls_connect};
+#[cfg(any(feature = "http3", feature = "doq"))]
+use helper::try_quic_connect;
 #[cfg(feature = "socks5")]
 use helper::{try_socks5_connect, try_socks5h_connect};
+use helper::{try_stream_connect, try_tcp_connect, try_tls_connect};
 pub use host::Host;
-use response::response_handler;
+use http1::{Http1Pool, Http1Sender};
+use http2::{Http2Pool, Http2Stream, Keepalive};
+#[cfg(feature = "odoh")]
+use odoh::Odoh;
+#[cfg(feature = "odoh")]
+pub(crate) use odoh::{parse_odoh_configs, OdohTarget};
+#[cfg(feature = "http3")]
+use response::http3_response_handler;
+use response::{decode_response, http1_response_handler, http2_response_handler};
+use selector::Selector;
+pub use selector::{HedgeDelay, Strategy};
 pub(crate) use session::Session;
+pub use transport::Transport;
+pub(crate) use upstreams::Upstreams;
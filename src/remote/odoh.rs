@@ -0,0 +1,209 @@
+use super::{Connection, Host, Transport};
+use crate::helper::split_host_port;
+use crate::{DohError, DohResult, Resolver};
+use bytes::Bytes;
+use futures::lock::Mutex;
+use getrandom::rand_core::UnwrapErr;
+use getrandom::SysRng;
+use odoh_rs::{
+    compose, decrypt_response, encrypt_query, parse, ObliviousDoHConfigContents,
+    ObliviousDoHConfigs, ObliviousDoHMessage, ObliviousDoHMessagePlaintext, OdohSecret,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_rustls::rustls::ClientConfig;
+
+/// The path of the ODoH configs of the target (see RFC 9230 section 6).
+const CONFIGS_PATH: &str = "/.well-known/odohconfigs";
+
+/// The time after that the ODoH config is fetched again, if the target does not send a cache
+/// duration.
+const CONFIG_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// The queries are padded to a multiple of this size, so the length does not reveal the query.
+const PADDING_BLOCK_SIZE: usize = 128;
+
+/// The target of Oblivious DoH (see RFC 9230), which decrypts the queries. The queries are sent
+/// through the remote host, which acts as the relay and does not see the content of them.
+pub struct OdohTarget {
+    host: String,
+    port: u16,
+    path: String,
+    client_config: Arc<ClientConfig>,
+    /// The ODoH config, which is given instead of fetching it from the target.
+    config: Option<ObliviousDoHConfigContents>,
+}
+
+/// Parse the ODoH configs (see RFC 9230 section 6) and return the first supported one.
+pub(crate) fn parse_odoh_configs(mut bytes: Bytes) -> DohResult<ObliviousDoHConfigContents> {
+    let configs: ObliviousDoHConfigs = parse(&mut bytes)?;
+    match configs.supported().into_iter().next() {
+        Some(config) => Ok(config.into()),
+        None => Err(DohError::OdohConfig),
+    }
+}
+
+impl OdohTarget {
+    /// Create a new ODoH target from `Host[:Port][/Path]`, where an IPv6 address is enclosed in
+    /// brackets. The path is `dns-query`, if it is not given. The `client_config` is only used to
+    /// fetch the ODoH config from the target.
+    pub(crate) fn new(target: &str, client_config: Arc<ClientConfig>) -> DohResult<OdohTarget> {
+        let (host_port, path) = target.split_once('/').unwrap_or((target, "dns-query"));
+        let (host, port) = match split_host_port(host_port) {
+            Some((host, Some(port))) => match port.parse() {
+                Ok(port) => (host, port),
+                Err(_) => return Err(DohError::OdohTarget(target.to_owned())),
+            },
+            Some((host, None)) => (host, 443),
+            None => return Err(DohError::OdohTarget(target.to_owned())),
+        };
+        if host.is_empty() {
+            return Err(DohError::OdohTarget(target.to_owned()));
+        }
+        Ok(OdohTarget {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+            client_config,
+            config: None,
+        })
+    }
+
+    /// Use the ODoH config instead of fetching it from the target.
+    pub(crate) fn set_config(&mut self, config: ObliviousDoHConfigContents) {
+        self.config.replace(config);
+    }
+
+    /// The authority of the target, the port is omitted, if it is the default one.
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == 443 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    /// The URI of the relay, which forwards the queries to the target.
+    pub(crate) fn relay_uri(&self, relay_uri: &str) -> String {
+        let separator = if relay_uri.contains('?') { '&' } else { '?' };
+        format!(
+            "{}{}targethost={}&targetpath=/{}",
+            relay_uri,
+            separator,
+            self.authority(),
+            self.path
+        )
+    }
+
+    /// Fetch the ODoH configs from the target over the proxy of the relay `host` and return the
+    /// first supported one. Without a proxy, the target sees the address of the client.
+    async fn fetch_config(
+        &self,
+        resolver: &Resolver,
+        host: &Host,
+    ) -> DohResult<(ObliviousDoHConfigContents, Option<Duration>)> {
+        let host = host.with_remote(&self.host, self.port);
+        if host.is_direct() {
+            warn!(
+                "Fetch ODoH config directly from {}, which sees the address of the client",
+                self.host
+            );
+        } else {
+            debug!("Fetch ODoH config from {}", host);
+        }
+        let mut connection: Connection = host
+            .connect(
+                resolver,
+                &self.client_config,
+                &self.host,
+                Transport::Http2,
+                None,
+                None,
+            )
+            .await?;
+        let uri = format!("https://{}{}", self.authority(), CONFIGS_PATH);
+        let (body, duration) = connection.get(&uri).await?;
+        Ok((parse_odoh_configs(body)?, duration))
+    }
+}
+
+/// The state of the ODoH target of a session. The config is locked while it is fetched, so
+/// concurrent queries wait for the same config instead of fetching it again.
+pub(super) struct Odoh {
+    target: OdohTarget,
+    config: Mutex<Option<(ObliviousDoHConfigContents, Instant)>>,
+}
+
+/// A query, which is encrypted for the target, and the secret to decrypt the response of it.
+pub(super) struct OdohQuery {
+    plaintext: ObliviousDoHMessagePlaintext,
+    secret: OdohSecret,
+}
+
+impl Odoh {
+    pub(super) fn new(target: OdohTarget) -> Odoh {
+        Odoh {
+            target,
+            config: Mutex::new(None),
+        }
+    }
+
+    /// Forget the ODoH config, so it is fetched again for the next query. This is done, if the
+    /// target could not decrypt the query or the response could not be decrypted, because the
+    /// target could have rotated its key.
+    pub(super) async fn reset(&self) {
+        self.config.lock().await.take();
+    }
+
+    async fn config(
+        &self,
+        resolver: &Resolver,
+        host: &Host,
+    ) -> DohResult<ObliviousDoHConfigContents> {
+        if let Some(contents) = &self.target.config {
+            return Ok(contents.clone());
+        }
+        let mut config = self.config.lock().await;
+        if let Some((_, expire)) = config.as_ref() {
+            if Instant::now() >= *expire {
+                config.take();
+            }
+        }
+        if let Some((contents, _)) = config.as_ref() {
+            return Ok(contents.clone());
+        }
+        let (contents, duration) = self.target.fetch_config(resolver, host).await?;
+        let expire = Instant::now() + duration.unwrap_or(CONFIG_LIFETIME);
+        config.replace((contents.clone(), expire));
+        Ok(contents)
+    }
+
+    /// Encrypt the DNS message `data` with the ODoH config of the target.
+    pub(super) async fn encrypt(
+        &self,
+        resolver: &Resolver,
+        host: &Host,
+        data: Bytes,
+    ) -> DohResult<(Bytes, OdohQuery)> {
+        let padding_len = PADDING_BLOCK_SIZE - data.len() % PADDING_BLOCK_SIZE;
+        let plaintext = ObliviousDoHMessagePlaintext::new(data, padding_len);
+        let config = self.config(resolver, host).await?;
+        let (message, secret) = encrypt_query(&plaintext, &config, &mut UnwrapErr(SysRng))?;
+        let message = compose(&message)?.freeze();
+        Ok((message, OdohQuery { plaintext, secret }))
+    }
+}
+
+impl OdohQuery {
+    /// Decrypt the response of the target and return the DNS message.
+    pub(super) fn decrypt(self, mut body: Bytes) -> DohResult<Bytes> {
+        let message: ObliviousDoHMessage = parse(&mut body)?;
+        let response = decrypt_response(&self.plaintext, &message, self.secret)?;
+        Ok(response.into_msg())
+    }
+}
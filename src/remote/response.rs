@@ -1,9 +1,13 @@
+use super::{Http1Sender, Http2Stream};
 use crate::{DohError, DohResult};
 use bytes::{Bytes, BytesMut};
 use dns_message_parser::{Dns, MAXIMUM_DNS_PACKET_SIZE};
-use h2::client::ResponseFuture;
 use h2::RecvStream;
+use http::header::HOST;
 use http::response::Parts;
+use http::{HeaderValue, Request, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Body;
 use std::time::Duration;
 
 fn check_header_status(header: &Parts) -> DohResult<()> {
@@ -14,10 +18,10 @@ fn check_header_status(header: &Parts) -> DohResult<()> {
     }
 }
 
-fn check_header_content_type(header: &Parts) -> DohResult<()> {
+fn check_header_content_type(header: &Parts, content_type: &str) -> DohResult<()> {
     match header.headers.get("content-type") {
         Some(value) => {
-            if value == "application/dns-message" {
+            if value == content_type {
                 Ok(())
             } else {
                 Err(DohError::HeaderContentType(value.clone()))
@@ -83,24 +87,25 @@ fn get_min_ttl(dns: &Dns) -> Option<Duration> {
     Some(Duration::from_secs(min_ttl as u64))
 }
 
+/// Append the chunk to the body. Returns `false` if the maximum size of a DNS packet is reached.
+fn extend_body(body: &mut BytesMut, chunk: Bytes) -> bool {
+    let body_len = body.len();
+    if body_len + chunk.len() < MAXIMUM_DNS_PACKET_SIZE {
+        body.extend(chunk);
+        true
+    } else {
+        body.extend(chunk.slice(0..MAXIMUM_DNS_PACKET_SIZE - body_len));
+        false
+    }
+}
+
 async fn get_body(recv_stream: &mut RecvStream) -> DohResult<Bytes> {
     let mut body = BytesMut::new();
     while let Some(result) = recv_stream.data().await {
         match result {
             Ok(b) => {
-                let body_len = body.len();
-                let b_len = b.len();
-
-                recv_stream.flow_control().release_capacity(b_len)?;
-
-                if body_len < MAXIMUM_DNS_PACKET_SIZE {
-                    if body_len + b_len < MAXIMUM_DNS_PACKET_SIZE {
-                        body.extend(b);
-                    } else {
-                        body.extend(b.slice(0..MAXIMUM_DNS_PACKET_SIZE - body_len));
-                        break;
-                    }
-                } else {
+                recv_stream.flow_control().release_capacity(b.len())?;
+                if !extend_body(&mut body, b) {
                     break;
                 }
             }
@@ -117,32 +122,125 @@ async fn get_body(recv_stream: &mut RecvStream) -> DohResult<Bytes> {
     Ok(body.freeze())
 }
 
-async fn get_dns_response(recv_stream: &mut RecvStream) -> DohResult<Dns> {
-    let body = get_body(recv_stream).await?;
+/// Check the header of the response and get the cache duration of it. The content type is only
+/// checked, if it is given.
+fn check_header(header: &Parts, content_type: Option<&str>) -> DohResult<Option<Duration>> {
+    check_header_status(header)?;
+    if let Some(content_type) = content_type {
+        check_header_content_type(header, content_type)?;
+    }
+    Ok(get_duration(header))
+}
+
+/// Decode the body of the response. If the header has no cache duration, then the minimum TTL of
+/// the records is used.
+pub(super) fn decode_response(
+    body: Bytes,
+    duration: Option<Duration>,
+) -> DohResult<(Dns, Option<Duration>)> {
     let dns_response = Dns::decode(body)?;
     if !dns_response.is_response() {
         return Err(DohError::DnsNotResponse(dns_response));
     }
-    Ok(dns_response)
+
+    let duration = duration.or_else(|| get_min_ttl(&dns_response));
+
+    Ok((dns_response, duration))
 }
 
-pub(super) async fn response_handler(
-    response_future: ResponseFuture,
-) -> DohResult<(Dns, Option<Duration>)> {
+/// Send the request over a stream of the HTTP/2 pool, which is released after the response is
+/// received.
+pub(super) async fn http2_response_handler(
+    stream: Http2Stream,
+    request: Request<()>,
+    body: Option<Bytes>,
+    content_type: Option<&str>,
+) -> DohResult<(Bytes, Option<Duration>)> {
+    // Wait until the connection is ready to open another stream.
+    let mut send_request = stream.send_request.clone().ready().await?;
+    let (response_future, mut send_stream) = send_request.send_request(request, body.is_none())?;
+    if let Some(body) = body {
+        debug!("Send HTTP2 body: {:?}", body);
+        send_stream.send_data(body, true)?;
+    }
+
     let response = response_future.await?;
     let (header, mut recv_stream) = response.into_parts();
 
-    check_header_status(&header)?;
-    check_header_content_type(&header)?;
-    let mut duration = get_duration(&header);
+    let duration = check_header(&header, content_type)?;
+    let body = get_body(&mut recv_stream).await?;
+    Ok((body, duration))
+}
+
+/// Send the request over a keep-alive HTTP/1.1 connection and put the connection back into the
+/// pool after the response is received.
+pub(super) async fn http1_response_handler(
+    mut sender: Http1Sender,
+    request: Request<()>,
+    body: Option<Bytes>,
+    content_type: Option<&str>,
+) -> DohResult<(Bytes, Option<Duration>)> {
+    // HTTP/1.1 uses the origin form of the URI and the host header instead of the authority.
+    let (mut parts, _) = request.into_parts();
+    if let Some(authority) = parts.uri.authority() {
+        let host = HeaderValue::from_str(authority.as_str()).unwrap();
+        parts.headers.insert(HOST, host);
+    }
+    if let Some(path_and_query) = parts.uri.path_and_query() {
+        parts.uri = Uri::from(path_and_query.clone());
+    }
+    let request = Request::from_parts(parts, Full::new(body.unwrap_or_default()));
+
+    // The connection is not reused, if it is not ready anymore.
+    let send_request = sender.send_request();
+    send_request.ready().await?;
+    let response = send_request.send_request(request).await?;
+    let (header, mut incoming) = response.into_parts();
+
+    let duration = check_header(&header, content_type)?;
+    let mut body = BytesMut::new();
+    while let Some(frame) = incoming.frame().await {
+        if let Ok(chunk) = frame?.into_data() {
+            if !extend_body(&mut body, chunk) {
+                break;
+            }
+        }
+    }
+    // The connection can only be reused, if the whole body is received.
+    if incoming.is_end_stream() {
+        sender.put();
+    }
+    Ok((body.freeze(), duration))
+}
 
-    let dns_response = get_dns_response(&mut recv_stream).await?;
+#[cfg(feature = "http3")]
+pub(super) async fn http3_response_handler(
+    mut send_request: h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>,
+    request: Request<()>,
+    body: Option<Bytes>,
+    content_type: Option<&str>,
+) -> DohResult<(Bytes, Option<Duration>)> {
+    use bytes::Buf;
 
-    if duration.is_none() {
-        duration = get_min_ttl(&dns_response);
+    let mut request_stream = send_request.send_request(request).await?;
+    if let Some(body) = body {
+        debug!("Send HTTP3 body: {:?}", body);
+        request_stream.send_data(body).await?;
     }
+    request_stream.finish().await?;
 
-    Ok((dns_response, duration))
+    let response = request_stream.recv_response().await?;
+    let (header, _) = response.into_parts();
+
+    let duration = check_header(&header, content_type)?;
+    let mut body = BytesMut::new();
+    while let Some(mut chunk) = request_stream.recv_data().await? {
+        let chunk = chunk.copy_to_bytes(chunk.remaining());
+        if !extend_body(&mut body, chunk) {
+            break;
+        }
+    }
+    Ok((body.freeze(), duration))
 }
 
 #[cfg(test)]
@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The weight of a new latency sample for the exponentially weighted moving average.
+const EWMA_ALPHA: f64 = 0.3;
+
+/// The number of recent latency samples, which are kept for the percentile of the hedge delay.
+const LATENCY_SAMPLES: usize = 64;
+
+/// The strategy to select the remote host for a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Use the first remote host and the next one only if it fails.
+    Failover,
+    /// Use the remote hosts one after another.
+    RoundRobin,
+    /// Use a random remote host.
+    Random,
+    /// Use a random remote host with a probability proportional to its weight.
+    Weighted,
+    /// Use the remote host with the lowest measured latency.
+    Fastest,
+}
+
+/// The delay after that the query is sent to a second remote host, if the first remote host has
+/// not answered yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HedgeDelay {
+    /// A fixed delay.
+    Fixed(Duration),
+    /// The percentile (1-100) of the recent latencies of the first remote host.
+    Percentile(u8),
+}
+
+#[derive(Default)]
+struct Stats {
+    /// The exponentially weighted moving average of the latency in seconds.
+    latency: Option<f64>,
+    /// The recent latencies.
+    samples: VecDeque<Duration>,
+    /// The time until the remote host is considered as down.
+    down_until: Option<Instant>,
+}
+
+impl Stats {
+    fn is_down(&self, now: Instant) -> bool {
+        match self.down_until {
+            Some(down_until) => now < down_until,
+            None => false,
+        }
+    }
+}
+
+struct State {
+    /// The index of the remote host, which is used for the next queries (failover) or the
+    /// counter of the queries (round robin).
+    current: usize,
+    /// The time of the failover away from the first remote host.
+    failover: Option<Instant>,
+    stats: Vec<Stats>,
+}
+
+/// Select the remote host for a query.
+///
+/// With the failover strategy, the first remote host is preferred, if it fails then the next
+/// remote host is used. After the failback time the first remote host is tried again. With the
+/// other strategies, a failed remote host is tried last until the failback time is over.
+pub(super) struct Selector {
+    strategy: Strategy,
+    weights: Vec<u32>,
+    state: Mutex<State>,
+    failback: Duration,
+}
+
+impl Selector {
+    pub(super) fn new(strategy: Strategy, weights: Vec<u32>, failback: Duration) -> Selector {
+        let state = State {
+            current: 0,
+            failover: None,
+            stats: weights.iter().map(|_| Stats::default()).collect(),
+        };
+        Selector {
+            strategy,
+            weights,
+            state: Mutex::new(state),
+            failback,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    fn first_weighted(&self) -> usize {
+        let total: u64 = self.weights.iter().map(|weight| *weight as u64).sum();
+        if total == 0 {
+            return 0;
+        }
+        let mut value = fastrand::u64(0..total);
+        for (index, weight) in self.weights.iter().enumerate() {
+            let weight = *weight as u64;
+            if value < weight {
+                return index;
+            }
+            value -= weight;
+        }
+        0
+    }
+
+    /// Get the indices of the remote hosts in the order, in which they should be tried.
+    pub(super) fn order(&self) -> Vec<usize> {
+        let len = self.len();
+        let mut state = self.state.lock().unwrap();
+        let first = match self.strategy {
+            Strategy::Failover => {
+                if let Some(failover) = state.failover {
+                    if failover.elapsed() >= self.failback {
+                        info!("Try the first remote host again");
+                        state.current = 0;
+                        state.failover = None;
+                    }
+                }
+                state.current
+            }
+            Strategy::RoundRobin => {
+                let first = state.current % len;
+                state.current = state.current.wrapping_add(1);
+                first
+            }
+            Strategy::Random => fastrand::usize(0..len),
+            Strategy::Weighted => self.first_weighted(),
+            Strategy::Fastest => 0,
+        };
+        let mut order: Vec<usize> = (0..len).map(|i| (first + i) % len).collect();
+
+        if self.strategy == Strategy::Fastest {
+            // Remote hosts without a measurement are tried first to get one.
+            let latency = |index: &usize| state.stats[*index].latency.unwrap_or_default();
+            order.sort_by(|a, b| latency(a).total_cmp(&latency(b)));
+        }
+        if self.strategy != Strategy::Failover {
+            let now = Instant::now();
+            order.sort_by_key(|index| state.stats[*index].is_down(now));
+        }
+
+        order
+    }
+
+    /// Get the delay after that the query should be sent to the next remote host, if the remote
+    /// host has not answered yet. If there are no latency samples of the remote host yet, then
+    /// `default` is returned for a percentile.
+    pub(super) fn hedge_delay(
+        &self,
+        index: usize,
+        hedge_delay: HedgeDelay,
+        default: Duration,
+    ) -> Duration {
+        match hedge_delay {
+            HedgeDelay::Fixed(delay) => delay,
+            HedgeDelay::Percentile(percentile) => {
+                let state = self.state.lock().unwrap();
+                let mut samples: Vec<Duration> =
+                    state.stats[index].samples.iter().copied().collect();
+                if samples.is_empty() {
+                    return default;
+                }
+                samples.sort_unstable();
+                let rank = (samples.len() * percentile as usize).div_ceil(100);
+                samples[rank.clamp(1, samples.len()) - 1]
+            }
+        }
+    }
+
+    /// Feed the latency of a successful query back.
+    pub(super) fn succeeded(&self, index: usize, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let stats = &mut state.stats[index];
+        if stats.samples.len() == LATENCY_SAMPLES {
+            stats.samples.pop_front();
+        }
+        stats.samples.push_back(latency);
+        let latency = latency.as_secs_f64();
+        stats.latency = match stats.latency {
+            Some(average) => Some(EWMA_ALPHA * latency + (1.0 - EWMA_ALPHA) * average),
+            None => Some(latency),
+        };
+        stats.down_until = None;
+    }
+
+    /// Mark the remote host as failed, so the other remote hosts are used for the next queries.
+    pub(super) fn failed(&self, index: usize) {
+        let len = self.len();
+        if len == 1 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.stats[index].down_until = Some(Instant::now() + self.failback);
+        if self.strategy == Strategy::Failover && state.current == index {
+            state.current = (index + 1) % len;
+            if state.current == 0 {
+                state.failover = None;
+            } else if state.failover.is_none() {
+                state.failover = Some(Instant::now());
+            }
+            warn!("Failover to remote host {}", state.current + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HedgeDelay, Selector, Strategy};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn test_failover() {
+        let selector = Selector::new(Strategy::Failover, vec![1, 1, 1], Duration::from_secs(60));
+        assert_eq!(selector.order(), vec![0, 1, 2]);
+
+        selector.failed(0);
+        assert_eq!(selector.order(), vec![1, 2, 0]);
+
+        // A failure of a remote host, which is not used anymore, is ignored.
+        selector.failed(0);
+        assert_eq!(selector.order(), vec![1, 2, 0]);
+
+        selector.failed(1);
+        selector.failed(2);
+        assert_eq!(selector.order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_failback() {
+        let selector = Selector::new(Strategy::Failover, vec![1, 1], Duration::from_secs(1));
+        selector.failed(0);
+        assert_eq!(selector.order(), vec![1, 0]);
+
+        sleep(Duration::from_secs(1));
+
+        assert_eq!(selector.order(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_single() {
+        let selector = Selector::new(Strategy::Failover, vec![1], Duration::from_secs(0));
+        selector.failed(0);
+        assert_eq!(selector.order(), vec![0]);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let selector = Selector::new(Strategy::RoundRobin, vec![1, 1, 1], Duration::from_secs(60));
+        assert_eq!(selector.order(), vec![0, 1, 2]);
+        assert_eq!(selector.order(), vec![1, 2, 0]);
+        assert_eq!(selector.order(), vec![2, 0, 1]);
+
+        selector.failed(1);
+        assert_eq!(selector.order(), vec![0, 2, 1]);
+        assert_eq!(selector.order(), vec![2, 0, 1]);
+
+        selector.succeeded(1, Duration::from_millis(10));
+        assert_eq!(selector.order(), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_random() {
+        let selector = Selector::new(Strategy::Random, vec![1, 1, 1], Duration::from_secs(60));
+        for _ in 0..16 {
+            let mut order = selector.order();
+            order.sort_unstable();
+            assert_eq!(order, vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_weighted() {
+        let selector = Selector::new(Strategy::Weighted, vec![0, 1, 0], Duration::from_secs(60));
+        for _ in 0..16 {
+            assert_eq!(selector.order(), vec![1, 2, 0]);
+        }
+    }
+
+    #[test]
+    fn test_fastest() {
+        let selector = Selector::new(Strategy::Fastest, vec![1, 1, 1], Duration::from_secs(60));
+        selector.succeeded(0, Duration::from_millis(30));
+        selector.succeeded(1, Duration::from_millis(10));
+        assert_eq!(selector.order(), vec![2, 1, 0]);
+
+        selector.succeeded(2, Duration::from_millis(20));
+        assert_eq!(selector.order(), vec![1, 2, 0]);
+
+        selector.failed(1);
+        assert_eq!(selector.order(), vec![2, 0, 1]);
+
+        // The moving average of the third remote host is 0.3 * 40 + 0.7 * 20 = 26.
+        selector.succeeded(2, Duration::from_millis(40));
+        selector.succeeded(1, Duration::from_millis(10));
+        assert_eq!(selector.order(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_hedge_delay() {
+        let selector = Selector::new(Strategy::Failover, vec![1, 1], Duration::from_secs(60));
+        let default = Duration::from_secs(1);
+        let fixed = HedgeDelay::Fixed(Duration::from_millis(50));
+        assert_eq!(
+            selector.hedge_delay(0, fixed, default),
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            selector.hedge_delay(0, HedgeDelay::Percentile(90), default),
+            default
+        );
+
+        for latency in (1..=100).rev() {
+            selector.succeeded(0, Duration::from_millis(latency));
+        }
+        // Only the last 64 samples (1-64 ms) are kept.
+        assert_eq!(
+            selector.hedge_delay(0, HedgeDelay::Percentile(90), default),
+            Duration::from_millis(58)
+        );
+        assert_eq!(
+            selector.hedge_delay(0, HedgeDelay::Percentile(100), default),
+            Duration::from_millis(64)
+        );
+        assert_eq!(
+            selector.hedge_delay(0, HedgeDelay::Percentile(1), default),
+            Duration::from_millis(1)
+        );
+        assert_eq!(
+            selector.hedge_delay(1, HedgeDelay::Percentile(90), default),
+            default
+        );
+    }
+}
@@ -1,123 +1,450 @@
-use super::{response_handler, Config, Host};
-use crate::{DohError, DohResult};
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use super::{decode_response, Config, Connection, Ech, Host, Keepalive, PendingRequest, Transport};
+#[cfg(feature = "odoh")]
+use super::{Odoh, OdohTarget};
+use crate::{DohError, DohResult, Resolver};
 use bytes::Bytes;
 use dns_message_parser::Dns;
-use h2::client::SendRequest;
-use http::Request;
+use futures::channel::oneshot::{channel, Receiver};
+use futures::future::{FutureExt, Shared};
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::spawn;
+use tokio::time::sleep;
 use tokio_rustls::rustls::ClientConfig;
 
+/// The delay after the first failed connection attempt.
+const BACKOFF_MIN: Duration = Duration::from_millis(100);
+
+/// The maximum delay between the connection attempts.
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// The delay after the `attempt`-th failed connection attempt (starting at 0). The delay doubles
+/// after each attempt and a random jitter of up to the half of it is subtracted, so the clients do
+/// not connect at the same time.
+fn backoff(attempt: u32) -> Duration {
+    let delay = BACKOFF_MIN
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX);
+    delay.mul_f64(1.0 - fastrand::f64() / 2.0)
+}
+
+/// The connection to the remote host and the connection attempt, which is in progress. The state
+/// is only locked briefly and never while connecting, so the queries do not block each other.
+#[derive(Default)]
+struct State {
+    connection_id: u32,
+    connection: Option<Connection>,
+    /// Resolves to `true` if the connection attempt in the background succeeded.
+    connecting: Option<Shared<Receiver<bool>>>,
+    /// Is `true` if all retries failed. The queries fail fast, while the remote host is probed in
+    /// the background (circuit breaker).
+    down: bool,
+}
+
 pub(crate) struct Session {
     config: Config,
     host: Host,
-    connection_id: u32,
-    send_request: Option<SendRequest<Bytes>>,
+    resolver: Arc<Resolver>,
+    state: Mutex<State>,
+    ech: Option<Ech>,
+    /// Is `true` if the server negotiated HTTP/2 on the last successful connection. The protocol of
+    /// a resumed session is not known before the handshake is finished, therefore the first
+    /// request is only sent as early data, if the session was resumed from an HTTP/2 connection.
+    /// It is kept, if the connection is dropped, because the protocol of the server is the same.
+    h2_resumable: AtomicBool,
+    #[cfg(feature = "odoh")]
+    odoh: Option<Odoh>,
 }
 
 impl Session {
     pub(crate) fn new(
         host: Host,
+        resolver: Arc<Resolver>,
         domain: String,
         client_config: Arc<ClientConfig>,
         uri: String,
         retries: u32,
         post: bool,
+        transport: Transport,
+        max_connections: usize,
+        keepalive: Option<Duration>,
+        idle_timeout: Option<Duration>,
     ) -> Session {
-        let config = Config::new(domain, client_config, uri, retries, post);
+        let config = Config::new(
+            domain,
+            client_config,
+            uri,
+            retries,
+            post,
+            transport,
+            max_connections,
+            keepalive,
+            idle_timeout,
+        );
         Session {
             config,
             host,
-            connection_id: 0,
-            send_request: None,
+            resolver,
+            state: Mutex::new(State::default()),
+            ech: None,
+            h2_resumable: AtomicBool::new(false),
+            #[cfg(feature = "odoh")]
+            odoh: None,
         }
     }
 
-    async fn connect(&mut self) -> DohResult<()> {
-        if self.send_request.is_some() {
-            return Ok(());
+    /// Encrypt the client hello of the TLS connections to the remote host with ECH.
+    pub(crate) fn set_ech(&mut self, ech: Ech) {
+        self.ech.replace(ech);
+    }
+
+    /// Get the client config for the next connection to the remote host, which uses ECH, if it is
+    /// enabled.
+    async fn client_config(&self) -> DohResult<Arc<ClientConfig>> {
+        let config = &self.config;
+        let client_config = match &self.ech {
+            Some(ech) => {
+                ech.client_config(&self.resolver, &config.domain, &config.client_config)
+                    .await?
+            }
+            None => config.client_config.clone(),
+        };
+        Ok(client_config)
+    }
+
+    /// Connect to the remote host with the client config. The early data is disabled, if the
+    /// server did not negotiate HTTP/2 on the last connection, so the handshake is finished before
+    /// the protocol is chosen.
+    async fn connect_with(&self, client_config: Arc<ClientConfig>) -> DohResult<Connection> {
+        let config = &self.config;
+        let client_config = if client_config.enable_early_data
+            && config.transport == Transport::Http2
+            && !self.h2_resumable.load(Ordering::Relaxed)
+        {
+            let mut client_config = (*client_config).clone();
+            client_config.enable_early_data = false;
+            Arc::new(client_config)
+        } else {
+            client_config
+        };
+        self.host
+            .connect(
+                &self.resolver,
+                &client_config,
+                &config.domain,
+                config.transport,
+                config.keepalive,
+                config.idle_timeout,
+            )
+            .await
+    }
+
+    /// Connect to the remote host once. If the server rejected ECH, then the remote host is
+    /// connected once more with the retry configs of the server or for the fallback without ECH.
+    async fn connect_once(&self) -> DohResult<Connection> {
+        let config = &self.config;
+        let client_config = self.client_config().await?;
+        let mut result = self.connect_with(client_config).await;
+        if let Some(ech) = &self.ech {
+            if let Err(e) = &result {
+                if let Some(client_config) = ech.rejected(e, &config.domain, &config.client_config)
+                {
+                    result = self.connect_with(client_config).await;
+                }
+            }
+            if result.is_err() {
+                ech.reset();
+            }
         }
+        if let Ok(connection) = &result {
+            let h2 = matches!(connection, Connection::Http2(_));
+            self.h2_resumable.store(h2, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Send the queries through the remote host as relay to the Oblivious DoH target (see
+    /// RFC 9230). ODoH always uses the POST method.
+    #[cfg(feature = "odoh")]
+    pub(crate) fn set_odoh_target(&mut self, target: OdohTarget) {
+        let config = &mut self.config;
+        config.uri = target.relay_uri(&config.uri);
+        config.post = true;
+        config.content_type = odoh_rs::ODOH_HTTP_HEADER;
+        self.odoh.replace(Odoh::new(target));
+    }
+
+    /// Try to connect to the remote host up to `attempts` times with a backoff between them.
+    async fn try_connect(&self, attempts: u32) -> Option<Connection> {
         let config = &self.config;
-        let client_config = &config.client_config;
         let domain = &config.domain.as_str();
-        for i in 0..config.retries {
+        for i in 0..attempts {
+            if i != 0 {
+                sleep(backoff(i - 1)).await;
+            }
             info!("Try to connect to {}: {}", self.host, i + 1);
-            match self.host.connect(client_config, domain).await {
-                Ok(send_request) => {
-                    info!("Connected to {} at {}", domain, self.host);
-                    self.send_request.replace(send_request);
-                    self.connection_id = self.connection_id.wrapping_add(1);
-                    return Ok(());
+            match self.connect_once().await {
+                Ok(connection) => {
+                    info!(
+                        "Connected to {} at {} over {}",
+                        domain,
+                        self.host,
+                        connection.version()
+                    );
+                    return Some(connection);
                 }
                 Err(e) => {
                     error!("Could not connect to {} at {}: {}", domain, self.host, e);
                 }
             }
         }
-        Err(DohError::CouldNotConnectServer)
+        None
     }
 
-    pub(crate) fn disconnect(&mut self, connection_id: u32) {
-        if self.connection_id == connection_id {
+    /// Connect to the remote host in the background and return a future, which resolves when the
+    /// connection is established or all retries failed. If all retries failed, then the remote host
+    /// is marked as down and probed in the background with an increasing delay until it can be
+    /// connected again.
+    fn start_connect(self: &Arc<Self>) -> Shared<Receiver<bool>> {
+        let (sender, receiver) = channel();
+        let session = self.clone();
+        spawn(async move {
+            let mut sender = Some(sender);
+            let mut attempts = session.config.retries;
+            let mut probe = attempts;
+            loop {
+                let connection = session.try_connect(attempts).await;
+                {
+                    let mut state = session.state.lock().unwrap();
+                    if let Some(connection) = connection {
+                        if state.down {
+                            info!("{} is up again", session.host);
+                        }
+                        state.connecting.take();
+                        state.down = false;
+                        let is_http2 = matches!(connection, Connection::Http2(_));
+                        state.connection.replace(connection);
+                        state.connection_id = state.connection_id.wrapping_add(1);
+                        if is_http2 {
+                            session.start_keepalive(state.connection_id);
+                        }
+                        if let Some(sender) = sender {
+                            let _ = sender.send(true);
+                        }
+                        return;
+                    }
+                    if let Some(sender) = sender.take() {
+                        error!("{} is down, probe it in the background", session.host);
+                        state.down = true;
+                        let _ = sender.send(false);
+                    }
+                }
+                sleep(backoff(probe)).await;
+                attempts = 1;
+                probe += 1;
+            }
+        });
+        receiver.shared()
+    }
+
+    /// Get the connection attempt in the background, which is started if none is in progress.
+    fn connecting(self: &Arc<Self>, state: &mut State) -> Shared<Receiver<bool>> {
+        match &state.connecting {
+            Some(connecting) if connecting.peek().is_none() => connecting.clone(),
+            _ => {
+                let connecting = self.start_connect();
+                state.connecting.replace(connecting.clone());
+                connecting
+            }
+        }
+    }
+
+    /// Send PINGs over the idle HTTP/2 connections in the background and reconnect, if the server
+    /// does not answer them, so the dead connection is noticed before the next query. The
+    /// connections are closed, if they are idle for the idle timeout.
+    fn start_keepalive(self: &Arc<Self>, connection_id: u32) {
+        let keepalive = self.config.keepalive;
+        let idle_timeout = self.config.idle_timeout;
+        let interval = match (keepalive, idle_timeout) {
+            (Some(keepalive), Some(idle_timeout)) => keepalive.min(idle_timeout),
+            (Some(interval), None) | (None, Some(interval)) => interval,
+            (None, None) => return,
+        };
+        let session = self.clone();
+        spawn(async move {
+            loop {
+                sleep(interval).await;
+                let pool = {
+                    let state = session.state.lock().unwrap();
+                    match &state.connection {
+                        Some(Connection::Http2(pool)) if state.connection_id == connection_id => {
+                            pool.clone()
+                        }
+                        _ => return,
+                    }
+                };
+                match pool.keepalive(keepalive, idle_timeout).await {
+                    Keepalive::Alive => {}
+                    Keepalive::Idle => {
+                        debug!("Close idle connection to {}", session.host);
+                        session.drop_connection(connection_id);
+                        return;
+                    }
+                    Keepalive::Dead => {
+                        info!("Connection to {} is dead, reconnect", session.host);
+                        session.disconnect(connection_id);
+                        let mut state = session.state.lock().unwrap();
+                        // The connection attempt runs in the background without a query.
+                        if state.connection.is_none() {
+                            drop(session.connecting(&mut state));
+                        }
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Get a handle of the connection and its ID. If there is no open connection, then wait for
+    /// the connection attempt in the background, which is started if none is in progress.
+    async fn connect(self: &Arc<Self>) -> DohResult<(Connection, u32)> {
+        loop {
+            let connecting = {
+                let mut state = self.state.lock().unwrap();
+                match &state.connection {
+                    Some(connection) if connection.is_closed() => {
+                        debug!("Connection to server is closed");
+                        state.connection.take();
+                    }
+                    Some(connection) => return Ok((connection.clone(), state.connection_id)),
+                    None => {}
+                }
+                if state.down {
+                    return Err(DohError::CouldNotConnectServer);
+                }
+                self.connecting(&mut state)
+            };
+            if !connecting.await.unwrap_or(false) {
+                return Err(DohError::CouldNotConnectServer);
+            }
+        }
+    }
+
+    /// Open another connection to the remote host besides the `connection`.
+    async fn connect_other(&self, connection: &Connection) -> DohResult<Connection> {
+        let config = &self.config;
+        let domain = config.domain.as_str();
+        debug!(
+            "Open another {} connection to {}",
+            connection.version(),
+            domain
+        );
+        self.connect_once().await
+    }
+
+    /// HTTP/1.1 connections can only send one request at the same time, therefore take an idle
+    /// keep-alive connection or open another one, if all connections are busy. HTTP/2 connections
+    /// are limited by the maximum number of concurrent streams of the server, therefore open
+    /// another connection, if all connections are full. Otherwise the request waits for a stream.
+    /// Both pools are limited to `max_connections`, then HTTP/1.1 requests wait for an idle
+    /// connection.
+    async fn send_over(
+        &self,
+        mut connection: Connection,
+        data: Bytes,
+    ) -> DohResult<PendingRequest> {
+        let config = &self.config;
+        match &connection {
+            Connection::Http1(pool) => loop {
+                if let Some(sender) = pool.take() {
+                    return Ok(PendingRequest::http1(sender, config, data));
+                }
+                if !pool.reserve(config.max_connections) {
+                    pool.released().await;
+                    continue;
+                }
+                match self.connect_other(&connection).await {
+                    Ok(Connection::Http1(other)) => {
+                        if let Some(sender) = pool.insert(other) {
+                            return Ok(PendingRequest::http1(sender, config, data));
+                        }
+                    }
+                    Ok(mut other) => {
+                        pool.cancel();
+                        debug!("Server does not support HTTP1 anymore");
+                        self.state.lock().unwrap().connection.replace(other.clone());
+                        return other.send_request(config, data);
+                    }
+                    Err(e) => {
+                        pool.cancel();
+                        return Err(e);
+                    }
+                }
+            },
+            Connection::Http2(pool) if pool.reserve(config.max_connections) => {
+                match self.connect_other(&connection).await {
+                    Ok(Connection::Http2(other)) => pool.append(other),
+                    Ok(mut other) => {
+                        pool.cancel();
+                        debug!("Server does not support HTTP2 anymore");
+                        self.state.lock().unwrap().connection.replace(other.clone());
+                        return other.send_request(config, data);
+                    }
+                    Err(e) => {
+                        pool.cancel();
+                        error!("Could not open another HTTP2 connection: {}", e);
+                    }
+                }
+                connection.send_request(config, data)
+            }
+            _ => connection.send_request(config, data),
+        }
+    }
+
+    /// Drop the connection, if it is still the current one. Returns `false` if another connection
+    /// is established in the meantime.
+    fn drop_connection(&self, connection_id: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.connection_id == connection_id {
+            state.connection.take();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn disconnect(&self, connection_id: u32) {
+        if self.drop_connection(connection_id) {
             debug!("Disconnect connetion to server");
-            self.send_request.take();
         }
     }
 
     pub(crate) async fn send_request(
-        &mut self,
+        self: &Arc<Self>,
         data: Bytes,
     ) -> DohResult<(
-        impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
+        impl Future<Output = DohResult<(Bytes, Option<Duration>)>>,
         u32,
     )> {
-        let config = &self.config;
-        let post = config.post;
-
-        let request = if post {
-            Request::builder()
-                .method("POST")
-                .uri(config.uri.clone())
-                .header("accept", "application/dns-message")
-                .header("content-type", "application/dns-message")
-                .header("content-length", data.len().to_string())
-                .body(())
-                .unwrap()
-        } else {
-            let uri = format!("{}?dns={}", config.uri, URL_SAFE_NO_PAD.encode(&data[..]));
-            Request::builder()
-                .method("GET")
-                .uri(uri)
-                .header("accept", "application/dns-message")
-                .body(())
-                .unwrap()
-        };
-
-        self.connect().await?;
-
-        debug!("Send HTTP2 request to server: {:?}", request);
-        if let Some(send_request) = self.send_request.as_mut() {
-            let (response, mut request) = send_request.send_request(request, false)?;
-            if post {
-                debug!("Send HTTP2 body: {:?}", data);
-                request.send_data(data, true)?;
+        let (connection, connection_id) = self.connect().await?;
+        match self.send_over(connection, data).await {
+            Ok(pending_request) => Ok((pending_request.response(), connection_id)),
+            Err(e) => {
+                self.drop_connection(connection_id);
+                Err(e)
             }
-            Ok((response_handler(response), self.connection_id))
-        } else {
-            Err(DohError::IsNotConnected)
         }
     }
 
     pub(crate) async fn start_request(
-        &mut self,
+        self: &Arc<Self>,
         dns_request: &mut Dns,
     ) -> DohResult<(
         impl Future<Output = DohResult<(Dns, Option<Duration>)>>,
         u32,
     )> {
-        self.connect().await?;
         let id = dns_request.id;
         dns_request.id = 0;
         let bytes = dns_request.encode()?;
@@ -125,12 +452,49 @@ impl Session {
         let data = bytes.freeze();
         dns_request.id = id;
 
-        match self.send_request(data).await {
-            Ok(r) => Ok(r),
-            Err(e) => {
-                self.send_request.take();
-                Err(e)
+        #[cfg(feature = "odoh")]
+        let (data, odoh_query) = match self.odoh.as_ref() {
+            Some(odoh) => {
+                let (data, odoh_query) = odoh.encrypt(&self.resolver, &self.host, data).await?;
+                (data, Some(odoh_query))
             }
-        }
+            None => (data, None),
+        };
+
+        let (response, connection_id) = self.send_request(data.clone()).await?;
+        let session = self.clone();
+        let response = async move {
+            let result = match response.await {
+                // The query is sent once more over a new connection, if the connection was lost
+                // before the server answered it.
+                Err(e) if e.is_connection_lost() => {
+                    info!("Connection to {} is lost, send again: {}", session.host, e);
+                    session.disconnect(connection_id);
+                    let (response, connection_id) = session.send_request(data).await?;
+                    let result = response.await;
+                    if matches!(&result, Err(e) if e.is_connection_lost()) {
+                        session.disconnect(connection_id);
+                    }
+                    result
+                }
+                result => result,
+            };
+            #[cfg(feature = "odoh")]
+            let result = match odoh_query {
+                Some(odoh_query) => {
+                    let result = result
+                        .and_then(|(body, duration)| Ok((odoh_query.decrypt(body)?, duration)));
+                    // The config of the target could be outdated.
+                    if matches!(&result, Err(e) if e.is_odoh_config_outdated()) {
+                        session.odoh.as_ref().unwrap().reset().await;
+                    }
+                    result
+                }
+                None => result,
+            };
+            let (body, duration) = result?;
+            decode_response(body, duration)
+        };
+        Ok((response, connection_id))
     }
 }
@@ -0,0 +1,69 @@
+use std::fmt::{Display, Formatter, Result};
+
+/// The protocol, which is used to send the DNS queries to the remote host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// HTTP/2 over TLS over TCP, with a fallback to HTTP/1.1 if the server does not support
+    /// HTTP/2.
+    Http2,
+    /// DNS over TLS (see RFC 7858), without HTTP.
+    Dot,
+    /// HTTP/3 over QUIC.
+    #[cfg(feature = "http3")]
+    Http3,
+    /// DNS over QUIC (see RFC 9250), without HTTP.
+    #[cfg(feature = "doq")]
+    Doq,
+}
+
+impl Transport {
+    /// The ALPN protocol identifiers of the transport in the order of preference.
+    pub(crate) fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        match self {
+            Transport::Http2 => vec![
+                vec![104, 50],                            // h2
+                vec![104, 116, 116, 112, 47, 49, 46, 49], // http/1.1
+            ],
+            Transport::Dot => Vec::new(),
+            #[cfg(feature = "http3")]
+            Transport::Http3 => vec![vec![104, 51]], // h3
+            #[cfg(feature = "doq")]
+            Transport::Doq => vec![vec![100, 111, 113]], // doq
+        }
+    }
+
+    /// Returns `true` if the DNS messages are sent over HTTP.
+    pub(crate) fn is_http(&self) -> bool {
+        match self {
+            Transport::Http2 => true,
+            #[cfg(feature = "http3")]
+            Transport::Http3 => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the transport uses QUIC, which cannot be used over a proxy.
+    #[cfg(any(feature = "http3", feature = "doq"))]
+    pub(crate) fn is_quic(&self) -> bool {
+        match self {
+            #[cfg(feature = "http3")]
+            Transport::Http3 => true,
+            #[cfg(feature = "doq")]
+            Transport::Doq => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Transport::Http2 => write!(f, "HTTP/2"),
+            Transport::Dot => write!(f, "DoT"),
+            #[cfg(feature = "http3")]
+            Transport::Http3 => write!(f, "HTTP/3"),
+            #[cfg(feature = "doq")]
+            Transport::Doq => write!(f, "DoQ"),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use super::{HedgeDelay, Selector, Session, Strategy};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The sessions to all remote hosts.
+pub(crate) struct Upstreams {
+    sessions: Vec<Arc<Session>>,
+    selector: Selector,
+}
+
+impl Upstreams {
+    pub(crate) fn new(
+        sessions: Vec<(Session, u32)>,
+        strategy: Strategy,
+        failback: Duration,
+    ) -> Upstreams {
+        let (sessions, weights): (Vec<_>, Vec<_>) = sessions.into_iter().unzip();
+        let selector = Selector::new(strategy, weights, failback);
+        let sessions = sessions.into_iter().map(Arc::new).collect();
+        Upstreams { sessions, selector }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &Arc<Session> {
+        &self.sessions[index]
+    }
+
+    /// Get the indices of the sessions in the order, in which they should be tried.
+    pub(crate) fn order(&self) -> Vec<usize> {
+        self.selector.order()
+    }
+
+    /// Get the delay after that the query should be sent to the next session, if the session has
+    /// not answered yet.
+    pub(crate) fn hedge_delay(
+        &self,
+        index: usize,
+        hedge_delay: HedgeDelay,
+        default: Duration,
+    ) -> Duration {
+        self.selector.hedge_delay(index, hedge_delay, default)
+    }
+
+    /// Feed the latency of a successful query back into the selection of the sessions.
+    pub(crate) fn succeeded(&self, index: usize, latency: Duration) {
+        self.selector.succeeded(index, latency)
+    }
+
+    /// Mark the session as failed, so the other sessions are used for the next queries.
+    pub(crate) fn failed(&self, index: usize) {
+        self.selector.failed(index)
+    }
+}
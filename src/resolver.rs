@@ -0,0 +1,361 @@
+use crate::{DohError, DohResult};
+use bytes::Bytes;
+use dns_message_parser::question::{QClass, QType, Question};
+use dns_message_parser::rr::{ServiceParameter, RR};
+use dns_message_parser::{Dns, Flags, Opcode, RCode};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+/// The time after that a query to a bootstrap DNS server is given up.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The default port of the bootstrap DNS servers.
+pub(crate) const BOOTSTRAP_DNS_PORT: u16 = 53;
+
+/// Resolve the domain names of the remote hosts and the proxies. If bootstrap DNS servers are
+/// given, then they are queried directly over UDP (or TCP, if the response is truncated) and the
+/// addresses are cached with their TTL. Otherwise, the system resolver is used, which does not
+/// work if the `doh-client` is the system resolver.
+pub(crate) struct Resolver {
+    servers: Vec<SocketAddr>,
+    cache: Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>,
+}
+
+fn create_query(domain_name: &str, q_type: QType) -> DohResult<Dns> {
+    let domain_name = domain_name
+        .parse()
+        .map_err(|_| DohError::BootstrapDomain(domain_name.to_owned()))?;
+    let question = Question {
+        domain_name,
+        q_class: QClass::IN,
+        q_type,
+    };
+    Ok(Dns {
+        id: fastrand::u16(..),
+        flags: Flags {
+            qr: false,
+            opcode: Opcode::Query,
+            aa: false,
+            tc: false,
+            rd: true,
+            ra: false,
+            ad: false,
+            cd: false,
+            rcode: RCode::NoError,
+        },
+        questions: vec![question],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    })
+}
+
+/// Get the addresses of the response and the minimum TTL of the answers, which includes the
+/// CNAME records of the chain.
+fn get_addrs(dns_response: &Dns) -> (Vec<IpAddr>, Option<u32>) {
+    let mut addrs = Vec::new();
+    let mut min_ttl: Option<u32> = None;
+    for answer in &dns_response.answers {
+        match answer {
+            RR::A(a) => addrs.push(IpAddr::V4(a.ipv4_addr)),
+            RR::AAAA(aaaa) => addrs.push(IpAddr::V6(aaaa.ipv6_addr)),
+            _ => {}
+        }
+        if let Some(ttl) = answer.get_ttl() {
+            min_ttl = Some(min_ttl.map_or(ttl, |min_ttl| min_ttl.min(ttl)));
+        }
+    }
+    if addrs.is_empty() {
+        return (addrs, None);
+    }
+    (addrs, min_ttl)
+}
+
+async fn query_udp(server: SocketAddr, query: &Bytes) -> DohResult<Bytes> {
+    let local_addr: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local_addr).await?;
+    socket.connect(server).await?;
+    socket.send(query).await?;
+    let mut buffer = vec![0; u16::MAX as usize];
+    let len = socket.recv(&mut buffer).await?;
+    buffer.truncate(len);
+    Ok(Bytes::from(buffer))
+}
+
+async fn query_tcp(server: SocketAddr, query: &Bytes) -> DohResult<Bytes> {
+    let mut tcp_stream = TcpStream::connect(server).await?;
+    tcp_stream.write_u16(query.len() as u16).await?;
+    tcp_stream.write_all(query).await?;
+    let len = tcp_stream.read_u16().await?;
+    let mut buffer = vec![0; len as usize];
+    tcp_stream.read_exact(&mut buffer).await?;
+    Ok(Bytes::from(buffer))
+}
+
+/// Send the query to the bootstrap DNS server and return the response.
+async fn exchange(server: SocketAddr, domain_name: &str, q_type: QType) -> DohResult<Dns> {
+    let dns_query = create_query(domain_name, q_type)?;
+    let query = dns_query.encode()?.freeze();
+    let mut dns_response = Dns::decode(query_udp(server, &query).await?)?;
+    if dns_response.flags.tc {
+        debug!("Response of bootstrap DNS server is truncated, retry over TCP");
+        dns_response = Dns::decode(query_tcp(server, &query).await?)?;
+    }
+    if !dns_response.is_response() || dns_response.id != dns_query.id {
+        return Err(DohError::DnsNotResponse(dns_response));
+    }
+    if dns_response.flags.rcode != RCode::NoError {
+        return Err(DohError::BootstrapRCode(dns_response.flags.rcode));
+    }
+    Ok(dns_response)
+}
+
+/// Send the query to the bootstrap DNS server and return the addresses of the response and their
+/// minimum TTL.
+async fn query(
+    server: SocketAddr,
+    domain_name: &str,
+    q_type: QType,
+) -> DohResult<(Vec<IpAddr>, Option<u32>)> {
+    let dns_response = exchange(server, domain_name, q_type).await?;
+    Ok(get_addrs(&dns_response))
+}
+
+/// Get the ECH configs (`ECHConfigList`) of the HTTPS record with the highest priority, which has
+/// them, and the TTL of the record (see RFC 9460).
+fn get_ech_config_list(dns_response: &Dns) -> Option<(Vec<u8>, u32)> {
+    dns_response
+        .answers
+        .iter()
+        .filter_map(|answer| match answer {
+            // The priority 0 is the alias mode, which has no parameters.
+            RR::HTTPS(https) if https.priority != 0 => Some(https),
+            _ => None,
+        })
+        .filter_map(|https| {
+            https
+                .parameters
+                .iter()
+                .find_map(|parameter| match parameter {
+                    ServiceParameter::ECH { config_list } => {
+                        Some((https.priority, config_list.clone(), https.ttl))
+                    }
+                    _ => None,
+                })
+        })
+        .min_by_key(|(priority, _, _)| *priority)
+        .map(|(_, config_list, ttl)| (config_list, ttl))
+}
+
+impl Resolver {
+    pub(crate) fn new(servers: Vec<SocketAddr>) -> Resolver {
+        Resolver {
+            servers,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Query the A and AAAA records of the domain name at the bootstrap DNS servers one after
+    /// another, until one of them answers with at least one address.
+    async fn resolve(&self, domain_name: &str) -> DohResult<(Vec<IpAddr>, Option<u32>)> {
+        for server in &self.servers {
+            let result = timeout(QUERY_TIMEOUT, async {
+                let (a, aaaa) = futures::join!(
+                    query(*server, domain_name, QType::A),
+                    query(*server, domain_name, QType::AAAA)
+                );
+                match (a, aaaa) {
+                    (Err(e), Err(_)) => Err(e),
+                    (a, aaaa) => {
+                        let (mut addrs, a_ttl) = a.unwrap_or_default();
+                        let (aaaa_addrs, aaaa_ttl) = aaaa.unwrap_or_default();
+                        addrs.extend(aaaa_addrs);
+                        let ttl = match (a_ttl, aaaa_ttl) {
+                            (Some(a_ttl), Some(aaaa_ttl)) => Some(a_ttl.min(aaaa_ttl)),
+                            (a_ttl, aaaa_ttl) => a_ttl.or(aaaa_ttl),
+                        };
+                        Ok((addrs, ttl))
+                    }
+                }
+            })
+            .await;
+            match result {
+                Ok(Ok((addrs, ttl))) if !addrs.is_empty() => return Ok((addrs, ttl)),
+                Ok(Ok(_)) => info!(
+                    "Bootstrap DNS server {} has no address of {}",
+                    server, domain_name
+                ),
+                Ok(Err(e)) => error!("Could not resolve {} at {}: {}", domain_name, server, e),
+                Err(e) => error!("Could not resolve {} at {}: {}", domain_name, server, e),
+            }
+        }
+        Err(DohError::Bootstrap(domain_name.to_owned()))
+    }
+
+    /// Get the socket addresses of the host. The IPv4 addresses are returned before the IPv6
+    /// addresses.
+    pub(crate) async fn lookup(&self, host: &str, port: u16) -> DohResult<Vec<SocketAddr>> {
+        if let Ok(ip_addr) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip_addr, port)]);
+        }
+        if self.servers.is_empty() {
+            return Ok(lookup_host((host, port)).await?.collect());
+        }
+
+        if let Some((addrs, expire)) = self.cache.lock().unwrap().get(host) {
+            if Instant::now() < *expire {
+                return Ok(addrs
+                    .iter()
+                    .map(|addr| SocketAddr::new(*addr, port))
+                    .collect());
+            }
+        }
+        let (addrs, ttl) = self.resolve(host).await?;
+        info!(
+            "Resolved {} to {:?} with the bootstrap DNS servers",
+            host, addrs
+        );
+        let socket_addrs = addrs
+            .iter()
+            .map(|addr| SocketAddr::new(*addr, port))
+            .collect();
+        let expire = Instant::now() + Duration::from_secs(ttl.unwrap_or_default().into());
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(host.to_owned(), (addrs, expire));
+        Ok(socket_addrs)
+    }
+
+    /// Get the ECH configs of the domain and their TTL from its HTTPS record at the bootstrap DNS
+    /// servers. Returns `None` if no bootstrap DNS servers are given, because the system resolver
+    /// cannot query HTTPS records, or if the domain has no ECH configs.
+    pub(crate) async fn lookup_ech(&self, domain_name: &str) -> Option<(Vec<u8>, u32)> {
+        if self.servers.is_empty() {
+            info!(
+                "Cannot query the HTTPS record of {} without bootstrap DNS servers",
+                domain_name
+            );
+            return None;
+        }
+        for server in &self.servers {
+            let result = timeout(QUERY_TIMEOUT, exchange(*server, domain_name, QType::HTTPS)).await;
+            match result {
+                Ok(Ok(dns_response)) => {
+                    let ech_config_list = get_ech_config_list(&dns_response);
+                    if ech_config_list.is_none() {
+                        info!("HTTPS record of {} has no ECH configs", domain_name);
+                    }
+                    return ech_config_list;
+                }
+                Ok(Err(e)) => error!(
+                    "Could not query the HTTPS record of {} at {}: {}",
+                    domain_name, server, e
+                ),
+                Err(e) => error!(
+                    "Could not query the HTTPS record of {} at {}: {}",
+                    domain_name, server, e
+                ),
+            }
+        }
+        None
+    }
+
+    /// Forget the cached addresses of the host, so it is resolved again for the next connection.
+    /// This is done, if no address of the host can be connected.
+    pub(crate) fn forget(&self, host: &str) {
+        self.cache.lock().unwrap().remove(host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_query, get_addrs, get_ech_config_list};
+    use dns_message_parser::question::QType;
+    use dns_message_parser::rr::{Class, ServiceBinding, ServiceParameter, A, AAAA, CNAME, RR};
+    use std::collections::BTreeSet;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_get_addrs() {
+        let mut dns = create_query("doh.example.", QType::A).unwrap();
+        let domain_name = "doh.example.".parse().unwrap();
+        let c_name = "cdn.example.".parse().unwrap();
+        dns.answers = vec![
+            RR::CNAME(CNAME {
+                domain_name,
+                ttl: 30,
+                class: Class::IN,
+                c_name,
+            }),
+            RR::A(A {
+                domain_name: "cdn.example.".parse().unwrap(),
+                ttl: 300,
+                ipv4_addr: Ipv4Addr::new(192, 0, 2, 1),
+            }),
+            RR::AAAA(AAAA {
+                domain_name: "cdn.example.".parse().unwrap(),
+                ttl: 120,
+                ipv6_addr: Ipv6Addr::LOCALHOST,
+            }),
+        ];
+        let (addrs, ttl) = get_addrs(&dns);
+        assert_eq!(
+            addrs,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            ]
+        );
+        assert_eq!(ttl, Some(30));
+    }
+
+    #[test]
+    fn test_get_addrs_empty() {
+        let dns = create_query("doh.example.", QType::AAAA).unwrap();
+        assert_eq!(get_addrs(&dns), (Vec::new(), None));
+    }
+
+    fn create_https(priority: u16, parameters: BTreeSet<ServiceParameter>) -> RR {
+        RR::HTTPS(ServiceBinding {
+            name: "doh.example.".parse().unwrap(),
+            ttl: 60,
+            priority,
+            target_name: "doh.example.".parse().unwrap(),
+            parameters,
+            https: true,
+        })
+    }
+
+    #[test]
+    fn test_get_ech_config_list() {
+        let mut dns = create_query("doh.example.", QType::HTTPS).unwrap();
+        let ech = |config_list: &[u8]| ServiceParameter::ECH {
+            config_list: config_list.to_vec(),
+        };
+        dns.answers = vec![
+            create_https(0, BTreeSet::new()),
+            create_https(3, BTreeSet::from([ech(&[3])])),
+            create_https(1, BTreeSet::from([ServiceParameter::PORT { port: 443 }])),
+            create_https(2, BTreeSet::from([ech(&[2])])),
+        ];
+        assert_eq!(get_ech_config_list(&dns), Some((vec![2], 60)));
+    }
+
+    #[test]
+    fn test_get_ech_config_list_empty() {
+        let mut dns = create_query("doh.example.", QType::HTTPS).unwrap();
+        assert_eq!(get_ech_config_list(&dns), None);
+        dns.answers = vec![create_https(1, BTreeSet::new())];
+        assert_eq!(get_ech_config_list(&dns), None);
+    }
+}
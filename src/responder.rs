@@ -0,0 +1,37 @@
+use crate::message::get_udp_payload_size;
+use crate::DohResult;
+use bytes::Bytes;
+use dns_message_parser::Dns;
+use futures::channel::mpsc::UnboundedSender;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// The way back to the client, which sent a DNS query.
+pub(crate) enum Responder {
+    /// The query was received via the UDP socket from the address.
+    Udp(Arc<UdpSocket>, SocketAddr),
+    /// The query was received via a TCP connection. The sender passes the DNS message to the task,
+    /// which writes the length prefixed messages to the connection.
+    Tcp(UnboundedSender<Bytes>),
+}
+
+impl Responder {
+    /// Get the maximum size of a response to the request or `None` if the size is not limited.
+    pub(crate) fn max_size(&self, dns_request: &Dns) -> Option<usize> {
+        match self {
+            Responder::Udp(_, _) => Some(get_udp_payload_size(dns_request)),
+            Responder::Tcp(_) => None,
+        }
+    }
+
+    pub(crate) async fn send(&self, bytes: Bytes) -> DohResult<()> {
+        match self {
+            Responder::Udp(socket, addr) => {
+                socket.send_to(bytes.as_ref(), addr).await?;
+            }
+            Responder::Tcp(sender) => sender.unbounded_send(bytes)?,
+        }
+        Ok(())
+    }
+}
@@ -1,27 +1,191 @@
 use crate::config::Config;
-use crate::error::Result as DohResult;
+use crate::context::Context;
+use crate::error::{Error as DohError, Result as DohResult};
 use crate::handler::request_handler;
+use crate::listen::Listener;
+use crate::responder::Responder;
 use bytes::Bytes;
 use dns_message_parser::MAXIMUM_DNS_PACKET_SIZE;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::future::select_all;
+use futures::StreamExt;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::spawn;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, timeout as create_timeout};
 
-/// Run the `doh-client` with a specific configuration.
-pub async fn run(config: Config) -> DohResult<()> {
-    let (recv, context) = config.into().await?;
+/// The time after that an idle TCP connection is closed (see RFC 7766 section 6.2.3).
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
 
-    let context = Arc::new(context);
+/// The maximum number of open TCP connections, further connections wait in the backlog of the
+/// listener (see RFC 7766 section 6.2.2).
+const TCP_MAX_CONNECTIONS: usize = 128;
+
+/// The maximum number of concurrently handled queries of a TCP connection, further queries are not
+/// read until a response is written (see RFC 7766 section 6.2.1.1).
+const TCP_MAX_QUERIES: usize = 16;
+
+/// The time to wait after a failed accept, e.g. if the limit of open files is reached, before the
+/// next connection is accepted.
+const TCP_ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
 
+/// Handle the request concurrently. The `permit` is released, after the request is handled.
+fn spawn_request_handler(
+    msg: Bytes,
+    responder: Responder,
+    context: Arc<Context>,
+    permit: Option<OwnedSemaphorePermit>,
+) {
+    spawn(async move {
+        if let Err(e) = request_handler(msg, &responder, context.as_ref()).await {
+            error!("Could not handle request: {}", e);
+        }
+        drop(permit);
+    });
+}
+
+async fn run_udp(socket: UdpSocket, context: Arc<Context>) -> DohResult<()> {
+    let socket = Arc::new(socket);
     let mut buffer: [u8; MAXIMUM_DNS_PACKET_SIZE] = [0; MAXIMUM_DNS_PACKET_SIZE];
     loop {
-        let (n, addr) = recv.recv_from(&mut buffer[..]).await?;
+        let (n, addr) = socket.recv_from(&mut buffer[..]).await?;
         let msg = Bytes::copy_from_slice(&buffer[..n]);
         debug!("Receive UDP packet: {:?}", msg);
-        let c = context.clone();
-        spawn(async move {
-            if let Err(e) = request_handler(msg, addr, c.as_ref()).await {
-                error!("Could not handle request: {}", e);
+        let responder = Responder::Udp(socket.clone(), addr);
+        spawn_request_handler(msg, responder, context.clone(), None);
+    }
+}
+
+async fn write_tcp_responses(
+    mut write_half: OwnedWriteHalf,
+    mut receiver: UnboundedReceiver<Bytes>,
+) -> DohResult<()> {
+    while let Some(msg) = receiver.next().await {
+        // Responses, which do not fit into the length prefix, are dropped.
+        if let Ok(length) = u16::try_from(msg.len()) {
+            write_half.write_u16(length).await?;
+            write_half.write_all(msg.as_ref()).await?;
+        }
+    }
+    write_half.shutdown().await?;
+    Ok(())
+}
+
+/// Read the length prefixed DNS messages (see RFC 1035 section 4.2.2) from the TCP connection.
+/// Every query is handled concurrently and the responses are written in the order they are
+/// available (see RFC 7766 section 6.2.1.1). At most `TCP_MAX_QUERIES` queries are handled at the
+/// same time.
+async fn handle_tcp_connection(stream: TcpStream, addr: SocketAddr, context: Arc<Context>) {
+    let (mut read_half, write_half) = stream.into_split();
+    let (sender, receiver) = unbounded();
+    let writer = spawn(write_tcp_responses(write_half, receiver));
+    let queries = Arc::new(Semaphore::new(TCP_MAX_QUERIES));
+
+    loop {
+        let permit = queries
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let length = match create_timeout(TCP_IDLE_TIMEOUT, read_half.read_u16()).await {
+            Ok(Ok(length)) => length as usize,
+            Ok(Err(e)) => {
+                debug!("TCP connection of {} is closed: {}", addr, e);
+                break;
+            }
+            Err(_) => {
+                debug!("TCP connection of {} is idle", addr);
+                break;
+            }
+        };
+        let mut buffer = vec![0; length];
+        // A client, which sends the message slowly, must not keep the connection open forever.
+        match create_timeout(TCP_IDLE_TIMEOUT, read_half.read_exact(&mut buffer)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                error!("Could not read DNS message from {}: {}", addr, e);
+                break;
             }
+            Err(_) => {
+                error!("Could not read DNS message from {}: timeout", addr);
+                break;
+            }
+        }
+        let msg = Bytes::from(buffer);
+        debug!("Receive TCP message: {:?}", msg);
+        let responder = Responder::Tcp(sender.clone());
+        spawn_request_handler(msg, responder, context.clone(), Some(permit));
+    }
+
+    // The writer stops after all pending requests of this connection are answered.
+    drop(sender);
+    match writer.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("Could not write DNS message to {}: {}", addr, e),
+        Err(e) => error!("TCP writer task of {} failed: {}", addr, e),
+    }
+}
+
+async fn run_tcp(listener: TcpListener, context: Arc<Context>) -> DohResult<()> {
+    let connections = Arc::new(Semaphore::new(TCP_MAX_CONNECTIONS));
+    loop {
+        let permit = connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let (stream, addr) = match listener.accept().await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("Could not accept TCP connection: {}", e);
+                sleep(TCP_ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        debug!("Accept TCP connection: {}", addr);
+        if let Err(e) = stream.set_nodelay(true) {
+            error!("Could not set TCP_NODELAY for {}: {}", addr, e);
+        }
+        let context = context.clone();
+        spawn(async move {
+            handle_tcp_connection(stream, addr, context).await;
+            drop(permit);
         });
     }
 }
+
+/// Run the `doh-client` with a specific configuration.
+pub async fn run(config: Config) -> DohResult<()> {
+    let (listeners, context) = config.into().await?;
+    if listeners.is_empty() {
+        return Err(DohError::NoListener);
+    }
+
+    let context = Arc::new(context);
+
+    let mut runs = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        info!("Listen on {}", listener);
+        let context = context.clone();
+        let run = match listener {
+            Listener::Udp(socket) => spawn(run_udp(socket, context)),
+            Listener::Tcp(listener) => spawn(run_tcp(listener, context)),
+        };
+        runs.push(run);
+    }
+
+    // Stop, if one of the listeners fails.
+    let (result, _, runs) = select_all(runs).await;
+    for run in runs {
+        run.abort();
+    }
+    match result {
+        Ok(result) => result,
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,316 @@
+use crate::helper::split_host_port;
+use crate::resolver::BOOTSTRAP_DNS_PORT;
+use crate::Transport;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rustls_pki_types::ServerName;
+use std::net::{IpAddr, SocketAddr};
+
+/// The prefix of a DNS stamp (see https://dnscrypt.info/stamps-specifications).
+const STAMP_PREFIX: &str = "sdns://";
+
+/// The protocol identifiers of the supported stamps.
+const STAMP_DOH: u8 = 0x02;
+const STAMP_DOT: u8 = 0x03;
+#[cfg(feature = "doq")]
+const STAMP_DOQ: u8 = 0x04;
+#[cfg(feature = "odoh")]
+const STAMP_ODOH_TARGET: u8 = 0x05;
+const STAMP_ODOH_RELAY: u8 = 0x85;
+
+/// The length of the SHA256 hashes of the certificates.
+const CERT_HASH_LEN: usize = 32;
+
+/// Returns `true` if the value is a DNS stamp.
+pub(crate) fn is_stamp(value: &str) -> bool {
+    value.starts_with(STAMP_PREFIX)
+}
+
+/// A reader of the binary encoding of the stamps.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (bytes, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        Some(self.read(1)?[0])
+    }
+
+    /// Read a length-prefixed string.
+    fn read_lp(&mut self) -> Option<&'a str> {
+        let len = self.read_u8()?;
+        std::str::from_utf8(self.read(len as usize)?).ok()
+    }
+
+    /// Read a set of variable length-prefixed values. The high bit of the length is set, if
+    /// another value follows. Empty values are skipped.
+    fn read_vlp(&mut self) -> Option<Vec<&'a [u8]>> {
+        let mut values = Vec::new();
+        loop {
+            let len = self.read_u8()?;
+            let value = self.read((len & 0x7f) as usize)?;
+            if !value.is_empty() {
+                values.push(value);
+            }
+            if len & 0x80 == 0 {
+                return Some(values);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A DNS stamp of a DoH, DNS over TLS, DNS over QUIC server or an Oblivious DoH relay.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    /// The address of the server or the hostname, if the stamp does not contain an address.
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// The hostname of the server certificate.
+    pub(crate) domain: String,
+    /// The URI of the DoH server, which is only used for HTTP.
+    pub(crate) uri: String,
+    pub(crate) transport: Transport,
+    /// The SHA256 hashes of the `tbsCertificate` of the pinned certificates. The chain of the
+    /// server has to contain at least one of them, if this is not empty.
+    pub(crate) cert_hashes: Vec<Vec<u8>>,
+    /// The DNS servers to resolve the hostname.
+    pub(crate) bootstrap_dns: Vec<SocketAddr>,
+}
+
+/// Decode the binary encoding of the stamp.
+fn decode(stamp: &str) -> Option<Vec<u8>> {
+    let stamp = stamp.strip_prefix(STAMP_PREFIX)?;
+    URL_SAFE_NO_PAD.decode(stamp).ok()
+}
+
+/// Parse the hostname with an optional port and check, that it is a valid server name.
+fn parse_hostname(hostname: &str) -> Option<(&str, Option<u16>)> {
+    let (domain, port) = split_host_port(hostname)?;
+    ServerName::try_from(domain).ok()?;
+    let port = match port {
+        Some(port) => Some(port.parse().ok()?),
+        None => None,
+    };
+    Some((domain, port))
+}
+
+/// Parse the IP address with an optional port, IPv6 addresses are enclosed in brackets.
+fn parse_addr(addr: &str) -> Option<(IpAddr, Option<u16>)> {
+    let (ip_addr, port) = split_host_port(addr)?;
+    let ip_addr = ip_addr.parse().ok()?;
+    let port = match port {
+        Some(port) => Some(port.parse().ok()?),
+        None => None,
+    };
+    Some((ip_addr, port))
+}
+
+impl Stamp {
+    /// Parse the DNS stamp of a DoH, DNS over TLS, DNS over QUIC server or an Oblivious DoH relay.
+    pub(crate) fn parse(stamp: &str) -> Option<Stamp> {
+        let stamp = decode(stamp)?;
+        let mut reader = Reader(&stamp);
+        let (transport, default_port) = match reader.read_u8()? {
+            STAMP_DOH | STAMP_ODOH_RELAY => (Transport::Http2, 443),
+            STAMP_DOT => (Transport::Dot, 853),
+            #[cfg(feature = "doq")]
+            STAMP_DOQ => (Transport::Doq, 853),
+            _ => return None,
+        };
+        // The properties of the server are informational.
+        reader.read(8)?;
+        let addr = reader.read_lp()?;
+        let cert_hashes = reader.read_vlp()?;
+        if cert_hashes.iter().any(|hash| hash.len() != CERT_HASH_LEN) {
+            return None;
+        }
+        let hostname = reader.read_lp()?;
+        let path = if transport.is_http() {
+            reader.read_lp()?
+        } else {
+            ""
+        };
+        let bootstrap_dns = if reader.is_empty() {
+            Vec::new()
+        } else {
+            reader.read_vlp()?
+        };
+        if !reader.is_empty() {
+            return None;
+        }
+
+        let (domain, hostname_port) = parse_hostname(hostname)?;
+        let (host, port) = if addr.is_empty() {
+            (domain.to_owned(), hostname_port)
+        } else {
+            let (ip_addr, port) = parse_addr(addr)?;
+            (ip_addr.to_string(), port.or(hostname_port))
+        };
+        let mut bootstrap_addrs = Vec::with_capacity(bootstrap_dns.len());
+        for bootstrap_dns in bootstrap_dns {
+            let (ip_addr, port) = parse_addr(std::str::from_utf8(bootstrap_dns).ok()?)?;
+            let port = port.unwrap_or(BOOTSTRAP_DNS_PORT);
+            bootstrap_addrs.push(SocketAddr::new(ip_addr, port));
+        }
+        Some(Stamp {
+            host,
+            port: port.unwrap_or(default_port),
+            domain: domain.to_owned(),
+            uri: format!("https://{}{}", hostname, path),
+            transport,
+            cert_hashes: cert_hashes.into_iter().map(<[u8]>::to_vec).collect(),
+            bootstrap_dns: bootstrap_addrs,
+        })
+    }
+}
+
+/// Parse the DNS stamp of an Oblivious DoH target and return it as `Domain[:Port][/Path]`.
+#[cfg(feature = "odoh")]
+pub(crate) fn parse_odoh_target(stamp: &str) -> Option<String> {
+    let stamp = decode(stamp)?;
+    let mut reader = Reader(&stamp);
+    if reader.read_u8()? != STAMP_ODOH_TARGET {
+        return None;
+    }
+    reader.read(8)?;
+    let hostname = reader.read_lp()?;
+    let path = reader.read_lp()?;
+    if !reader.is_empty() {
+        return None;
+    }
+    parse_hostname(hostname)?;
+    Some(format!("{}{}", hostname, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stamp, STAMP_DOH, STAMP_DOT};
+    use crate::Transport;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    fn lp(value: &str) -> Vec<u8> {
+        let mut bytes = vec![value.len() as u8];
+        bytes.extend(value.as_bytes());
+        bytes
+    }
+
+    fn vlp(values: &[&[u8]]) -> Vec<u8> {
+        if values.is_empty() {
+            return vec![0];
+        }
+        let mut bytes = Vec::new();
+        for (index, value) in values.iter().enumerate() {
+            if index + 1 == values.len() {
+                bytes.push(value.len() as u8);
+            } else {
+                bytes.push(value.len() as u8 | 0x80);
+            }
+            bytes.extend(*value);
+        }
+        bytes
+    }
+
+    fn encode(bytes: Vec<u8>) -> String {
+        format!("sdns://{}", URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    #[test]
+    fn test_parse_doh_stamp() {
+        let hash = [1; 32];
+        let mut bytes = vec![STAMP_DOH];
+        bytes.extend([0; 8]);
+        bytes.extend(lp("[2606:4700::1111]"));
+        bytes.extend(vlp(&[&hash]));
+        bytes.extend(lp("dns.example:8443"));
+        bytes.extend(lp("/dns-query"));
+        bytes.extend(vlp(&[b"9.9.9.9", b"[2620:fe::fe]:5353"]));
+        let stamp = Stamp::parse(&encode(bytes)).unwrap();
+        assert_eq!(
+            stamp,
+            Stamp {
+                host: "2606:4700::1111".to_owned(),
+                port: 8443,
+                domain: "dns.example".to_owned(),
+                uri: "https://dns.example:8443/dns-query".to_owned(),
+                transport: Transport::Http2,
+                cert_hashes: vec![hash.to_vec()],
+                bootstrap_dns: vec![
+                    "9.9.9.9:53".parse().unwrap(),
+                    "[2620:fe::fe]:5353".parse().unwrap()
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_cloudflare_stamp() {
+        let stamp =
+            Stamp::parse("sdns://AgcAAAAAAAAABzEuMC4wLjEAEmRucy5jbG91ZGZsYXJlLmNvbQovZG5zLXF1ZXJ5")
+                .unwrap();
+        assert_eq!(stamp.host, "1.0.0.1");
+        assert_eq!(stamp.port, 443);
+        assert_eq!(stamp.domain, "dns.cloudflare.com");
+        assert_eq!(stamp.uri, "https://dns.cloudflare.com/dns-query");
+        assert_eq!(stamp.transport, Transport::Http2);
+    }
+
+    #[test]
+    fn test_parse_dot_stamp() {
+        let mut bytes = vec![STAMP_DOT];
+        bytes.extend([0; 8]);
+        bytes.extend(lp(""));
+        bytes.extend(vlp(&[]));
+        bytes.extend(lp("dns.example"));
+        let stamp = Stamp::parse(&encode(bytes)).unwrap();
+        assert_eq!(stamp.host, "dns.example");
+        assert_eq!(stamp.port, 853);
+        assert_eq!(stamp.transport, Transport::Dot);
+        assert!(stamp.cert_hashes.is_empty());
+        assert!(stamp.bootstrap_dns.is_empty());
+    }
+
+    #[cfg(feature = "odoh")]
+    #[test]
+    fn test_parse_odoh_target() {
+        let mut bytes = vec![super::STAMP_ODOH_TARGET];
+        bytes.extend([0; 8]);
+        bytes.extend(lp("odoh.example:8443"));
+        bytes.extend(lp("/dns-query"));
+        assert_eq!(
+            super::parse_odoh_target(&encode(bytes)),
+            Some("odoh.example:8443/dns-query".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_stamp() {
+        assert_eq!(Stamp::parse("https://dns.example/dns-query"), None);
+        assert_eq!(Stamp::parse("sdns://!"), None);
+        // A DNSCrypt stamp is not supported.
+        assert_eq!(Stamp::parse(&encode(vec![0x01; 16])), None);
+
+        // The hash has to be a SHA256 hash.
+        let mut bytes = vec![STAMP_DOH];
+        bytes.extend([0; 8]);
+        bytes.extend(lp("1.1.1.1"));
+        bytes.extend(vlp(&[&[1; 16]]));
+        bytes.extend(lp("dns.example"));
+        bytes.extend(lp("/dns-query"));
+        assert_eq!(Stamp::parse(&encode(bytes)), None);
+
+        // The path of DoH is missing.
+        let mut bytes = vec![STAMP_DOH];
+        bytes.extend([0; 8]);
+        bytes.extend(lp("1.1.1.1"));
+        bytes.extend(vlp(&[]));
+        bytes.extend(lp("dns.example"));
+        assert_eq!(Stamp::parse(&encode(bytes)), None);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::helper::split_host_port;
+use rustls_pki_types::ServerName;
+
+/// The default port of the URI template.
+const HTTPS_PORT: u16 = 443;
+
+/// The URI template of a DoH server (see RFC 8484 section 4.1), e.g.
+/// `https://dns.example:8443/dns-query{?dns}`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct UriTemplate {
+    /// The host of the URI without brackets, which is the domain name (or the IP address) of the
+    /// server certificate.
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// The URI without the `dns` variable. The `dns` parameter is appended for GET requests.
+    pub(crate) uri: String,
+    /// `true` if the template contains the `dns` variable, otherwise the server only supports
+    /// POST requests.
+    pub(crate) get_supported: bool,
+}
+
+/// Remove the `dns` variable (`{?dns}` or `{&dns}`) at the end of the path and the query of the
+/// template and return whether it was given. Other variables are not supported.
+fn remove_dns_variable(path_query: &str) -> Option<(&str, bool)> {
+    let (path_query, get_supported) = match path_query.find('{') {
+        Some(index) => match &path_query[index..] {
+            "{?dns}" if !path_query[..index].contains('?') => (&path_query[..index], true),
+            "{&dns}" if path_query[..index].contains('?') => (&path_query[..index], true),
+            _ => return None,
+        },
+        None => (path_query, false),
+    };
+    if path_query.contains('}') {
+        return None;
+    }
+    Some((path_query, get_supported))
+}
+
+impl UriTemplate {
+    /// Parse the `https` URI template. The port is 443, if it is not given. A template without the
+    /// `dns` variable is a plain URI, which only supports POST requests.
+    pub(crate) fn parse(template: &str) -> Option<UriTemplate> {
+        let rest = template.strip_prefix("https://")?;
+        let (authority, path_query) = match rest.find(['/', '?', '{']) {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        if authority.contains('@') {
+            return None;
+        }
+        let (host, port) = split_host_port(authority)?;
+        if ServerName::try_from(host).is_err() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => HTTPS_PORT,
+        };
+        let (path_query, get_supported) = remove_dns_variable(path_query)?;
+        let uri = if path_query.starts_with('/') {
+            format!("https://{}{}", authority, path_query)
+        } else {
+            format!("https://{}/{}", authority, path_query)
+        };
+        Some(UriTemplate {
+            host: host.to_owned(),
+            port,
+            uri,
+            get_supported,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UriTemplate;
+
+    fn parse(template: &str) -> Option<(String, u16, String)> {
+        UriTemplate::parse(template)
+            .map(|uri_template| (uri_template.host, uri_template.port, uri_template.uri))
+    }
+
+    fn get_supported(template: &str) -> bool {
+        UriTemplate::parse(template).unwrap().get_supported
+    }
+
+    #[test]
+    fn test_parse_uri_template() {
+        assert_eq!(
+            parse("https://dns.example:8443/custom/dns-query{?dns}"),
+            Some((
+                "dns.example".to_owned(),
+                8443,
+                "https://dns.example:8443/custom/dns-query".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse("https://dns.example/dns-query?ecs=0{&dns}"),
+            Some((
+                "dns.example".to_owned(),
+                443,
+                "https://dns.example/dns-query?ecs=0".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse("https://dns.example"),
+            Some((
+                "dns.example".to_owned(),
+                443,
+                "https://dns.example/".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_uri_template_get_supported() {
+        assert!(get_supported("https://dns.example/dns-query{?dns}"));
+        assert!(get_supported("https://dns.example/dns-query?ecs=0{&dns}"));
+        assert!(!get_supported("https://dns.example/dns-query"));
+        assert!(!get_supported("https://dns.example/dns-query?ecs=0"));
+    }
+
+    #[test]
+    fn test_parse_uri_template_ipv6() {
+        assert_eq!(
+            parse("https://[2606:4700:4700::1111]/dns-query{?dns}"),
+            Some((
+                "2606:4700:4700::1111".to_owned(),
+                443,
+                "https://[2606:4700:4700::1111]/dns-query".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse("https://[::1]:8443/dns-query"),
+            Some((
+                "::1".to_owned(),
+                8443,
+                "https://[::1]:8443/dns-query".to_owned()
+            ))
+        );
+        assert_eq!(parse("https://::1:8443/dns-query"), None);
+        assert_eq!(parse("https://[::1/dns-query"), None);
+        assert_eq!(parse("https://[dns.example]/dns-query"), None);
+    }
+
+    #[test]
+    fn test_parse_uri_template_invalid() {
+        assert_eq!(parse("http://dns.example/dns-query"), None);
+        assert_eq!(parse("https://dns.example:port/dns-query"), None);
+        assert_eq!(parse("https://user@dns.example/dns-query"), None);
+        assert_eq!(parse("https:///dns-query"), None);
+        assert_eq!(parse("https://dns.example/dns-query{?name}"), None);
+        assert_eq!(parse("https://dns.example/dns-query?ecs=0{?dns}"), None);
+        assert_eq!(parse("https://dns.example/dns-query{?dns}/path"), None);
+    }
+}
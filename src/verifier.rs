@@ -0,0 +1,168 @@
+use aws_lc_rs::digest::{digest, SHA256, SHA256_OUTPUT_LEN};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{VerifierBuilderError, WebPkiServerVerifier};
+use rustls::{
+    CertificateError, DigitallySignedStruct, Error as RustlsError, RootCertStore, SignatureScheme,
+};
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use std::sync::Arc;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+/// Get the SHA256 hash of the `tbsCertificate` of the certificate, which is used by the DNS stamps
+/// to pin a certificate of the chain.
+fn cert_hash(cert: &CertificateDer<'_>) -> Option<Vec<u8>> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    let tbs_certificate = cert.tbs_certificate.as_ref();
+    Some(digest(&SHA256, tbs_certificate).as_ref().to_vec())
+}
+
+/// Get the SHA256 hash of the `subjectPublicKeyInfo` of the certificate, which pins the public key
+/// like `pin-sha256` of HPKP (see RFC 7469 section 2.4).
+fn spki_hash(cert: &CertificateDer<'_>) -> Option<Vec<u8>> {
+    let (_, cert) = X509Certificate::from_der(cert).ok()?;
+    Some(digest(&SHA256, cert.public_key().raw).as_ref().to_vec())
+}
+
+/// Decode the base64 encoded SHA256 hash of a public key, e.g. the output of
+/// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+pub(crate) fn parse_spki_hash(pin: &str) -> Option<Vec<u8>> {
+    let spki_hash = STANDARD.decode(pin).ok()?;
+    if spki_hash.len() == SHA256_OUTPUT_LEN {
+        Some(spki_hash)
+    } else {
+        None
+    }
+}
+
+/// Verify the certificate of the server with the root certificates and check the pins: the hash of
+/// one certificate of the chain has to be one of `cert_hashes` and the hash of the public key of
+/// the server certificate has to be one of `spki_hashes`, if they are not empty. If `pin_only` is
+/// `true`, then the certificate is not verified with the root certificates and the domain, so
+/// self-signed certificates can be pinned too.
+#[derive(Debug)]
+pub(crate) struct PinVerifier {
+    verifier: Arc<WebPkiServerVerifier>,
+    cert_hashes: Vec<Vec<u8>>,
+    spki_hashes: Vec<Vec<u8>>,
+    pin_only: bool,
+}
+
+impl PinVerifier {
+    pub(crate) fn new(
+        root_store: RootCertStore,
+        cert_hashes: Vec<Vec<u8>>,
+        spki_hashes: Vec<Vec<u8>>,
+        pin_only: bool,
+    ) -> Result<PinVerifier, VerifierBuilderError> {
+        let verifier = WebPkiServerVerifier::builder(Arc::new(root_store)).build()?;
+        Ok(PinVerifier {
+            verifier,
+            cert_hashes,
+            spki_hashes,
+            pin_only,
+        })
+    }
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, RustlsError> {
+        let verified = if self.pin_only {
+            ServerCertVerified::assertion()
+        } else {
+            self.verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?
+        };
+        let pinned = self.cert_hashes.is_empty()
+            || std::iter::once(end_entity)
+                .chain(intermediates)
+                .filter_map(cert_hash)
+                .any(|cert_hash| self.cert_hashes.contains(&cert_hash));
+        if !pinned {
+            error!("No certificate of {:?} is pinned", server_name);
+            return Err(RustlsError::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        // Only the key of the server certificate is compared, because the intermediates are not
+        // verified in the pin only mode and could be any certificates in the other mode.
+        let pinned = self.spki_hashes.is_empty()
+            || spki_hash(end_entity).is_some_and(|spki_hash| self.spki_hashes.contains(&spki_hash));
+        if !pinned {
+            error!("The public key of {:?} is not pinned", server_name);
+            return Err(RustlsError::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cert_hash, parse_spki_hash, spki_hash};
+    use aws_lc_rs::digest::{digest, SHA256};
+    use rcgen::PublicKeyData;
+    use rustls_pki_types::CertificateDer;
+
+    #[test]
+    fn test_cert_hash() {
+        let certified_key = rcgen::generate_simple_self_signed(vec!["doh.test".into()]).unwrap();
+        assert_eq!(cert_hash(certified_key.cert.der()).unwrap().len(), 32);
+        assert_eq!(cert_hash(&CertificateDer::from(vec![0x30, 0x00])), None);
+    }
+
+    #[test]
+    fn test_spki_hash() {
+        let certified_key = rcgen::generate_simple_self_signed(vec!["doh.test".into()]).unwrap();
+        let spki = certified_key.signing_key.subject_public_key_info();
+        assert_eq!(
+            spki_hash(certified_key.cert.der()).unwrap(),
+            digest(&SHA256, &spki).as_ref()
+        );
+        assert_eq!(spki_hash(&CertificateDer::from(vec![0x30, 0x00])), None);
+    }
+
+    #[test]
+    fn test_parse_spki_hash() {
+        let pin = "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        assert_eq!(parse_spki_hash(pin).unwrap().len(), 32);
+        assert_eq!(parse_spki_hash("47DEQpj8HBSa"), None);
+        assert_eq!(parse_spki_hash("not base64"), None);
+    }
+}
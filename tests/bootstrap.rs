@@ -0,0 +1,76 @@
+mod common;
+
+use bytes::Bytes;
+use common::{query, start_doh_client_with_bootstrap_dns, start_dot_server, Certificate, DOMAIN};
+use dns_message_parser::question::QType;
+use dns_message_parser::rr::{A, RR};
+use dns_message_parser::{Dns, Flags, Opcode, RCode};
+use doh_client::{RemoteHost, Transport, UpstreamConfig};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// Start a bootstrap DNS server, which resolves `DOMAIN` to the loopback address, and return its
+/// address and a counter of the received queries.
+async fn start_bootstrap_dns() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+
+    let counter = queries.clone();
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut dns = Dns::decode(Bytes::copy_from_slice(&buffer[..len])).unwrap();
+            let question = &dns.questions[0];
+            assert_eq!(question.domain_name.to_string(), format!("{}.", DOMAIN));
+            if question.q_type == QType::A {
+                dns.answers.push(RR::A(A {
+                    domain_name: question.domain_name.clone(),
+                    ttl: 300,
+                    ipv4_addr: Ipv4Addr::LOCALHOST,
+                }));
+            }
+            dns.flags = Flags {
+                qr: true,
+                opcode: Opcode::Query,
+                aa: true,
+                tc: false,
+                rd: true,
+                ra: true,
+                ad: false,
+                cd: false,
+                rcode: RCode::NoError,
+            };
+            let response = dns.encode().unwrap();
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+
+    (addr, queries)
+}
+
+#[tokio::test]
+async fn query_with_bootstrap_dns() {
+    let certificate = Certificate::new("bootstrap");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let (bootstrap_dns, queries) = start_bootstrap_dns().await;
+
+    // The domain name can only be resolved by the bootstrap DNS server.
+    let remote_host = RemoteHost::Direct(DOMAIN.to_owned(), server_addr.port());
+    let cafile = certificate.cafile();
+    let upstream = UpstreamConfig::new(remote_host, DOMAIN, Some(&cafile), "dns-query")
+        .unwrap()
+        .with_transport(Transport::Dot)
+        .unwrap();
+    let listen_addr = start_doh_client_with_bootstrap_dns(upstream, true, vec![bootstrap_dns]);
+
+    for id in 1..=3 {
+        query(listen_addr, id).await;
+    }
+    // One A and one AAAA query.
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+}
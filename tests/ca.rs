@@ -0,0 +1,69 @@
+mod common;
+
+use common::{
+    create_upstream, query, query_fails, start_doh_client, start_dot_server, Certificate,
+};
+use doh_client::{CaCerts, Transport};
+use std::path::PathBuf;
+
+/// A directory with the CA certificates of PEM files.
+struct CaDir {
+    path: PathBuf,
+}
+
+impl CaDir {
+    fn new(name: &str, certificates: &[&Certificate]) -> CaDir {
+        let path = std::env::temp_dir().join(format!("doh-client-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        for (index, certificate) in certificates.iter().enumerate() {
+            std::fs::copy(&certificate.cafile, path.join(format!("{}.pem", index))).unwrap();
+        }
+        CaDir { path }
+    }
+
+    fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for CaDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[tokio::test]
+async fn merge_ca_files() {
+    let certificate = Certificate::new("ca-merge");
+    let other = Certificate::new("ca-merge-other");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+
+    let ca_certs = CaCerts::path(&other.cafile());
+    let upstream = create_upstream(ca_certs, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+    query_fails(listen_addr, 1).await;
+
+    let ca_certs = CaCerts::path(&other.cafile()).with_path(&certificate.cafile());
+    let upstream = create_upstream(ca_certs, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+    query(listen_addr, 2).await;
+}
+
+#[tokio::test]
+async fn ca_directory() {
+    let certificate = Certificate::new("ca-dir");
+    let other = Certificate::new("ca-dir-other");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+
+    let ca_dir = CaDir::new("ca-dir", &[&other, &certificate]);
+    let ca_certs = CaCerts::native().with_path(ca_dir.path());
+    let upstream = create_upstream(ca_certs, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+    query(listen_addr, 1).await;
+
+    let ca_dir = CaDir::new("ca-dir-other", &[&other]);
+    let ca_certs = CaCerts::native().with_path(ca_dir.path()).without_native();
+    let upstream = create_upstream(ca_certs, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+    query_fails(listen_addr, 2).await;
+}
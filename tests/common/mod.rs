@@ -0,0 +1,343 @@
+#![allow(dead_code)]
+
+use aws_lc_rs::digest::{digest, SHA256};
+use bytes::Bytes;
+use dns_message_parser::question::{QClass, QType, Question};
+use dns_message_parser::rr::{A, RR};
+use dns_message_parser::{Dns, Flags, Opcode, RCode};
+use doh_client::{run, CaCerts, Config, ListenConfig, RemoteHost, Transport, UpstreamConfig};
+use rcgen::{CertifiedKey, PublicKeyData};
+use rustls::ServerConfig;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::{sleep, timeout};
+use tokio_rustls::TlsAcceptor;
+
+pub const DOMAIN: &str = "doh.test";
+
+pub const ANSWER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 3);
+
+/// A self-signed certificate for `DOMAIN` and the loopback addresses, which is written to a PEM file
+/// for the client.
+pub struct Certificate {
+    certified_key: CertifiedKey<rcgen::KeyPair>,
+    pub cafile: PathBuf,
+}
+
+impl Certificate {
+    pub fn new(name: &str) -> Certificate {
+        Certificate::with_names(name, &[])
+    }
+
+    /// Create a certificate, which is also valid for the `other_names`.
+    pub fn with_names(name: &str, other_names: &[&str]) -> Certificate {
+        let mut subject_alt_names = vec![
+            DOMAIN.to_owned(),
+            Ipv4Addr::LOCALHOST.to_string(),
+            Ipv6Addr::LOCALHOST.to_string(),
+        ];
+        subject_alt_names.extend(other_names.iter().map(|other_name| other_name.to_string()));
+        let certified_key = rcgen::generate_simple_self_signed(subject_alt_names).unwrap();
+        let cafile =
+            std::env::temp_dir().join(format!("doh-client-{}-{}.pem", name, std::process::id()));
+        std::fs::write(&cafile, certified_key.cert.pem()).unwrap();
+        Certificate {
+            certified_key,
+            cafile,
+        }
+    }
+
+    pub fn cert_chain(&self) -> Vec<CertificateDer<'static>> {
+        vec![self.certified_key.cert.der().clone()]
+    }
+
+    pub fn private_key(&self) -> PrivateKeyDer<'static> {
+        let key_der = self.certified_key.signing_key.serialize_der();
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_der))
+    }
+
+    /// The SHA256 hash of the public key (SPKI) of the certificate.
+    pub fn spki_hash(&self) -> Vec<u8> {
+        let spki = self.certified_key.signing_key.subject_public_key_info();
+        digest(&SHA256, &spki).as_ref().to_vec()
+    }
+
+    pub fn cafile(&self) -> String {
+        self.cafile.to_str().unwrap().to_owned()
+    }
+
+    /// The TLS config of a server, which uses the certificate.
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(self.cert_chain(), self.private_key())
+            .unwrap()
+    }
+}
+
+/// The certificate is the only trusted CA certificate.
+impl From<&Certificate> for CaCerts {
+    fn from(certificate: &Certificate) -> CaCerts {
+        CaCerts::path(&certificate.cafile())
+    }
+}
+
+impl Drop for Certificate {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.cafile);
+    }
+}
+
+/// Create the answer of the DoH server for the request.
+pub fn create_answer(dns_request: Dns) -> Dns {
+    let question = &dns_request.questions[0];
+    let answer = RR::A(A {
+        domain_name: question.domain_name.clone(),
+        ttl: 60,
+        ipv4_addr: ANSWER,
+    });
+    Dns {
+        id: dns_request.id,
+        flags: Flags {
+            qr: true,
+            opcode: Opcode::Query,
+            aa: false,
+            tc: false,
+            rd: dns_request.flags.rd,
+            ra: true,
+            ad: false,
+            cd: false,
+            rcode: RCode::NoError,
+        },
+        questions: dns_request.questions,
+        answers: vec![answer],
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+pub fn create_query(id: u16) -> Dns {
+    let question = Question {
+        domain_name: "www.example.com.".parse().unwrap(),
+        q_class: QClass::IN,
+        q_type: QType::A,
+    };
+    Dns {
+        id,
+        flags: Flags {
+            qr: false,
+            opcode: Opcode::Query,
+            aa: false,
+            tc: false,
+            rd: true,
+            ra: false,
+            ad: false,
+            cd: false,
+            rcode: RCode::NoError,
+        },
+        questions: vec![question],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
+
+/// Answer the queries of a DNS over TLS connection.
+pub async fn handle_dot_connection<T: AsyncRead + AsyncWrite + Unpin>(mut connection: T) {
+    while let Ok(len) = connection.read_u16().await {
+        let mut msg = vec![0; len as usize];
+        connection.read_exact(&mut msg).await.unwrap();
+        let dns_request = Dns::decode(Bytes::from(msg)).unwrap();
+        let dns_response = create_answer(dns_request).encode().unwrap();
+        connection
+            .write_u16(dns_response.len() as u16)
+            .await
+            .unwrap();
+        connection.write_all(&dns_response).await.unwrap();
+    }
+}
+
+/// Start a local DNS over TLS server and return its address and a counter of the established TLS
+/// connections.
+pub async fn start_dot_server(certificate: &Certificate) -> (SocketAddr, Arc<AtomicUsize>) {
+    let acceptor = TlsAcceptor::from(Arc::new(certificate.server_config()));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                if let Ok(tls_stream) = acceptor.accept(tcp_stream).await {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    handle_dot_connection(tls_stream).await;
+                }
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+/// Create the upstream for the server at `server_addr`, which trusts the `ca_certs` and uses the
+/// `transport`.
+pub fn create_upstream(
+    ca_certs: impl Into<CaCerts>,
+    server_addr: SocketAddr,
+    transport: Transport,
+) -> UpstreamConfig {
+    let remote_host = RemoteHost::Direct(server_addr.ip().to_string(), server_addr.port());
+    UpstreamConfig::new(remote_host, DOMAIN, ca_certs, "dns-query")
+        .unwrap()
+        .with_transport(transport)
+        .unwrap()
+}
+
+/// Start the `doh-client` with a UDP listener on a free port and return the address of it.
+pub fn start_doh_client(upstream: UpstreamConfig, post: bool) -> SocketAddr {
+    start_doh_client_with_bootstrap_dns(upstream, post, Vec::new())
+}
+
+/// Start the `doh-client`, which resolves the domain name of the upstream with the bootstrap DNS
+/// servers, and return the address of it.
+pub fn start_doh_client_with_bootstrap_dns(
+    upstream: UpstreamConfig,
+    post: bool,
+    bootstrap_dns: Vec<SocketAddr>,
+) -> SocketAddr {
+    let listen_addr = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    start(listen_addr, false, vec![upstream], post, bootstrap_dns);
+    listen_addr
+}
+
+/// Start the `doh-client` with the remote hosts, which are used by the failover strategy, and
+/// return the address of its UDP listener.
+pub fn start_doh_client_with_upstreams(upstreams: Vec<UpstreamConfig>) -> SocketAddr {
+    let listen_addr = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    start(listen_addr, false, upstreams, true, Vec::new());
+    listen_addr
+}
+
+/// Start the `doh-client` with a UDP and a TCP listener on a free port and return the address of
+/// them.
+pub fn start_doh_client_with_tcp(upstream: UpstreamConfig) -> SocketAddr {
+    let listen_addr = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    start(listen_addr, true, vec![upstream], true, Vec::new());
+    listen_addr
+}
+
+fn start(
+    listen_addr: SocketAddr,
+    listen_tcp: bool,
+    upstreams: Vec<UpstreamConfig>,
+    post: bool,
+    bootstrap_dns: Vec<SocketAddr>,
+) {
+    let config = Config::new(ListenConfig::Addrs(vec![listen_addr]), upstreams)
+        .unwrap()
+        .with_listen_tcp(listen_tcp)
+        .with_bootstrap_dns(bootstrap_dns)
+        .with_cache(0, false)
+        .unwrap();
+    let config = if post { config } else { config.with_get() };
+    tokio::spawn(run(config));
+}
+
+/// Send a query to the `doh-client` and check the answer.
+pub async fn query(listen_addr: SocketAddr, id: u16) {
+    let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let query = create_query(id).encode().unwrap().freeze();
+    let mut buffer = [0; 512];
+    let mut response = None;
+    for _ in 0..20 {
+        client.send_to(&query, listen_addr).await.unwrap();
+        let recv = timeout(Duration::from_millis(500), client.recv(&mut buffer)).await;
+        if let Ok(Ok(len)) = recv {
+            response = Some(Dns::decode(Bytes::copy_from_slice(&buffer[..len])).unwrap());
+            break;
+        }
+    }
+
+    let response = response.expect("no response from doh-client");
+    assert_answer(&response, id);
+}
+
+/// Check that the response of the `doh-client` answers the query with the `id`.
+pub fn assert_answer(response: &Dns, id: u16) {
+    assert_eq!(response.id, id);
+    assert_eq!(response.flags.rcode, RCode::NoError);
+    assert_eq!(
+        response.answers,
+        vec![RR::A(A {
+            domain_name: "www.example.com.".parse().unwrap(),
+            ttl: 60,
+            ipv4_addr: ANSWER,
+        })]
+    );
+}
+
+/// Send a query to the `doh-client`, which has to fail, so a SERVFAIL response is received.
+pub async fn query_fails(listen_addr: SocketAddr, id: u16) {
+    let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let query = create_query(id).encode().unwrap().freeze();
+    let mut buffer = [0; 512];
+    let mut response = None;
+    // The query is sent again, if the doh-client is not listening yet.
+    for _ in 0..20 {
+        client.send_to(&query, listen_addr).await.unwrap();
+        let recv = timeout(Duration::from_millis(500), client.recv(&mut buffer)).await;
+        if let Ok(Ok(len)) = recv {
+            response = Some(Dns::decode(Bytes::copy_from_slice(&buffer[..len])).unwrap());
+            break;
+        }
+    }
+
+    let response = response.expect("no response from doh-client");
+    assert_eq!(response.id, id);
+    assert_eq!(response.flags.rcode, RCode::ServFail);
+}
+
+/// Connect to the TCP listener of the `doh-client`, which may not be listening yet.
+pub async fn connect_tcp(listen_addr: SocketAddr) -> TcpStream {
+    for _ in 0..20 {
+        match TcpStream::connect(listen_addr).await {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::from_millis(100)).await,
+        }
+    }
+    panic!("could not connect to doh-client");
+}
+
+/// Write a length-prefixed DNS message (see RFC 1035 section 4.2.2).
+pub async fn write_message<T: AsyncWrite + Unpin>(connection: &mut T, msg: &[u8]) {
+    connection.write_u16(msg.len() as u16).await.unwrap();
+    connection.write_all(msg).await.unwrap();
+}
+
+/// Read a length-prefixed DNS message, which is a query of the client or a response of the
+/// `doh-client`.
+pub async fn read_message<T: AsyncRead + Unpin>(connection: &mut T) -> Option<Dns> {
+    let len = connection.read_u16().await.ok()?;
+    let mut msg = vec![0; len as usize];
+    connection.read_exact(&mut msg).await.ok()?;
+    Some(Dns::decode(Bytes::from(msg)).unwrap())
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{
+    create_upstream, handle_dot_connection, query, query_fails, start_doh_client, Certificate,
+};
+use doh_client::Transport;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::time::{sleep, timeout};
+use tokio_rustls::TlsAcceptor;
+
+/// The time after that the server closes a connection without a TLS handshake.
+const CLOSE_DELAY: Duration = Duration::from_millis(300);
+
+/// Start a DNS over TLS server, which closes the first `failing` connections after `CLOSE_DELAY`,
+/// and return its address and a counter of the accepted connections.
+async fn start_server(certificate: &Certificate, failing: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+    let server_config = certificate.server_config();
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let failed = counter.fetch_add(1, Ordering::SeqCst) < failing;
+            tokio::spawn(async move {
+                if failed {
+                    sleep(CLOSE_DELAY).await;
+                    drop(tcp_stream);
+                } else if let Ok(tls_stream) = acceptor.accept(tcp_stream).await {
+                    handle_dot_connection(tls_stream).await;
+                }
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+#[tokio::test]
+async fn concurrent_queries_share_connection_attempt() {
+    let certificate = Certificate::new("connect");
+    let (server_addr, connections) = start_server(&certificate, usize::MAX).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+
+    tokio::join!(
+        query_fails(listen_addr, 1),
+        query_fails(listen_addr, 2),
+        query_fails(listen_addr, 3),
+        query_fails(listen_addr, 4)
+    );
+    // The queries wait for the same connection attempt in the background, which tries to connect
+    // 3 times (the retries), instead of connecting one after another.
+    assert_eq!(connections.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn fail_fast_while_down() {
+    let certificate = Certificate::new("connect-down");
+    let (server_addr, connections) = start_server(&certificate, 3).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query_fails(listen_addr, 1).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 3);
+
+    // The remote host is down, so the query fails without a connection attempt.
+    let start = Instant::now();
+    query_fails(listen_addr, 2).await;
+    assert!(start.elapsed() < CLOSE_DELAY);
+
+    // The remote host is probed in the background until it is up again.
+    timeout(Duration::from_secs(5), async {
+        while connections.load(Ordering::SeqCst) < 4 {
+            sleep(CLOSE_DELAY).await;
+        }
+    })
+    .await
+    .unwrap();
+    sleep(CLOSE_DELAY).await;
+    query(listen_addr, 3).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 4);
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "doq")]
+
+mod common;
+
+use bytes::Bytes;
+use common::{create_answer, create_upstream, query, start_doh_client, Certificate};
+use dns_message_parser::Dns;
+use doh_client::Transport;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Endpoint, RecvStream, SendStream, ServerConfig as QuinnServerConfig};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Answer the query of a single QUIC stream.
+async fn handle_stream(mut send: SendStream, mut recv: RecvStream) {
+    let query = recv.read_to_end(2 + u16::MAX as usize).await.unwrap();
+    let len = u16::from_be_bytes([query[0], query[1]]) as usize;
+    assert_eq!(query.len() - 2, len);
+    let dns_request = Dns::decode(Bytes::from(query).slice(2..)).unwrap();
+    // The DNS ID has to be 0 (see RFC 9250 section 4.2.1).
+    assert_eq!(dns_request.id, 0);
+
+    let dns_response = create_answer(dns_request).encode().unwrap();
+    send.write_all(&(dns_response.len() as u16).to_be_bytes())
+        .await
+        .unwrap();
+    send.write_all(&dns_response).await.unwrap();
+    send.finish().unwrap();
+}
+
+/// Start a local DNS over QUIC server and return its address and a counter of the accepted
+/// connections.
+fn start_server(certificate: &Certificate) -> (SocketAddr, Arc<AtomicUsize>) {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"doq".to_vec()];
+    let quic_server_config = QuicServerConfig::try_from(server_config).unwrap();
+    let server_config = QuinnServerConfig::with_crypto(Arc::new(quic_server_config));
+    let endpoint = Endpoint::server(server_config, (Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+    let addr = endpoint.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let connection = incoming.await.unwrap();
+                while let Ok((send, recv)) = connection.accept_bi().await {
+                    tokio::spawn(handle_stream(send, recv));
+                }
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+#[tokio::test]
+async fn query_over_doq() {
+    let certificate = Certificate::new("doq");
+    let (server_addr, connections) = start_server(&certificate);
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Doq);
+    let listen_addr = start_doh_client(upstream, true);
+
+    tokio::join!(
+        query(listen_addr, 1),
+        query(listen_addr, 2),
+        query(listen_addr, 3)
+    );
+    // Every query uses its own stream of the same connection.
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{create_answer, create_upstream, query, read_message, start_doh_client, Certificate};
+use doh_client::Transport;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+
+/// The number of queries, which the server collects before it answers them.
+const BATCH: usize = 3;
+
+/// Handle the queries of a DNS over TLS connection. The queries are collected and answered in
+/// reverse order, so the client has to pipeline them and match the responses by the ID.
+async fn handle_connection<T: AsyncRead + AsyncWrite + Unpin>(mut connection: T) {
+    loop {
+        let mut queries = match read_message(&mut connection).await {
+            Some(dns_request) => vec![dns_request],
+            None => return,
+        };
+        while queries.len() < BATCH {
+            match timeout(Duration::from_secs(1), read_message(&mut connection)).await {
+                Ok(Some(dns_request)) => queries.push(dns_request),
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        for dns_request in queries.into_iter().rev() {
+            let dns_response = create_answer(dns_request).encode().unwrap();
+            connection
+                .write_u16(dns_response.len() as u16)
+                .await
+                .unwrap();
+            connection.write_all(&dns_response).await.unwrap();
+        }
+        connection.flush().await.unwrap();
+    }
+}
+
+/// Start a local DNS over TLS server and return its address and a counter of the accepted
+/// connections.
+async fn start_server(certificate: &Certificate) -> (SocketAddr, Arc<AtomicUsize>) {
+    let server_config = certificate.server_config();
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                handle_connection(tls_stream).await;
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+#[tokio::test]
+async fn query_over_dot() {
+    let certificate = Certificate::new("dot");
+    let (server_addr, connections) = start_server(&certificate).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+
+    tokio::join!(
+        query(listen_addr, 1),
+        query(listen_addr, 2),
+        query(listen_addr, 3)
+    );
+    query(listen_addr, 4).await;
+    // The queries are pipelined over a single connection.
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
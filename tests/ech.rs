@@ -0,0 +1,212 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+    create_upstream, handle_dot_connection, query, query_fails, start_doh_client,
+    start_doh_client_with_bootstrap_dns, Certificate, DOMAIN,
+};
+use dns_message_parser::question::QType;
+use dns_message_parser::rr::{ServiceBinding, ServiceParameter, RR};
+use dns_message_parser::{Dns, Flags, Opcode, RCode};
+use doh_client::Transport;
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_rustls::LazyConfigAcceptor;
+
+/// The public name of the ECH config, which is sent in cleartext instead of `DOMAIN`.
+const PUBLIC_NAME: &str = "public.test";
+
+/// Encode an `ECHConfigList` with one X25519, HKDF-SHA256 and AES-128-GCM config of
+/// `PUBLIC_NAME` (see draft-ietf-tls-esni section 4).
+fn create_ech_config_list() -> Vec<u8> {
+    let mut contents = vec![0x01, 0x00, 0x20, 0x00, 0x20];
+    contents.extend([0x42; 32]);
+    contents.extend([0x00, 0x04, 0x00, 0x01, 0x00, 0x01, 0x00]);
+    contents.push(PUBLIC_NAME.len() as u8);
+    contents.extend(PUBLIC_NAME.as_bytes());
+    contents.extend([0x00, 0x00]);
+
+    let mut config = vec![0xfe, 0x0d];
+    config.extend((contents.len() as u16).to_be_bytes());
+    config.extend(contents);
+    let mut config_list = (config.len() as u16).to_be_bytes().to_vec();
+    config_list.extend(config);
+    config_list
+}
+
+/// Start a local DNS over TLS server, which does not support ECH, and return its address and the
+/// server names (SNI) of the client hellos.
+async fn start_server(certificate: &Certificate) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let server_config = certificate.server_config();
+    let server_config = Arc::new(server_config);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_names = Arc::new(Mutex::new(Vec::new()));
+
+    let names = server_names.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let server_config = server_config.clone();
+            let names = names.clone();
+            tokio::spawn(async move {
+                let acceptor = LazyConfigAcceptor::new(Default::default(), tcp_stream);
+                let Ok(start) = acceptor.await else {
+                    return;
+                };
+                let client_hello = start.client_hello();
+                let server_name = client_hello.server_name().unwrap_or_default().to_owned();
+                names.lock().unwrap().push(server_name);
+                if let Ok(tls_stream) = start.into_stream(server_config).await {
+                    handle_dot_connection(tls_stream).await;
+                }
+            });
+        }
+    });
+
+    (addr, server_names)
+}
+
+/// Start a bootstrap DNS server, which answers the HTTPS query of `DOMAIN` with the ECH config
+/// list.
+async fn start_bootstrap_dns() -> SocketAddr {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buffer).await.unwrap();
+            let mut dns = Dns::decode(Bytes::copy_from_slice(&buffer[..len])).unwrap();
+            let question = &dns.questions[0];
+            assert_eq!(question.domain_name.to_string(), format!("{}.", DOMAIN));
+            assert_eq!(question.q_type, QType::HTTPS);
+            let config_list = create_ech_config_list();
+            dns.answers.push(RR::HTTPS(ServiceBinding {
+                name: question.domain_name.clone(),
+                ttl: 300,
+                priority: 1,
+                target_name: question.domain_name.clone(),
+                parameters: BTreeSet::from([ServiceParameter::ECH { config_list }]),
+                https: true,
+            }));
+            dns.flags = Flags {
+                qr: true,
+                opcode: Opcode::Query,
+                aa: true,
+                tc: false,
+                rd: true,
+                ra: true,
+                ad: false,
+                cd: false,
+                rcode: RCode::NoError,
+            };
+            let response = dns.encode().unwrap();
+            socket.send_to(&response, peer).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn ech_hides_domain() {
+    let certificate = Certificate::new("ech");
+    let (server_addr, server_names) = start_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot)
+        .with_ech(Some(create_ech_config_list()), false)
+        .unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+
+    // The server does not support ECH, so the connection fails instead of sending the domain.
+    query_fails(listen_addr, 1).await;
+    let server_names = server_names.lock().unwrap();
+    assert!(!server_names.is_empty());
+    assert!(server_names
+        .iter()
+        .all(|server_name| server_name == PUBLIC_NAME));
+}
+
+#[tokio::test]
+async fn ech_config_of_https_record() {
+    let certificate = Certificate::new("ech-dns");
+    let (server_addr, server_names) = start_server(&certificate).await;
+    let bootstrap_dns = start_bootstrap_dns().await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot)
+        .with_ech(None, false)
+        .unwrap();
+    let listen_addr = start_doh_client_with_bootstrap_dns(upstream, true, vec![bootstrap_dns]);
+
+    query_fails(listen_addr, 1).await;
+    let server_names = server_names.lock().unwrap();
+    assert!(!server_names.is_empty());
+    assert!(server_names
+        .iter()
+        .all(|server_name| server_name == PUBLIC_NAME));
+}
+
+#[tokio::test]
+async fn ech_unavailable() {
+    let certificate = Certificate::new("ech-unavailable");
+    let (server_addr, server_names) = start_server(&certificate).await;
+
+    // Without bootstrap DNS servers the HTTPS record cannot be fetched, so the remote host is not
+    // connected at all.
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot)
+        .with_ech(None, false)
+        .unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+    query_fails(listen_addr, 1).await;
+    assert!(server_names.lock().unwrap().is_empty());
+
+    // With the fallback the domain is sent in cleartext.
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot)
+        .with_ech(None, true)
+        .unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+    query(listen_addr, 2).await;
+    assert_eq!(*server_names.lock().unwrap(), vec![DOMAIN.to_owned()]);
+}
+
+#[tokio::test]
+async fn ech_rejected() {
+    // The certificate is valid for the public name, so the server can reject ECH without retry
+    // configs.
+    let certificate = Certificate::with_names("ech-rejected", &[PUBLIC_NAME]);
+    let (server_addr, server_names) = start_server(&certificate).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot)
+        .with_ech(Some(create_ech_config_list()), false)
+        .unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+    query_fails(listen_addr, 1).await;
+    {
+        let server_names = server_names.lock().unwrap();
+        assert!(!server_names.is_empty());
+        assert!(server_names
+            .iter()
+            .all(|server_name| server_name == PUBLIC_NAME));
+    }
+    server_names.lock().unwrap().clear();
+
+    // With the fallback the connection is established again without ECH.
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot)
+        .with_ech(Some(create_ech_config_list()), true)
+        .unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+    query(listen_addr, 2).await;
+    assert_eq!(
+        *server_names.lock().unwrap(),
+        vec![PUBLIC_NAME.to_owned(), DOMAIN.to_owned()]
+    );
+}
+
+#[test]
+fn invalid_ech_config() {
+    let certificate = Certificate::new("ech-invalid");
+    let server_addr = (Ipv4Addr::LOCALHOST, 853).into();
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    assert!(upstream.with_ech(Some(vec![0x00, 0x01]), false).is_err());
+}
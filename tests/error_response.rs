@@ -0,0 +1,72 @@
+mod common;
+
+use bytes::Bytes;
+use common::{create_query, create_upstream, query_fails, start_doh_client, Certificate};
+use dns_message_parser::{Dns, Opcode, RCode};
+use doh_client::Transport;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+/// Start the `doh-client` with a remote host, which refuses the connections.
+fn start_doh_client_without_server(certificate: &Certificate) -> SocketAddr {
+    let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let upstream = create_upstream(certificate, server_addr, Transport::Http2);
+    start_doh_client(upstream, true)
+}
+
+/// Send the message to the `doh-client` and return the response.
+async fn send(listen_addr: SocketAddr, msg: &[u8]) -> Dns {
+    let client = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut buffer = [0; 512];
+    for _ in 0..20 {
+        client.send_to(msg, listen_addr).await.unwrap();
+        let recv = timeout(Duration::from_millis(500), client.recv(&mut buffer)).await;
+        if let Ok(Ok(len)) = recv {
+            return Dns::decode(Bytes::copy_from_slice(&buffer[..len])).unwrap();
+        }
+    }
+    panic!("no response from doh-client");
+}
+
+#[tokio::test]
+async fn servfail_without_upstream() {
+    let certificate = Certificate::new("error-servfail");
+    let listen_addr = start_doh_client_without_server(&certificate);
+
+    query_fails(listen_addr, 1).await;
+}
+
+#[tokio::test]
+async fn formerr_on_malformed_query() {
+    let certificate = Certificate::new("error-formerr");
+    let listen_addr = start_doh_client_without_server(&certificate);
+
+    // The header announces a question, which is missing.
+    let msg = [
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    let response = send(listen_addr, &msg).await;
+    assert_eq!(response.id, 0x1234);
+    assert!(response.is_response());
+    assert_eq!(response.flags.rcode, RCode::FormErr);
+}
+
+#[tokio::test]
+async fn notimp_on_other_opcode() {
+    let certificate = Certificate::new("error-notimp");
+    let listen_addr = start_doh_client_without_server(&certificate);
+
+    let mut dns_request = create_query(2);
+    dns_request.flags.opcode = Opcode::Status;
+    let response = send(listen_addr, &dns_request.encode().unwrap()).await;
+    assert_eq!(response.id, 2);
+    assert_eq!(response.flags.opcode, Opcode::Status);
+    assert_eq!(response.flags.rcode, RCode::NotImp);
+}
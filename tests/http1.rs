@@ -0,0 +1,143 @@
+mod common;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use common::{create_answer, create_upstream, query, start_doh_client, Certificate, DOMAIN};
+use dns_message_parser::Dns;
+use doh_client::Transport;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::time::sleep;
+use tokio_rustls::TlsAcceptor;
+
+/// Handle the requests of a keep-alive HTTP/1.1 connection and answer each one after `delay`.
+async fn handle_connection<T: AsyncRead + AsyncWrite + Unpin>(connection: T, delay: Duration) {
+    let mut reader = BufReader::new(connection);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.unwrap() == 0 {
+            return;
+        }
+        let mut request_line = request_line.split_whitespace();
+        let method = request_line.next().unwrap().to_owned();
+        let target = request_line.next().unwrap().to_owned();
+        assert!(target.starts_with("/dns-query"));
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            if header == "\r\n" {
+                break;
+            }
+            let (name, value) = header.trim_end().split_once(':').unwrap();
+            match name.to_lowercase().as_str() {
+                "host" => assert_eq!(value.trim(), DOMAIN),
+                "content-length" => content_length = value.trim().parse().unwrap(),
+                _ => {}
+            }
+        }
+
+        let body = if method == "POST" {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await.unwrap();
+            Bytes::from(body)
+        } else {
+            let (_, dns) = target.split_once("?dns=").unwrap();
+            Bytes::from(URL_SAFE_NO_PAD.decode(dns).unwrap())
+        };
+
+        let dns_request = Dns::decode(body).unwrap();
+        let dns_response = create_answer(dns_request).encode().unwrap();
+        let header = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\n\r\n",
+            dns_response.len()
+        );
+        sleep(delay).await;
+        let connection = reader.get_mut();
+        connection.write_all(header.as_bytes()).await.unwrap();
+        connection.write_all(&dns_response).await.unwrap();
+        connection.flush().await.unwrap();
+    }
+}
+
+/// Start a local DoH server, which only supports HTTP/1.1, and return its address and a counter of
+/// the accepted connections.
+async fn start_server(
+    certificate: &Certificate,
+    delay: Duration,
+) -> (SocketAddr, Arc<AtomicUsize>) {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                handle_connection(tls_stream, delay).await;
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+async fn query_over_http1(post: bool) {
+    let certificate = Certificate::new(if post { "http1-post" } else { "http1-get" });
+    let (server_addr, connections) = start_server(&certificate, Duration::ZERO).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2);
+    let listen_addr = start_doh_client(upstream, post);
+
+    for id in 1..=3 {
+        query(listen_addr, id).await;
+    }
+    // The connection is kept alive and reused for all queries.
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn query_over_http1_post() {
+    query_over_http1(true).await;
+}
+
+#[tokio::test]
+async fn query_over_http1_get() {
+    query_over_http1(false).await;
+}
+
+#[tokio::test]
+async fn pool_up_to_max_connections() {
+    let certificate = Certificate::new("http1-pool");
+    let (server_addr, connections) = start_server(&certificate, Duration::from_millis(100)).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2)
+        .with_max_connections(NonZeroUsize::new(2).unwrap());
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 1).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    // Another connection is opened, because the first one is busy, the other queries wait for an
+    // idle connection.
+    tokio::join!(
+        query(listen_addr, 2),
+        query(listen_addr, 3),
+        query(listen_addr, 4),
+        query(listen_addr, 5)
+    );
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::{create_answer, create_upstream, query, start_doh_client, Certificate};
+use dns_message_parser::Dns;
+use doh_client::Transport;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{Request, Response};
+use std::future::pending;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::{sleep, timeout};
+use tokio_rustls::TlsAcceptor;
+
+/// The time after that the server answers a query.
+const RESPONSE_DELAY: Duration = Duration::from_millis(300);
+
+/// The interval of the PINGs and the idle timeout of the tests.
+const KEEPALIVE: Duration = Duration::from_secs(1);
+
+/// The counters of the HTTP/2 connections of the server.
+#[derive(Default)]
+struct Connections {
+    opened: AtomicUsize,
+    closed: AtomicUsize,
+}
+
+/// Answer the DoH request after `RESPONSE_DELAY`.
+async fn handle_request(request: Request<RecvStream>, mut respond: SendResponse<Bytes>) {
+    let mut recv_stream = request.into_body();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = recv_stream.data().await {
+        body.extend_from_slice(&chunk.unwrap());
+    }
+    let dns_request = Dns::decode(body.freeze()).unwrap();
+    let dns_response = create_answer(dns_request).encode().unwrap().freeze();
+    sleep(RESPONSE_DELAY).await;
+
+    let response = Response::builder()
+        .status(200)
+        .header("content-type", "application/dns-message")
+        .body(())
+        .unwrap();
+    if let Ok(mut send_stream) = respond.send_response(response, false) {
+        let _ = send_stream.send_data(dns_response, true);
+    }
+}
+
+/// Start a local DoH server, which allows only one concurrent stream per connection, and return
+/// its address and the counters of the HTTP/2 connections. If `freeze` is `true`, then the server
+/// stops to handle a connection after the first request, so the PINGs are not answered anymore.
+async fn start_server(certificate: &Certificate, freeze: bool) -> (SocketAddr, Arc<Connections>) {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(Connections::default());
+
+    let counters = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let counters = counters.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                let mut connection = h2::server::Builder::new()
+                    .max_concurrent_streams(1)
+                    .handshake(tls_stream)
+                    .await
+                    .unwrap();
+                counters.opened.fetch_add(1, Ordering::SeqCst);
+                while let Some(Ok((request, respond))) = connection.accept().await {
+                    tokio::spawn(handle_request(request, respond));
+                    if freeze {
+                        // Send the response, but do not read the connection afterwards.
+                        let _ = timeout(2 * RESPONSE_DELAY, connection.accept()).await;
+                        pending::<()>().await;
+                    }
+                }
+                counters.closed.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+#[tokio::test]
+async fn pool_up_to_max_connections() {
+    let certificate = Certificate::new("http2-pool");
+    let (server_addr, connections) = start_server(&certificate, false).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2)
+        .with_max_connections(NonZeroUsize::new(2).unwrap());
+    let listen_addr = start_doh_client(upstream, true);
+
+    // The first query receives the settings of the server.
+    query(listen_addr, 1).await;
+    assert_eq!(connections.opened.load(Ordering::SeqCst), 1);
+
+    // Another connection is opened for the second query, the third one waits for a stream.
+    tokio::join!(
+        query(listen_addr, 2),
+        query(listen_addr, 3),
+        query(listen_addr, 4)
+    );
+    assert_eq!(connections.opened.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn reconnect_if_ping_fails() {
+    let certificate = Certificate::new("http2-ping");
+    let (server_addr, connections) = start_server(&certificate, true).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2)
+        .with_keepalive(Some(KEEPALIVE))
+        .with_idle_timeout(None);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 1).await;
+    assert_eq!(connections.opened.load(Ordering::SeqCst), 1);
+
+    // The PING is not answered, so the remote host is connected again without a query.
+    sleep(5 * KEEPALIVE).await;
+    assert_eq!(connections.opened.load(Ordering::SeqCst), 2);
+    query(listen_addr, 2).await;
+    assert_eq!(connections.opened.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn close_idle_connection() {
+    let certificate = Certificate::new("http2-idle");
+    let (server_addr, connections) = start_server(&certificate, false).await;
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2)
+        .with_keepalive(None)
+        .with_idle_timeout(Some(KEEPALIVE));
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 1).await;
+    sleep(3 * KEEPALIVE).await;
+    assert_eq!(connections.closed.load(Ordering::SeqCst), 1);
+
+    // The next query connects again.
+    query(listen_addr, 2).await;
+    assert_eq!(connections.opened.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "http3")]
+
+mod common;
+
+use bytes::{Buf, Bytes, BytesMut};
+use common::{create_answer, create_upstream, query, start_doh_client, Certificate};
+use dns_message_parser::Dns;
+use doh_client::Transport;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Endpoint, ServerConfig as QuinnServerConfig};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+async fn handle_request<S: h3::quic::BidiStream<Bytes>>(
+    mut stream: h3::server::RequestStream<S, Bytes>,
+) {
+    let mut body = BytesMut::new();
+    while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+    let dns_request = Dns::decode(body.freeze()).unwrap();
+    let dns_response = create_answer(dns_request);
+    let response = http::Response::builder()
+        .status(200)
+        .header("content-type", "application/dns-message")
+        .body(())
+        .unwrap();
+    stream.send_response(response).await.unwrap();
+    stream
+        .send_data(dns_response.encode().unwrap().freeze())
+        .await
+        .unwrap();
+    stream.finish().await.unwrap();
+}
+
+/// Create the QUIC endpoint of a local DoH over HTTP/3 server.
+fn create_endpoint(certificate: &Certificate) -> Endpoint {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"h3".to_vec()];
+    let quic_server_config = QuicServerConfig::try_from(server_config).unwrap();
+    let server_config = QuinnServerConfig::with_crypto(Arc::new(quic_server_config));
+    Endpoint::server(server_config, (Ipv4Addr::LOCALHOST, 0).into()).unwrap()
+}
+
+/// Start a local DoH over HTTP/3 server and return its address.
+fn start_server(certificate: &Certificate) -> SocketAddr {
+    let endpoint = create_endpoint(certificate);
+    let addr = endpoint.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            tokio::spawn(async move {
+                let connection = h3_quinn::Connection::new(incoming.await.unwrap());
+                let mut connection = h3::server::Connection::<_, Bytes>::new(connection)
+                    .await
+                    .unwrap();
+                while let Ok(Some(resolver)) = connection.accept().await {
+                    tokio::spawn(async move {
+                        let (_, stream) = resolver.resolve_request().await.unwrap();
+                        handle_request(stream).await;
+                    });
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+/// Start a local DoH over HTTP/3 server, which closes each connection after the first response,
+/// and return its address and the number of accepted connections.
+fn start_closing_server(certificate: &Certificate) -> (SocketAddr, Arc<AtomicUsize>) {
+    let endpoint = create_endpoint(certificate);
+    let addr = endpoint.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let quic_connection = incoming.await.unwrap();
+                let connection = h3_quinn::Connection::new(quic_connection.clone());
+                let mut connection = h3::server::Connection::<_, Bytes>::new(connection)
+                    .await
+                    .unwrap();
+                if let Ok(Some(resolver)) = connection.accept().await {
+                    let (_, stream) = resolver.resolve_request().await.unwrap();
+                    handle_request(stream).await;
+                    sleep(Duration::from_millis(100)).await;
+                }
+                quic_connection.close(0u32.into(), b"");
+            });
+        }
+    });
+
+    (addr, connections)
+}
+
+#[tokio::test]
+async fn query_over_http3() {
+    let certificate = Certificate::new("http3");
+    let server_addr = start_server(&certificate);
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http3);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 0x1234).await;
+}
+
+#[tokio::test]
+async fn reconnect_closed_http3_connection() {
+    let certificate = Certificate::new("http3-close");
+    let (server_addr, connections) = start_closing_server(&certificate);
+
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http3);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 0x1234).await;
+    // Wait until the server closed the connection.
+    sleep(Duration::from_millis(500)).await;
+    query(listen_addr, 0x5678).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{
+    assert_answer, connect_tcp, create_answer, create_query, create_upstream, query, read_message,
+    write_message, Certificate,
+};
+use doh_client::{run, Config, ListenConfig, Transport};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// Start a local DNS over TLS server and return its address, a counter of the established TLS
+/// connections and a counter of the received queries.
+async fn start_server(
+    certificate: &Certificate,
+) -> (SocketAddr, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let acceptor = TlsAcceptor::from(Arc::new(certificate.server_config()));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let queries = Arc::new(AtomicUsize::new(0));
+
+    let (connection_counter, query_counter) = (connections.clone(), queries.clone());
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let connection_counter = connection_counter.clone();
+            let query_counter = query_counter.clone();
+            tokio::spawn(async move {
+                if let Ok(mut tls_stream) = acceptor.accept(tcp_stream).await {
+                    connection_counter.fetch_add(1, Ordering::SeqCst);
+                    while let Some(dns_request) = read_message(&mut tls_stream).await {
+                        query_counter.fetch_add(1, Ordering::SeqCst);
+                        let dns_response = create_answer(dns_request).encode().unwrap();
+                        write_message(&mut tls_stream, &dns_response).await;
+                    }
+                }
+            });
+        }
+    });
+
+    (addr, connections, queries)
+}
+
+/// Get an address on the loopback interface with a free port.
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn query_over_two_listen_addrs() {
+    let certificate = Certificate::new("listen-addrs");
+    let (server_addr, connections, queries) = start_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addrs = vec![free_addr(), free_addr()];
+    let config = Config::new(ListenConfig::Addrs(listen_addrs.clone()), vec![upstream])
+        .unwrap()
+        .with_listen_tcp(true);
+    tokio::spawn(run(config));
+
+    query(listen_addrs[0], 1).await;
+    query(listen_addrs[1], 2).await;
+    let mut connection = connect_tcp(listen_addrs[1]).await;
+    write_message(&mut connection, &create_query(3).encode().unwrap()).await;
+    let response = read_message(&mut connection).await.unwrap();
+    assert_answer(&response, 3);
+
+    // The listeners share the session to the upstream and the cache.
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
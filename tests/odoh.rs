@@ -0,0 +1,240 @@
+#![cfg(feature = "odoh")]
+
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::{create_answer, query, query_fails, start_doh_client, Certificate, DOMAIN};
+use dns_message_parser::Dns;
+use doh_client::{RemoteHost, Transport, UpstreamConfig};
+use getrandom::rand_core::UnwrapErr;
+use getrandom::SysRng;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{Request, Response};
+use odoh_rs::{
+    compose, decrypt_query, encrypt_response, parse, ObliviousDoHConfig, ObliviousDoHConfigs,
+    ObliviousDoHKeyPair, ObliviousDoHMessage, ObliviousDoHMessagePlaintext, ODOH_HTTP_HEADER,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// The address of the ODoH relay and target in the tests.
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+async fn get_body(mut recv_stream: RecvStream) -> Bytes {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = recv_stream.data().await {
+        let chunk = chunk.unwrap();
+        recv_stream
+            .flow_control()
+            .release_capacity(chunk.len())
+            .unwrap();
+        body.extend_from_slice(&chunk);
+    }
+    body.freeze()
+}
+
+fn send_response(mut respond: SendResponse<Bytes>, content_type: &str, body: Bytes) {
+    let response = Response::builder()
+        .status(200)
+        .header("content-type", content_type)
+        .body(())
+        .unwrap();
+    let mut send_stream = respond.send_response(response, false).unwrap();
+    send_stream.send_data(body, true).unwrap();
+}
+
+/// The ODoH configs of the key pair.
+fn create_configs(key_pair: &ObliviousDoHKeyPair) -> Bytes {
+    let config = ObliviousDoHConfig::from(key_pair.public().clone());
+    let configs = ObliviousDoHConfigs::from(vec![config]);
+    compose(&configs).unwrap().freeze()
+}
+
+/// The ODoH relay and target.
+struct Server {
+    key_pair: ObliviousDoHKeyPair,
+    target: String,
+    config_requests: AtomicUsize,
+    /// The status of the response to the first query, which is not answered, if it is not 200.
+    first_status: u16,
+    answered: AtomicBool,
+}
+
+/// Handle a request to the relay or the target. The server is both, so it decrypts the query
+/// directly instead of forwarding it.
+async fn handle_request(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    server: Arc<Server>,
+) {
+    let (parts, body) = request.into_parts();
+    let key_pair = &server.key_pair;
+    let target = &server.target;
+    if parts.uri.path() == "/.well-known/odohconfigs" {
+        assert_eq!(parts.uri.authority().unwrap().as_str(), target);
+        server.config_requests.fetch_add(1, Ordering::SeqCst);
+        send_response(
+            respond,
+            "application/octet-stream",
+            create_configs(key_pair),
+        );
+        return;
+    }
+    if !server.answered.swap(true, Ordering::SeqCst) && server.first_status != 200 {
+        let response = Response::builder()
+            .status(server.first_status)
+            .body(())
+            .unwrap();
+        respond.send_response(response, true).unwrap();
+        return;
+    }
+
+    assert_eq!(parts.method, "POST");
+    assert_eq!(parts.uri.path(), "/proxy");
+    let expected_query = format!("targethost={}&targetpath=/dns-query", target);
+    assert_eq!(parts.uri.query(), Some(expected_query.as_str()));
+    assert_eq!(parts.headers["content-type"], ODOH_HTTP_HEADER);
+
+    let mut body = get_body(body).await;
+    let query_message: ObliviousDoHMessage = parse(&mut body).unwrap();
+    let (query, secret) = decrypt_query(&query_message, key_pair).unwrap();
+    let dns_request = Dns::decode(query.clone().into_msg()).unwrap();
+    let dns_response = create_answer(dns_request).encode().unwrap();
+
+    let response = ObliviousDoHMessagePlaintext::new(dns_response, 0);
+    let mut nonce = [0; 16];
+    getrandom::fill(&mut nonce).unwrap();
+    let response_message = encrypt_response(&query, &response, secret, nonce).unwrap();
+    let response_message = compose(&response_message).unwrap().freeze();
+    send_response(respond, ODOH_HTTP_HEADER, response_message);
+}
+
+/// Start a local ODoH relay and target on `ip_addr`, which answers the first query with
+/// `first_status`, and return its address and the state of it.
+async fn start_server(
+    certificate: &Certificate,
+    ip_addr: IpAddr,
+    first_status: u16,
+) -> (SocketAddr, Arc<Server>) {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((ip_addr, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Arc::new(Server {
+        key_pair: ObliviousDoHKeyPair::new(&mut UnwrapErr(SysRng)),
+        target: addr.to_string(),
+        config_requests: AtomicUsize::new(0),
+        first_status,
+        answered: AtomicBool::new(false),
+    });
+
+    let state = server.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let server = state.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                let mut connection = h2::server::handshake(tls_stream).await.unwrap();
+                while let Some(Ok((request, respond))) = connection.accept().await {
+                    tokio::spawn(handle_request(request, respond, server.clone()));
+                }
+            });
+        }
+    });
+
+    (addr, server)
+}
+
+fn create_upstream(certificate: &Certificate, server_addr: SocketAddr) -> UpstreamConfig {
+    let remote_host = RemoteHost::Direct(server_addr.ip().to_string(), server_addr.port());
+    let cafile = certificate.cafile();
+    let target = format!("{}/dns-query", server_addr);
+    UpstreamConfig::new(remote_host, DOMAIN, Some(&cafile), "proxy")
+        .unwrap()
+        .with_odoh_target(&target, Some(&cafile))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn query_over_odoh() {
+    let certificate = Certificate::new("odoh");
+    let (server_addr, server) = start_server(&certificate, LOCALHOST, 200).await;
+    let listen_addr = start_doh_client(create_upstream(&certificate, server_addr), false);
+
+    for id in 1..=3 {
+        query(listen_addr, id).await;
+    }
+    // The ODoH config is only fetched once.
+    assert_eq!(server.config_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn query_ipv6_target() {
+    let certificate = Certificate::new("odoh-ipv6");
+    let ip_addr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+    let (server_addr, server) = start_server(&certificate, ip_addr, 200).await;
+    // The target is `[::1]:port/dns-query`.
+    let listen_addr = start_doh_client(create_upstream(&certificate, server_addr), false);
+
+    query(listen_addr, 1).await;
+    assert_eq!(server.config_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn fetch_config_again_if_rejected() {
+    let certificate = Certificate::new("odoh-rejected");
+    let (server_addr, server) = start_server(&certificate, LOCALHOST, 401).await;
+    let listen_addr = start_doh_client(create_upstream(&certificate, server_addr), false);
+
+    query_fails(listen_addr, 1).await;
+    query(listen_addr, 2).await;
+    // The target could have rotated its key, so the ODoH config is fetched again.
+    assert_eq!(server.config_requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn keep_config_on_server_error() {
+    let certificate = Certificate::new("odoh-server-error");
+    let (server_addr, server) = start_server(&certificate, LOCALHOST, 500).await;
+    let listen_addr = start_doh_client(create_upstream(&certificate, server_addr), false);
+
+    query_fails(listen_addr, 1).await;
+    query(listen_addr, 2).await;
+    assert_eq!(server.config_requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn query_with_given_config() {
+    let certificate = Certificate::new("odoh-given-config");
+    let (server_addr, server) = start_server(&certificate, LOCALHOST, 200).await;
+    let upstream = create_upstream(&certificate, server_addr)
+        .with_odoh_config(&create_configs(&server.key_pair))
+        .unwrap();
+    let listen_addr = start_doh_client(upstream, false);
+
+    query(listen_addr, 1).await;
+    // The target never sees the address of the client.
+    assert_eq!(server.config_requests.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn odoh_over_dot() {
+    let certificate = Certificate::new("odoh-dot");
+    let cafile = certificate.cafile();
+    let remote_host = RemoteHost::Direct(Ipv4Addr::LOCALHOST.to_string(), 853);
+    let upstream = UpstreamConfig::new(remote_host, DOMAIN, Some(&cafile), "")
+        .unwrap()
+        .with_transport(Transport::Dot)
+        .unwrap();
+    // ODoH needs HTTP to send the queries through the relay.
+    assert!(upstream
+        .with_odoh_target("odoh.test/dns-query", Some(&cafile))
+        .is_err());
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{
+    create_upstream, query, query_fails, start_doh_client, start_dot_server, Certificate,
+};
+use doh_client::{Transport, UpstreamConfig};
+
+/// Pin the public keys of the certificates.
+fn pin(
+    upstream: UpstreamConfig,
+    ca: &Certificate,
+    pinned: &[&Certificate],
+    pin_only: bool,
+) -> UpstreamConfig {
+    let cafile = ca.cafile();
+    let spki_hashes = pinned
+        .iter()
+        .map(|certificate| certificate.spki_hash())
+        .collect();
+    upstream
+        .with_spki_pins(Some(&cafile), spki_hashes, pin_only)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn pinned_public_key() {
+    let certificate = Certificate::new("pin");
+    let next = Certificate::new("pin-next");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    // The key of the next certificate is pinned too, so the certificate can be rotated.
+    let upstream = pin(upstream, &certificate, &[&next, &certificate], false);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 1).await;
+}
+
+#[tokio::test]
+async fn public_key_not_pinned() {
+    let certificate = Certificate::new("pin-wrong");
+    let other = Certificate::new("pin-wrong-other");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let upstream = pin(upstream, &certificate, &[&other], false);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query_fails(listen_addr, 1).await;
+}
+
+#[tokio::test]
+async fn pin_only() {
+    let certificate = Certificate::new("pin-only");
+    let other = Certificate::new("pin-only-other");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+
+    // The certificate of the server is not trusted by the CA certificates.
+    let upstream = create_upstream(&other, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+    query_fails(listen_addr, 1).await;
+
+    // But its public key is pinned.
+    let upstream = create_upstream(&other, server_addr, Transport::Dot);
+    let upstream = pin(upstream, &other, &[&certificate], true);
+    let listen_addr = start_doh_client(upstream, true);
+    query(listen_addr, 2).await;
+
+    let upstream = create_upstream(&other, server_addr, Transport::Dot);
+    let upstream = pin(upstream, &other, &[&other], true);
+    let listen_addr = start_doh_client(upstream, true);
+    query_fails(listen_addr, 3).await;
+}
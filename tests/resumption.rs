@@ -0,0 +1,296 @@
+mod common;
+
+use bytes::Bytes;
+use bytes::BytesMut;
+use common::{create_answer, create_upstream, query, query_fails, start_doh_client, Certificate};
+use dns_message_parser::Dns;
+use doh_client::Transport;
+use http::Response;
+use rustls::HandshakeKind;
+use std::future::pending;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// The counters of the TLS handshakes of the server.
+#[derive(Default)]
+struct Handshakes {
+    full: AtomicUsize,
+    resumed: AtomicUsize,
+    early_data: AtomicUsize,
+}
+
+/// The protocol of the server.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Dot,
+    Http1,
+    Http2,
+}
+
+/// Read the early data of the connection and count it, if it was received.
+fn read_early_data(connection: &mut TlsStream<TcpStream>, counters: &Handshakes) -> Vec<u8> {
+    let mut early_data = Vec::new();
+    if let Some(mut reader) = connection.get_mut().1.early_data() {
+        reader.read_to_end(&mut early_data).unwrap();
+    }
+    if !early_data.is_empty() {
+        counters.early_data.fetch_add(1, Ordering::SeqCst);
+    }
+    early_data
+}
+
+/// A TLS connection, which returns the early data before the data of the handshake is finished.
+struct EarlyDataStream {
+    early_data: Vec<u8>,
+    position: usize,
+    connection: TlsStream<TcpStream>,
+}
+
+impl AsyncRead for EarlyDataStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if this.position < this.early_data.len() {
+            let len = buf.remaining().min(this.early_data.len() - this.position);
+            buf.put_slice(&this.early_data[this.position..this.position + len]);
+            this.position += len;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.connection).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for EarlyDataStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.connection).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.connection).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.connection).poll_shutdown(cx)
+    }
+}
+
+/// Answer the requests of an HTTP/2 connection. On the first connection only the first request is
+/// answered, so the next query times out and the client drops the connection.
+async fn handle_http2_connection(
+    mut connection: TlsStream<TcpStream>,
+    counters: Arc<Handshakes>,
+    first: bool,
+) {
+    let early_data = read_early_data(&mut connection, &counters);
+    let connection = EarlyDataStream {
+        early_data,
+        position: 0,
+        connection,
+    };
+    let mut connection = h2::server::handshake(connection).await.unwrap();
+    let mut requests = 0;
+    while let Some(Ok((request, mut respond))) = connection.accept().await {
+        requests += 1;
+        if first && requests > 1 {
+            tokio::spawn(async move {
+                let _respond = respond;
+                pending::<()>().await;
+            });
+            continue;
+        }
+        tokio::spawn(async move {
+            let mut recv_stream = request.into_body();
+            let mut body = BytesMut::new();
+            while let Some(chunk) = recv_stream.data().await {
+                body.extend_from_slice(&chunk.unwrap());
+            }
+            let dns_request = Dns::decode(body.freeze()).unwrap();
+            let dns_response = create_answer(dns_request).encode().unwrap().freeze();
+            let response = Response::builder()
+                .status(200)
+                .header("content-type", "application/dns-message")
+                .body(())
+                .unwrap();
+            let mut send_stream = respond.send_response(response, false).unwrap();
+            send_stream.send_data(dns_response, true).unwrap();
+        });
+    }
+}
+
+/// Answer one query of a DNS over TLS connection and close it, so the client has to reconnect.
+/// The query is received as early data, after the handshake or partly both.
+async fn handle_connection(mut connection: TlsStream<TcpStream>, counters: Arc<Handshakes>) {
+    let early_data = read_early_data(&mut connection, &counters);
+    let mut reader = AsyncReadExt::chain(early_data.as_slice(), &mut connection);
+    let len = reader.read_u16().await.unwrap();
+    let mut msg = vec![0; len as usize];
+    reader.read_exact(&mut msg).await.unwrap();
+    let dns_request = Dns::decode(Bytes::from(msg)).unwrap();
+    let dns_response = create_answer(dns_request).encode().unwrap();
+    connection
+        .write_u16(dns_response.len() as u16)
+        .await
+        .unwrap();
+    connection.write_all(&dns_response).await.unwrap();
+    connection.shutdown().await.unwrap();
+}
+
+/// Answer one POST request of an HTTP/1.1 connection and close it, so the client has to reconnect.
+async fn handle_http1_connection(mut connection: TlsStream<TcpStream>, counters: Arc<Handshakes>) {
+    let early_data = read_early_data(&mut connection, &counters);
+    let mut reader = BufReader::new(AsyncReadExt::chain(early_data.as_slice(), &mut connection));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await.unwrap();
+    assert!(request_line.starts_with("POST "), "{}", request_line);
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.unwrap();
+        if header == "\r\n" {
+            break;
+        }
+        let (name, value) = header.trim_end().split_once(':').unwrap();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().unwrap();
+        }
+    }
+    let mut msg = vec![0; content_length];
+    reader.read_exact(&mut msg).await.unwrap();
+    let dns_request = Dns::decode(Bytes::from(msg)).unwrap();
+    let dns_response = create_answer(dns_request).encode().unwrap();
+    let header = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\n\
+        connection: close\r\n\r\n",
+        dns_response.len()
+    );
+    connection.write_all(header.as_bytes()).await.unwrap();
+    connection.write_all(&dns_response).await.unwrap();
+    connection.shutdown().await.unwrap();
+}
+
+/// Start a local DNS over TLS, HTTP/1.1 or HTTP/2 server, which accepts early data of resumed
+/// sessions, and return its address and the counters of its handshakes.
+async fn start_server(
+    certificate: &Certificate,
+    protocol: Protocol,
+) -> (SocketAddr, Arc<Handshakes>) {
+    let mut server_config = certificate.server_config();
+    server_config.max_early_data_size = 1024;
+    match protocol {
+        Protocol::Dot => {}
+        Protocol::Http1 => server_config.alpn_protocols = vec![b"http/1.1".to_vec()],
+        Protocol::Http2 => server_config.alpn_protocols = vec![b"h2".to_vec()],
+    }
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handshakes = Arc::new(Handshakes::default());
+
+    let counters = handshakes.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let counters = counters.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                let (_, server_connection) = tls_stream.get_ref();
+                let resumed = server_connection.handshake_kind() == Some(HandshakeKind::Resumed);
+                let first = if resumed {
+                    counters.resumed.fetch_add(1, Ordering::SeqCst);
+                    false
+                } else {
+                    counters.full.fetch_add(1, Ordering::SeqCst) == 0
+                };
+                match protocol {
+                    Protocol::Dot => handle_connection(tls_stream, counters).await,
+                    Protocol::Http1 => handle_http1_connection(tls_stream, counters).await,
+                    Protocol::Http2 => handle_http2_connection(tls_stream, counters, first).await,
+                }
+            });
+        }
+    });
+
+    (addr, handshakes)
+}
+
+#[tokio::test]
+async fn resume_tls_session() {
+    let certificate = Certificate::new("resumption");
+    let (server_addr, handshakes) = start_server(&certificate, Protocol::Dot).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client(upstream, true);
+
+    for id in 1..=3 {
+        query(listen_addr, id).await;
+    }
+    assert_eq!(handshakes.full.load(Ordering::SeqCst), 1);
+    assert_eq!(handshakes.resumed.load(Ordering::SeqCst), 2);
+    assert_eq!(handshakes.early_data.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn send_early_data() {
+    let certificate = Certificate::new("resumption-early-data");
+    let (server_addr, handshakes) = start_server(&certificate, Protocol::Dot).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot).with_early_data();
+    let listen_addr = start_doh_client(upstream, true);
+
+    for id in 1..=3 {
+        query(listen_addr, id).await;
+    }
+    assert_eq!(handshakes.full.load(Ordering::SeqCst), 1);
+    assert_eq!(handshakes.resumed.load(Ordering::SeqCst), 2);
+    // The queries of the resumed sessions are sent as early data.
+    assert_eq!(handshakes.early_data.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn no_early_data_without_http2() {
+    let certificate = Certificate::new("resumption-early-data-http1");
+    let (server_addr, handshakes) = start_server(&certificate, Protocol::Http1).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2).with_early_data();
+    let listen_addr = start_doh_client(upstream, true);
+
+    for id in 1..=3 {
+        query(listen_addr, id).await;
+    }
+    assert_eq!(handshakes.full.load(Ordering::SeqCst), 1);
+    assert_eq!(handshakes.resumed.load(Ordering::SeqCst), 2);
+    // The server does not support HTTP/2, so the handshake is finished before the request is sent.
+    assert_eq!(handshakes.early_data.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn early_data_after_disconnect() {
+    let certificate = Certificate::new("resumption-early-data-disconnect");
+    let (server_addr, handshakes) = start_server(&certificate, Protocol::Http2).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2).with_early_data();
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 1).await;
+    // The query times out, so the client drops the HTTP/2 connection.
+    query_fails(listen_addr, 2).await;
+    query(listen_addr, 3).await;
+    assert_eq!(handshakes.full.load(Ordering::SeqCst), 1);
+    assert_eq!(handshakes.resumed.load(Ordering::SeqCst), 1);
+    // The server still speaks HTTP/2, so the resumed session sends early data.
+    assert_eq!(handshakes.early_data.load(Ordering::SeqCst), 1);
+}
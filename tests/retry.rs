@@ -0,0 +1,177 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::{
+    create_answer, create_upstream, query, query_fails, start_doh_client,
+    start_doh_client_with_upstreams, Certificate,
+};
+use dns_message_parser::Dns;
+use doh_client::Transport;
+use h2::server::SendResponse;
+use h2::{Reason, RecvStream};
+use http::{Request, Response};
+use std::future::pending;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// How the server handles the first request.
+#[derive(Clone, Copy)]
+enum FirstRequest {
+    /// Reset the stream with `REFUSED_STREAM`.
+    Refuse,
+    /// Answer with the status 500.
+    ServerError,
+    /// Do not answer this and all other requests, so they time out.
+    Ignore,
+    /// Answer it like the other requests.
+    Answer,
+}
+
+/// The counters of the server.
+#[derive(Default)]
+struct Counters {
+    connections: AtomicUsize,
+    requests: AtomicUsize,
+}
+
+/// Handle the first request like `first_request` and answer the others.
+async fn handle_request(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    first_request: FirstRequest,
+    counters: Arc<Counters>,
+) {
+    let mut recv_stream = request.into_body();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = recv_stream.data().await {
+        body.extend_from_slice(&chunk.unwrap());
+    }
+
+    let first = counters.requests.fetch_add(1, Ordering::SeqCst) == 0;
+    match first_request {
+        FirstRequest::Refuse if first => {
+            respond.send_reset(Reason::REFUSED_STREAM);
+            return;
+        }
+        FirstRequest::ServerError if first => {
+            let response = Response::builder().status(500).body(()).unwrap();
+            respond.send_response(response, true).unwrap();
+            return;
+        }
+        FirstRequest::Ignore => {
+            // Keep the stream open without a response.
+            let _respond = respond;
+            pending::<()>().await;
+            return;
+        }
+        _ => {}
+    }
+
+    let dns_request = Dns::decode(body.freeze()).unwrap();
+    let dns_response = create_answer(dns_request).encode().unwrap().freeze();
+    let response = Response::builder()
+        .status(200)
+        .header("content-type", "application/dns-message")
+        .body(())
+        .unwrap();
+    let mut send_stream = respond.send_response(response, false).unwrap();
+    send_stream.send_data(dns_response, true).unwrap();
+}
+
+/// Start a local DoH server and return its address and its counters.
+async fn start_server(
+    certificate: &Certificate,
+    first_request: FirstRequest,
+) -> (SocketAddr, Arc<Counters>) {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let counters = Arc::new(Counters::default());
+
+    let server_counters = counters.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let counters = server_counters.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                let mut connection = h2::server::handshake(tls_stream).await.unwrap();
+                counters.connections.fetch_add(1, Ordering::SeqCst);
+                while let Some(Ok((request, respond))) = connection.accept().await {
+                    let counters = counters.clone();
+                    tokio::spawn(handle_request(request, respond, first_request, counters));
+                }
+            });
+        }
+    });
+
+    (addr, counters)
+}
+
+#[tokio::test]
+async fn retry_refused_stream() {
+    let certificate = Certificate::new("retry-refused");
+    let (server_addr, counters) = start_server(&certificate, FirstRequest::Refuse).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2);
+    let listen_addr = start_doh_client(upstream, true);
+
+    // The refused query is sent again over a new connection.
+    query(listen_addr, 1).await;
+    assert_eq!(counters.requests.load(Ordering::SeqCst), 2);
+    assert_eq!(counters.connections.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn no_retry_on_server_error() {
+    let certificate = Certificate::new("retry-server-error");
+    let (server_addr, counters) = start_server(&certificate, FirstRequest::ServerError).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Http2);
+    let listen_addr = start_doh_client(upstream, true);
+
+    query_fails(listen_addr, 1).await;
+    assert_eq!(counters.requests.load(Ordering::SeqCst), 1);
+
+    // The connection is still used after the error of the server.
+    query(listen_addr, 2).await;
+    assert_eq!(counters.connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn failover_on_timeout() {
+    let certificate = Certificate::new("retry-failover");
+    let (first_addr, first_counters) = start_server(&certificate, FirstRequest::Ignore).await;
+    let (second_addr, second_counters) = start_server(&certificate, FirstRequest::Answer).await;
+    let upstreams = vec![
+        create_upstream(&certificate, first_addr, Transport::Http2),
+        create_upstream(&certificate, second_addr, Transport::Http2),
+    ];
+    let listen_addr = start_doh_client_with_upstreams(upstreams);
+
+    // The query times out on the first remote host and is answered by the second one.
+    query(listen_addr, 1).await;
+    assert!(first_counters.requests.load(Ordering::SeqCst) >= 1);
+    assert!(second_counters.requests.load(Ordering::SeqCst) >= 1);
+}
+
+#[tokio::test]
+async fn upstreams_share_timeout() {
+    let certificate = Certificate::new("retry-shared-timeout");
+    let mut upstreams = Vec::new();
+    for _ in 0..3 {
+        let (server_addr, _) = start_server(&certificate, FirstRequest::Ignore).await;
+        upstreams.push(create_upstream(&certificate, server_addr, Transport::Http2));
+    }
+    let listen_addr = start_doh_client_with_upstreams(upstreams);
+
+    // Every remote host times out, but the query fails after the timeout of two seconds in total.
+    let start = Instant::now();
+    query_fails(listen_addr, 1).await;
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
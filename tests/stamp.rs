@@ -0,0 +1,74 @@
+mod common;
+
+use aws_lc_rs::digest::{digest, SHA256};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use common::{query, query_fails, start_doh_client, start_dot_server, Certificate, DOMAIN};
+use doh_client::{RemoteHost, UpstreamConfig};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::Ordering;
+
+/// Get the SHA256 hash of the `tbsCertificate`, which is the first element of the certificate
+/// sequence. The test certificates are longer than 255 bytes, so the lengths of both sequences are
+/// encoded with two bytes.
+fn cert_hash(certificate: &Certificate) -> Vec<u8> {
+    let cert = certificate.cert_chain().remove(0);
+    assert_eq!(&cert[..2], &[0x30, 0x82]);
+    assert_eq!(&cert[4..6], &[0x30, 0x82]);
+    let len = u16::from_be_bytes([cert[6], cert[7]]) as usize;
+    digest(&SHA256, &cert[4..8 + len]).as_ref().to_vec()
+}
+
+/// Create the DNS stamp of a DNS over TLS server with the address, the certificate hash and
+/// `DOMAIN` as hostname.
+fn create_dot_stamp(addr: SocketAddr, cert_hash: &[u8]) -> String {
+    let addr = addr.to_string();
+    let mut stamp = vec![0x03];
+    stamp.extend([0; 8]);
+    stamp.push(addr.len() as u8);
+    stamp.extend(addr.as_bytes());
+    stamp.push(cert_hash.len() as u8);
+    stamp.extend(cert_hash);
+    stamp.push(DOMAIN.len() as u8);
+    stamp.extend(DOMAIN.as_bytes());
+    format!("sdns://{}", URL_SAFE_NO_PAD.encode(stamp))
+}
+
+#[tokio::test]
+async fn query_with_pinned_cert() {
+    let certificate = Certificate::new("stamp-pinned");
+    let (server_addr, connections) = start_dot_server(&certificate).await;
+
+    let stamp = create_dot_stamp(server_addr, &cert_hash(&certificate));
+    let remote_host = RemoteHost::Direct(server_addr.ip().to_string(), server_addr.port());
+    let cafile = certificate.cafile();
+    let upstream = UpstreamConfig::from_stamp(remote_host, &stamp, Some(&cafile)).unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+
+    query(listen_addr, 1).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn query_with_wrong_cert_hash() {
+    let certificate = Certificate::new("stamp-wrong-hash");
+    let (server_addr, connections) = start_dot_server(&certificate).await;
+
+    // The certificate is trusted, but it is not pinned by the stamp.
+    let stamp = create_dot_stamp(server_addr, &[0; 32]);
+    let remote_host = RemoteHost::Direct(server_addr.ip().to_string(), server_addr.port());
+    let cafile = certificate.cafile();
+    let upstream = UpstreamConfig::from_stamp(remote_host, &stamp, Some(&cafile)).unwrap();
+    let listen_addr = start_doh_client(upstream, true);
+
+    query_fails(listen_addr, 1).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn invalid_stamp() {
+    let remote_host = RemoteHost::Direct(Ipv4Addr::LOCALHOST.to_string(), 853);
+    let certificate = Certificate::new("stamp-invalid");
+    let cafile = certificate.cafile();
+    let result = UpstreamConfig::from_stamp(remote_host, "sdns://AQ", Some(&cafile));
+    assert!(result.is_err());
+}
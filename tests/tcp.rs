@@ -0,0 +1,278 @@
+mod common;
+
+use common::{
+    assert_answer, connect_tcp, create_answer, create_query, create_upstream, read_message,
+    start_doh_client_with_tcp, start_dot_server, write_message, Certificate,
+};
+use dns_message_parser::{Dns, RCode};
+use doh_client::Transport;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+use tokio_rustls::TlsAcceptor;
+
+/// The delay of the answer to the first query of a connection.
+const FIRST_QUERY_DELAY: Duration = Duration::from_millis(500);
+
+/// Answer the queries of a DNS over TLS connection concurrently. The answer to the first query is
+/// delayed by `FIRST_QUERY_DELAY`, so it is sent after the answers to the following queries.
+async fn handle_connection<T: AsyncRead + AsyncWrite + Send + 'static>(connection: T) {
+    let (mut reader, writer) = tokio::io::split(connection);
+    let writer = Arc::new(Mutex::new(writer));
+    let mut delay = FIRST_QUERY_DELAY;
+    while let Some(dns_request) = read_message(&mut reader).await {
+        let writer = writer.clone();
+        tokio::spawn(async move {
+            sleep(delay).await;
+            let dns_response = create_answer(dns_request).encode().unwrap();
+            write_message(&mut *writer.lock().await, &dns_response).await;
+        });
+        delay = Duration::ZERO;
+    }
+}
+
+/// Start a local DNS over TLS server, which delays the answer to the first query of every
+/// connection, and return its address.
+async fn start_server(certificate: &Certificate) -> SocketAddr {
+    let acceptor = TlsAcceptor::from(Arc::new(certificate.server_config()));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                if let Ok(tls_stream) = acceptor.accept(tcp_stream).await {
+                    handle_connection(tls_stream).await;
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+/// Start a local DNS over TLS server, which never answers, and return its address and a counter of
+/// the received queries.
+async fn start_silent_server(certificate: &Certificate) -> (SocketAddr, Arc<AtomicUsize>) {
+    let acceptor = TlsAcceptor::from(Arc::new(certificate.server_config()));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+
+    let counter = queries.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                if let Ok(mut tls_stream) = acceptor.accept(tcp_stream).await {
+                    while read_message(&mut tls_stream).await.is_some() {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            });
+        }
+    });
+
+    (addr, queries)
+}
+
+/// Read a response of the `doh-client`, which has to be sent within five seconds.
+async fn read_response(connection: &mut TcpStream) -> Dns {
+    timeout(Duration::from_secs(5), read_message(connection))
+        .await
+        .expect("no response from doh-client")
+        .expect("connection is closed by doh-client")
+}
+
+#[tokio::test]
+async fn pipelined_queries() {
+    let certificate = Certificate::new("tcp-pipelined");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    // Send both queries before a response is read.
+    let mut queries = Vec::new();
+    for id in [1, 2] {
+        let query = create_query(id).encode().unwrap();
+        write_message(&mut queries, &query).await;
+    }
+    connection.write_all(&queries).await.unwrap();
+
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let response = read_response(&mut connection).await;
+        assert_answer(&response, response.id);
+        ids.push(response.id);
+    }
+    ids.sort_unstable();
+    assert_eq!(ids, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn out_of_order_responses() {
+    let certificate = Certificate::new("tcp-out-of-order");
+    let server_addr = start_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    write_message(&mut connection, &create_query(1).encode().unwrap()).await;
+    sleep(Duration::from_millis(100)).await;
+    write_message(&mut connection, &create_query(2).encode().unwrap()).await;
+
+    // The second query is answered first, because the upstream delays the answer to the first one.
+    let response = read_response(&mut connection).await;
+    assert_answer(&response, 2);
+    let response = read_response(&mut connection).await;
+    assert_answer(&response, 1);
+}
+
+#[tokio::test]
+async fn close_idle_connection() {
+    let certificate = Certificate::new("tcp-idle");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    write_message(&mut connection, &create_query(1).encode().unwrap()).await;
+    let response = read_response(&mut connection).await;
+    assert_answer(&response, 1);
+
+    // The connection is closed after ten seconds without a query.
+    let start = Instant::now();
+    let closed = timeout(Duration::from_secs(15), read_message(&mut connection))
+        .await
+        .expect("idle connection is not closed by doh-client");
+    assert!(closed.is_none());
+    assert!(start.elapsed() >= Duration::from_secs(9));
+}
+
+#[tokio::test]
+async fn close_connection_with_truncated_message() {
+    let certificate = Certificate::new("tcp-truncated");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    // The length prefix announces more bytes than the client sends before it closes the
+    // connection.
+    let query = create_query(1).encode().unwrap();
+    connection.write_u16(query.len() as u16 + 16).await.unwrap();
+    connection.write_all(&query).await.unwrap();
+    connection.shutdown().await.unwrap();
+
+    let closed = timeout(Duration::from_secs(5), read_message(&mut connection))
+        .await
+        .expect("connection is not closed by doh-client");
+    assert!(closed.is_none());
+}
+
+#[tokio::test]
+async fn formerr_on_message_longer_than_frame() {
+    let certificate = Certificate::new("tcp-frame");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    // The frame ends in the middle of the question of the first query.
+    let query = create_query(1).encode().unwrap();
+    write_message(&mut connection, &query[..20]).await;
+    write_message(&mut connection, &create_query(2).encode().unwrap()).await;
+
+    let mut responses = [
+        read_response(&mut connection).await,
+        read_response(&mut connection).await,
+    ];
+    responses.sort_unstable_by_key(|response| response.id);
+    assert_eq!(responses[0].id, 1);
+    assert!(responses[0].is_response());
+    assert_eq!(responses[0].flags.rcode, RCode::FormErr);
+    // The connection is still used after the malformed query.
+    assert_answer(&responses[1], 2);
+}
+
+#[tokio::test]
+async fn close_connection_with_slow_message() {
+    let certificate = Certificate::new("tcp-slow");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    // The rest of the message is never sent.
+    let query = create_query(1).encode().unwrap();
+    connection.write_u16(query.len() as u16).await.unwrap();
+    connection.write_all(&query[..4]).await.unwrap();
+
+    let start = Instant::now();
+    let closed = timeout(Duration::from_secs(15), read_message(&mut connection))
+        .await
+        .expect("connection is not closed by doh-client");
+    assert!(closed.is_none());
+    assert!(start.elapsed() >= Duration::from_secs(9));
+}
+
+#[tokio::test]
+async fn limit_queries_of_connection() {
+    let certificate = Certificate::new("tcp-queries");
+    let (server_addr, queries) = start_silent_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+    let mut connection = connect_tcp(listen_addr).await;
+
+    for id in 1..=17 {
+        write_message(&mut connection, &create_query(id).encode().unwrap()).await;
+    }
+
+    // Only 16 queries are handled at the same time, the last one is read after the first ones
+    // timed out.
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(queries.load(Ordering::SeqCst), 16);
+    for _ in 1..=16 {
+        let response = read_response(&mut connection).await;
+        assert_eq!(response.flags.rcode, RCode::ServFail);
+    }
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(queries.load(Ordering::SeqCst), 17);
+}
+
+#[tokio::test]
+async fn limit_connections() {
+    let certificate = Certificate::new("tcp-connections");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+
+    let mut connections = Vec::new();
+    for id in 1..=128 {
+        let mut connection = connect_tcp(listen_addr).await;
+        write_message(&mut connection, &create_query(id).encode().unwrap()).await;
+        let response = read_response(&mut connection).await;
+        assert_answer(&response, id);
+        connections.push(connection);
+    }
+
+    // The connection is not accepted, while 128 connections are open.
+    let mut connection = TcpStream::connect(listen_addr).await.unwrap();
+    write_message(&mut connection, &create_query(129).encode().unwrap()).await;
+    let response = timeout(Duration::from_millis(500), read_message(&mut connection)).await;
+    assert!(response.is_err());
+
+    drop(connections.pop());
+    let response = read_response(&mut connection).await;
+    assert_answer(&response, 129);
+}
@@ -0,0 +1,85 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{
+    assert_answer, connect_tcp, create_query, create_upstream, read_message,
+    start_doh_client_with_tcp, start_dot_server, write_message, Certificate,
+};
+use doh_client::Transport;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// Get the limit of open files of the process.
+fn get_nofile_limit() -> libc::rlimit {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) },
+        0
+    );
+    limit
+}
+
+fn set_nofile_limit(limit: &libc::rlimit) {
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, limit) }, 0);
+}
+
+/// Open files until the limit of open files is reached and return them.
+fn exhaust_files() -> Vec<File> {
+    let mut files = Vec::new();
+    loop {
+        match File::open("/dev/null") {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                assert_eq!(e.raw_os_error(), Some(libc::EMFILE));
+                return files;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn accept_after_error() {
+    let certificate = Certificate::new("tcp-accept");
+    let (server_addr, _) = start_dot_server(&certificate).await;
+    let upstream = create_upstream(&certificate, server_addr, Transport::Dot);
+    let listen_addr = start_doh_client_with_tcp(upstream);
+
+    // The connection to the upstream is established before the files are exhausted.
+    let mut connection = connect_tcp(listen_addr).await;
+    write_message(&mut connection, &create_query(1).encode().unwrap()).await;
+    let response = read_message(&mut connection).await.unwrap();
+    assert_answer(&response, 1);
+
+    let limit = get_nofile_limit();
+    let next_fd = File::open("/dev/null").unwrap().as_raw_fd();
+    let lowered = libc::rlimit {
+        rlim_cur: next_fd as libc::rlim_t + 64,
+        rlim_max: limit.rlim_max,
+    };
+    set_nofile_limit(&lowered);
+    let mut files = exhaust_files();
+
+    // The client gets the last free file descriptor, so the accept of the `doh-client` fails.
+    drop(files.pop());
+    let mut connection = TcpStream::connect(listen_addr).await.unwrap();
+    write_message(&mut connection, &create_query(2).encode().unwrap()).await;
+    let response = timeout(Duration::from_millis(500), read_message(&mut connection)).await;
+    assert!(response.is_err());
+
+    drop(files);
+    set_nofile_limit(&limit);
+
+    // The connection is accepted, when a file descriptor is available again.
+    let response = timeout(Duration::from_secs(5), read_message(&mut connection))
+        .await
+        .expect("no response from doh-client")
+        .expect("connection is closed by doh-client");
+    assert_answer(&response, 2);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use common::{create_answer, query, start_doh_client, Certificate, DOMAIN};
+use dns_message_parser::Dns;
+use doh_client::{RemoteHost, UpstreamConfig};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// The request target and the host header of the received requests.
+type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Handle the requests of a keep-alive HTTP/1.1 connection and record them.
+async fn handle_connection<T: AsyncRead + AsyncWrite + Unpin>(connection: T, requests: Requests) {
+    let mut reader = BufReader::new(connection);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).await.unwrap() == 0 {
+            return;
+        }
+        let mut request_line = request_line.split_whitespace();
+        let method = request_line.next().unwrap().to_owned();
+        let target = request_line.next().unwrap().to_owned();
+
+        let mut host = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            if header == "\r\n" {
+                break;
+            }
+            let (name, value) = header.trim_end().split_once(':').unwrap();
+            match name.to_lowercase().as_str() {
+                "host" => host = value.trim().to_owned(),
+                "content-length" => content_length = value.trim().parse().unwrap(),
+                _ => {}
+            }
+        }
+
+        let body = if method == "POST" {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await.unwrap();
+            Bytes::from(body)
+        } else {
+            let (_, dns) = target.split_once("dns=").unwrap();
+            Bytes::from(URL_SAFE_NO_PAD.decode(dns).unwrap())
+        };
+        requests.lock().unwrap().push((target, host));
+
+        let dns_request = Dns::decode(body).unwrap();
+        let dns_response = create_answer(dns_request).encode().unwrap();
+        let header = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\n\r\n",
+            dns_response.len()
+        );
+        let connection = reader.get_mut();
+        connection.write_all(header.as_bytes()).await.unwrap();
+        connection.write_all(&dns_response).await.unwrap();
+        connection.flush().await.unwrap();
+    }
+}
+
+/// Start a local DoH server, which only supports HTTP/1.1, and return its address and the received
+/// requests.
+async fn start_server(certificate: &Certificate) -> (SocketAddr, Requests) {
+    let mut server_config = certificate.server_config();
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Requests::default();
+
+    let server_requests = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let requests = server_requests.clone();
+            tokio::spawn(async move {
+                let tls_stream = acceptor.accept(tcp_stream).await.unwrap();
+                handle_connection(tls_stream, requests).await;
+            });
+        }
+    });
+
+    (addr, requests)
+}
+
+async fn query_uri_template(name: &str, dns_variable: &str, post: bool) -> Vec<(String, String)> {
+    let certificate = Certificate::new(name);
+    let (server_addr, requests) = start_server(&certificate).await;
+
+    let remote_host = RemoteHost::Direct(server_addr.ip().to_string(), server_addr.port());
+    let uri_template = format!(
+        "https://{}:{}/custom/dns-query?ecs=0{}",
+        DOMAIN,
+        server_addr.port(),
+        dns_variable
+    );
+    let cafile = certificate.cafile();
+    let upstream =
+        UpstreamConfig::from_uri_template(remote_host, &uri_template, Some(&cafile)).unwrap();
+    let listen_addr = start_doh_client(upstream, post);
+
+    query(listen_addr, 1).await;
+    let requests = requests.lock().unwrap().clone();
+    // The port of the template is part of the authority.
+    let authority = format!("{}:{}", DOMAIN, server_addr.port());
+    for (_, host) in requests.iter() {
+        assert_eq!(host, &authority);
+    }
+    requests
+}
+
+#[tokio::test]
+async fn query_uri_template_post() {
+    let requests = query_uri_template("uri-template-post", "{&dns}", true).await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].0, "/custom/dns-query?ecs=0");
+}
+
+#[tokio::test]
+async fn query_uri_template_get() {
+    let requests = query_uri_template("uri-template-get", "{&dns}", false).await;
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].0.starts_with("/custom/dns-query?ecs=0&dns="));
+}
+
+#[tokio::test]
+async fn query_uri_template_without_dns_variable() {
+    // The server of the template does not support GET, therefore POST is used.
+    let requests = query_uri_template("uri-template-post-only", "", false).await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].0, "/custom/dns-query?ecs=0");
+}
+
+#[test]
+fn invalid_uri_template() {
+    let certificate = Certificate::new("uri-template-invalid");
+    let remote_host = RemoteHost::Direct(Ipv4Addr::LOCALHOST.to_string(), 443);
+    let cafile = certificate.cafile();
+    let result = UpstreamConfig::from_uri_template(
+        remote_host,
+        "https://[::1/dns-query{?dns}",
+        Some(&cafile),
+    );
+    assert!(result.is_err());
+}